@@ -0,0 +1,2 @@
+-- Bumped on every write; exposed to clients as the `ETag` of a user.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
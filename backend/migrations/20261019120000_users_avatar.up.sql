@@ -0,0 +1,4 @@
+-- `avatar_key` points into the configured storage backend; `avatar_url` is the stable API
+-- path clients use, which redirects to a freshly signed download URL.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_key TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_url TEXT;
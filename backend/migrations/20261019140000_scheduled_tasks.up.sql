@@ -0,0 +1,15 @@
+-- One row per recurring task. `last_tick` is the scheduled time of the latest run, so a tick
+-- another instance already handled is skipped by an instance that takes the task's advisory
+-- lock only after that run finished.
+CREATE TABLE IF NOT EXISTS scheduled_tasks
+(
+    name             TEXT PRIMARY KEY NOT NULL,
+    last_tick        TIMESTAMPTZ      NOT NULL,
+    last_started_at  TIMESTAMPTZ,
+    last_duration_ms BIGINT,
+    -- ok | error
+    last_status      TEXT,
+    -- summary returned by the task, or its error
+    last_result      TEXT,
+    runs             BIGINT           NOT NULL DEFAULT 0
+);
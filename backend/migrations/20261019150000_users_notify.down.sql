@@ -0,0 +1,2 @@
+DROP TRIGGER IF EXISTS users_notify_change ON users;
+DROP FUNCTION IF EXISTS users_notify_change();
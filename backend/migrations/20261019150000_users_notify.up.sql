@@ -0,0 +1,37 @@
+-- Publishes every change to a user on the `user_changes` channel, whichever code path made it.
+-- Notifications are sent on commit, so listeners never see a change that was rolled back.
+-- The payload stays small (the 8000 byte limit); listeners load what they need by id.
+CREATE OR REPLACE FUNCTION users_notify_change() RETURNS trigger AS
+$$
+DECLARE
+    row RECORD;
+    op  TEXT;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        row := OLD;
+        op := 'purged';
+    ELSIF TG_OP = 'INSERT' THEN
+        row := NEW;
+        op := 'created';
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        row := NEW;
+        op := 'deleted';
+    ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        row := NEW;
+        op := 'restored';
+    ELSE
+        row := NEW;
+        op := 'updated';
+    END IF;
+    PERFORM pg_notify('user_changes',
+                      json_build_object('op', op, 'id', row.id, 'version', row.version)::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS users_notify_change ON users;
+CREATE TRIGGER users_notify_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON users
+    FOR EACH ROW
+EXECUTE FUNCTION users_notify_change();
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS documents;
+DROP FUNCTION IF EXISTS create_documents_table();
+-- The extension is left installed; other objects may use it.
@@ -0,0 +1,59 @@
+-- Documents with an embedding for similarity search. Needs the pgvector extension, which the
+-- Docker image ships. Without it `create_documents_table` leaves the table out with a warning,
+-- so the rest of the schema still applies, and `migrate::up` calls it again after every run:
+-- installing pgvector and running `backend migrate up` adds the table.
+--
+-- The shape comes from `[documents]` in the server config, which `migrate::up` passes in as
+-- `app.documents_*` settings. It is fixed once applied: changing it means a new migration.
+CREATE OR REPLACE FUNCTION create_documents_table() RETURNS VOID
+    LANGUAGE plpgsql AS
+$$
+DECLARE
+    dimension INTEGER := coalesce(nullif(current_setting('app.documents_dimension', true), ''), '1536')::INTEGER;
+    index_method TEXT := coalesce(nullif(current_setting('app.documents_index', true), ''), 'hnsw');
+    metric TEXT := coalesce(nullif(current_setting('app.documents_metric', true), ''), 'cosine');
+    lists INTEGER := coalesce(nullif(current_setting('app.documents_lists', true), ''), '100')::INTEGER;
+    opclass TEXT;
+BEGIN
+    IF to_regclass('documents') IS NOT NULL THEN
+        RETURN;
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'vector') THEN
+        RAISE WARNING 'pgvector is not installed, the documents table is not created';
+        RETURN;
+    END IF;
+    CREATE EXTENSION IF NOT EXISTS vector;
+
+    EXECUTE format(
+        'CREATE TABLE IF NOT EXISTS documents
+         (
+             id         TEXT PRIMARY KEY NOT NULL,
+             content    TEXT             NOT NULL,
+             metadata   JSONB            NOT NULL DEFAULT ''{}'',
+             embedding  vector(%s)       NOT NULL,
+             created_at TIMESTAMPTZ      NOT NULL DEFAULT now(),
+             updated_at TIMESTAMPTZ      NOT NULL DEFAULT now()
+         )', dimension);
+    CREATE INDEX IF NOT EXISTS documents_metadata_idx ON documents USING gin (metadata jsonb_path_ops);
+
+    -- Only searches by the indexed metric use the index; the others scan the table.
+    opclass := CASE metric
+        WHEN 'cosine' THEN 'vector_cosine_ops'
+        WHEN 'l2' THEN 'vector_l2_ops'
+        WHEN 'inner_product' THEN 'vector_ip_ops'
+    END;
+    IF opclass IS NULL THEN
+        RAISE EXCEPTION 'unknown documents metric %', metric;
+    END IF;
+    IF index_method = 'hnsw' THEN
+        EXECUTE format('CREATE INDEX IF NOT EXISTS documents_embedding_idx ON documents USING hnsw (embedding %s)', opclass);
+    ELSIF index_method = 'ivfflat' THEN
+        EXECUTE format('CREATE INDEX IF NOT EXISTS documents_embedding_idx ON documents USING ivfflat (embedding %s) WITH (lists = %s)', opclass, lists);
+    ELSIF index_method <> 'none' THEN
+        RAISE EXCEPTION 'unknown documents index %', index_method;
+    END IF;
+END
+$$;
+REVOKE ALL ON FUNCTION create_documents_table() FROM PUBLIC;
+
+SELECT create_documents_table();
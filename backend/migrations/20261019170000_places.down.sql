@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS places;
+DROP FUNCTION IF EXISTS create_places_table();
+-- The extension is left installed; other objects may use it.
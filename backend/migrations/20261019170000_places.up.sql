@@ -0,0 +1,34 @@
+-- Named locations for geospatial queries. Needs PostGIS, which `create_places_table` waits for
+-- the way `create_documents_table` waits for pgvector.
+--
+-- Points are WGS 84 longitude/latitude (SRID 4326). The plain index serves bounding boxes;
+-- the one on the geography cast serves distances in metres and nearest-first ordering.
+CREATE OR REPLACE FUNCTION create_places_table() RETURNS VOID
+    LANGUAGE plpgsql AS
+$$
+BEGIN
+    IF to_regclass('places') IS NOT NULL THEN
+        RETURN;
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'postgis') THEN
+        RAISE WARNING 'PostGIS is not installed, the places table is not created';
+        RETURN;
+    END IF;
+    CREATE EXTENSION IF NOT EXISTS postgis;
+
+    CREATE TABLE IF NOT EXISTS places
+    (
+        id         TEXT PRIMARY KEY      NOT NULL,
+        name       TEXT                  NOT NULL,
+        properties JSONB                 NOT NULL DEFAULT '{}',
+        location   geometry(POINT, 4326) NOT NULL,
+        created_at TIMESTAMPTZ           NOT NULL DEFAULT now(),
+        updated_at TIMESTAMPTZ           NOT NULL DEFAULT now()
+    );
+    CREATE INDEX IF NOT EXISTS places_location_idx ON places USING gist (location);
+    CREATE INDEX IF NOT EXISTS places_location_geography_idx ON places USING gist ((location::geography));
+END
+$$;
+REVOKE ALL ON FUNCTION create_places_table() FROM PUBLIC;
+
+SELECT create_places_table();
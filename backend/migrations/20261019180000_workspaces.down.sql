@@ -0,0 +1,37 @@
+DO
+$$
+DECLARE
+    scoped TEXT;
+BEGIN
+    FOREACH scoped IN ARRAY ARRAY ['documents', 'places']
+        LOOP
+            IF to_regclass(scoped) IS NULL THEN
+                CONTINUE;
+            END IF;
+            EXECUTE format('DROP POLICY IF EXISTS %I ON %I', scoped || '_workspace', scoped);
+            EXECUTE format('DROP POLICY IF EXISTS %I ON %I', scoped || '_owner', scoped);
+            EXECUTE format('ALTER TABLE %I NO FORCE ROW LEVEL SECURITY', scoped);
+            EXECUTE format('ALTER TABLE %I DISABLE ROW LEVEL SECURITY', scoped);
+            -- Ids may now repeat across workspaces; keep one row per id.
+            EXECUTE format('DELETE FROM %I a USING %I b
+                            WHERE a.id = b.id AND a.workspace_id > b.workspace_id', scoped, scoped);
+            EXECUTE format('ALTER TABLE %I DROP CONSTRAINT %I, ADD PRIMARY KEY (id)',
+                           scoped, scoped || '_pkey');
+            EXECUTE format('ALTER TABLE %I DROP COLUMN workspace_id', scoped);
+        END LOOP;
+END
+$$;
+DROP FUNCTION IF EXISTS scope_to_workspace(TEXT);
+DROP FUNCTION IF EXISTS create_workspace(TEXT, TEXT, TEXT);
+DROP FUNCTION IF EXISTS exempt_table_owner(TEXT);
+DROP POLICY IF EXISTS workspaces_select ON workspaces;
+DROP TABLE IF EXISTS memberships;
+DROP TABLE IF EXISTS workspaces;
+DROP FUNCTION IF EXISTS app_current_workspace();
+DROP FUNCTION IF EXISTS app_current_user();
+ALTER DEFAULT PRIVILEGES IN SCHEMA public REVOKE USAGE, SELECT ON SEQUENCES FROM app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public REVOKE SELECT, INSERT, UPDATE, DELETE ON TABLES FROM app_tenant;
+REVOKE USAGE, SELECT ON ALL SEQUENCES IN SCHEMA public FROM app_tenant;
+REVOKE SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA public FROM app_tenant;
+REVOKE USAGE ON SCHEMA public FROM app_tenant;
+-- The role is left in place: roles belong to the whole cluster, not this database.
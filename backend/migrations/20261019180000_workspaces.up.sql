@@ -0,0 +1,159 @@
+-- Workspaces (tenants) and the users who belong to them.
+--
+-- Request transactions run as `app_tenant` with `app.current_user` and `app.current_workspace`
+-- set (see `db::tenant`). Row-level security then limits workspace data to the current
+-- workspace, whatever the query: `app_tenant` is neither the owner of the tables nor a
+-- superuser, so no policy is bypassed. The policies are forced on the owner of the tables, the
+-- role running the migrations, too, and each table has one more policy letting that role
+-- through (see `exempt_table_owner`). It is the role of the SECURITY DEFINER functions, which
+-- act for users who are not members of a workspace yet, and of background jobs and scheduled
+-- tasks, so `db.url` must be that role, a member of it, a superuser or BYPASSRLS; the server
+-- checks this at startup.
+DO
+$$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'app_tenant') THEN
+        CREATE ROLE app_tenant NOLOGIN;
+    END IF;
+END
+$$;
+GRANT app_tenant TO CURRENT_USER;
+GRANT USAGE ON SCHEMA public TO app_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA public TO app_tenant;
+GRANT USAGE, SELECT ON ALL SEQUENCES IN SCHEMA public TO app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public GRANT USAGE, SELECT ON SEQUENCES TO app_tenant;
+
+CREATE OR REPLACE FUNCTION app_current_user() RETURNS TEXT
+    LANGUAGE sql STABLE AS
+$$
+SELECT nullif(current_setting('app.current_user', true), '')
+$$;
+
+CREATE OR REPLACE FUNCTION app_current_workspace() RETURNS TEXT
+    LANGUAGE sql STABLE AS
+$$
+SELECT nullif(current_setting('app.current_workspace', true), '')
+$$;
+
+-- Lets the owner of `exempted` through its policies. Does nothing when it already does.
+CREATE OR REPLACE FUNCTION exempt_table_owner(exempted TEXT) RETURNS VOID
+    LANGUAGE plpgsql AS
+$$
+BEGIN
+    IF EXISTS (SELECT 1
+               FROM pg_policies
+               WHERE schemaname = current_schema()
+                 AND tablename = exempted
+                 AND policyname = exempted || '_owner') THEN
+        RETURN;
+    END IF;
+    EXECUTE format('CREATE POLICY %I ON %I TO %I USING (true) WITH CHECK (true)',
+                   exempted || '_owner', exempted,
+                   (SELECT pg_get_userbyid(relowner) FROM pg_class WHERE oid = exempted::regclass));
+END
+$$;
+REVOKE ALL ON FUNCTION exempt_table_owner(TEXT) FROM PUBLIC;
+
+CREATE TABLE IF NOT EXISTS workspaces
+(
+    id         TEXT PRIMARY KEY NOT NULL,
+    name       VARCHAR(255)     NOT NULL,
+    created_at TIMESTAMPTZ      NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS memberships
+(
+    workspace_id TEXT        NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    user_id      TEXT        NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role         TEXT        NOT NULL DEFAULT 'member' CHECK (role IN ('owner', 'admin', 'member')),
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (workspace_id, user_id)
+);
+CREATE INDEX IF NOT EXISTS memberships_user_id_idx ON memberships (user_id);
+
+-- A user sees the memberships of the current workspace and their own, so they can pick a
+-- workspace, but only changes those of the current workspace.
+ALTER TABLE memberships ENABLE ROW LEVEL SECURITY;
+ALTER TABLE memberships FORCE ROW LEVEL SECURITY;
+CREATE POLICY memberships_select ON memberships FOR SELECT
+    USING (workspace_id = app_current_workspace() OR user_id = app_current_user());
+CREATE POLICY memberships_insert ON memberships FOR INSERT
+    WITH CHECK (workspace_id = app_current_workspace());
+CREATE POLICY memberships_update ON memberships FOR UPDATE
+    USING (workspace_id = app_current_workspace())
+    WITH CHECK (workspace_id = app_current_workspace());
+CREATE POLICY memberships_delete ON memberships FOR DELETE
+    USING (workspace_id = app_current_workspace());
+SELECT exempt_table_owner('memberships');
+
+-- New workspaces are only made by `create_workspace`, which adds their first owner.
+ALTER TABLE workspaces ENABLE ROW LEVEL SECURITY;
+ALTER TABLE workspaces FORCE ROW LEVEL SECURITY;
+CREATE POLICY workspaces_select ON workspaces FOR SELECT
+    USING (id = app_current_workspace()
+        OR EXISTS (SELECT 1
+                   FROM memberships m
+                   WHERE m.workspace_id = workspaces.id
+                     AND m.user_id = app_current_user()));
+CREATE POLICY workspaces_update ON workspaces FOR UPDATE
+    USING (id = app_current_workspace())
+    WITH CHECK (id = app_current_workspace());
+CREATE POLICY workspaces_delete ON workspaces FOR DELETE
+    USING (id = app_current_workspace());
+SELECT exempt_table_owner('workspaces');
+
+CREATE OR REPLACE FUNCTION create_workspace(new_id TEXT, new_name TEXT, owner_id TEXT)
+    RETURNS SETOF workspaces
+    LANGUAGE plpgsql
+    SECURITY DEFINER
+    SET search_path = public, pg_temp AS
+$$
+BEGIN
+    RETURN QUERY INSERT INTO workspaces (id, name) VALUES (new_id, new_name) RETURNING *;
+    INSERT INTO memberships (workspace_id, user_id, role) VALUES (new_id, owner_id, 'owner');
+END
+$$;
+REVOKE ALL ON FUNCTION create_workspace(TEXT, TEXT, TEXT) FROM PUBLIC;
+GRANT EXECUTE ON FUNCTION create_workspace(TEXT, TEXT, TEXT) TO app_tenant;
+
+-- Existing data moves to a `default` workspace that every existing user joins, admins as owners.
+INSERT INTO workspaces (id, name) VALUES ('default', 'Default') ON CONFLICT DO NOTHING;
+INSERT INTO memberships (workspace_id, user_id, role)
+SELECT 'default', id, CASE WHEN is_admin THEN 'owner' ELSE 'member' END
+FROM users
+WHERE deleted_at IS NULL
+ON CONFLICT DO NOTHING;
+
+-- Moves a table of workspace data into workspaces: new rows go to the current workspace, and
+-- ids only need to be unique within a workspace. Does nothing when the table is missing or
+-- already scoped, so `migrate::up` also runs it for tables an extension adds later.
+CREATE OR REPLACE FUNCTION scope_to_workspace(scoped TEXT) RETURNS VOID
+    LANGUAGE plpgsql AS
+$$
+BEGIN
+    IF to_regclass(scoped) IS NULL OR EXISTS (SELECT 1
+                                              FROM information_schema.columns
+                                              WHERE table_schema = current_schema()
+                                                AND table_name = scoped
+                                                AND column_name = 'workspace_id') THEN
+        RETURN;
+    END IF;
+    EXECUTE format('ALTER TABLE %I ADD COLUMN workspace_id TEXT NOT NULL DEFAULT ''default''
+                        REFERENCES workspaces (id) ON DELETE CASCADE', scoped);
+    EXECUTE format('ALTER TABLE %I ALTER COLUMN workspace_id SET DEFAULT app_current_workspace()', scoped);
+    EXECUTE format('ALTER TABLE %I DROP CONSTRAINT %I, ADD PRIMARY KEY (workspace_id, id)',
+                   scoped, scoped || '_pkey');
+    EXECUTE format('ALTER TABLE %I ENABLE ROW LEVEL SECURITY', scoped);
+    EXECUTE format('ALTER TABLE %I FORCE ROW LEVEL SECURITY', scoped);
+    EXECUTE format('CREATE POLICY %I ON %I
+                        USING (workspace_id = app_current_workspace())
+                        WITH CHECK (workspace_id = app_current_workspace())',
+                   scoped || '_workspace', scoped);
+    PERFORM exempt_table_owner(scoped);
+END
+$$;
+REVOKE ALL ON FUNCTION scope_to_workspace(TEXT) FROM PUBLIC;
+
+SELECT scope_to_workspace('documents');
+SELECT scope_to_workspace('places');
@@ -0,0 +1,3 @@
+DROP FUNCTION IF EXISTS respond_to_invitation(TEXT, INTEGER, TEXT, BOOLEAN);
+DROP FUNCTION IF EXISTS invitation_for_token(TEXT, INTEGER);
+DROP TABLE IF EXISTS invitations;
@@ -0,0 +1,94 @@
+-- Invitations to join a workspace, by email or by username. The invitee gets a signed token
+-- naming the invitation and its `token_version`; resending bumps the version, so only the
+-- latest token works.
+CREATE TABLE IF NOT EXISTS invitations
+(
+    id            TEXT PRIMARY KEY NOT NULL,
+    workspace_id  TEXT             NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    email         VARCHAR(255),
+    username      VARCHAR(255),
+    role          TEXT             NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+    -- pending -> accepted | declined | revoked. A pending invitation past expires_at can
+    -- still be resent.
+    status        TEXT             NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'accepted', 'declined', 'revoked')),
+    token_version INTEGER          NOT NULL DEFAULT 1,
+    invited_by    TEXT             REFERENCES users (id) ON DELETE SET NULL,
+    expires_at    TIMESTAMPTZ      NOT NULL,
+    created_at    TIMESTAMPTZ      NOT NULL DEFAULT now(),
+    sent_at       TIMESTAMPTZ      NOT NULL DEFAULT now(),
+    responded_at  TIMESTAMPTZ,
+    CHECK ((email IS NULL) <> (username IS NULL))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS invitations_pending_email_idx
+    ON invitations (workspace_id, lower(email)) WHERE status = 'pending' AND email IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS invitations_pending_username_idx
+    ON invitations (workspace_id, username) WHERE status = 'pending' AND username IS NOT NULL;
+
+ALTER TABLE invitations ENABLE ROW LEVEL SECURITY;
+ALTER TABLE invitations FORCE ROW LEVEL SECURITY;
+CREATE POLICY invitations_workspace ON invitations
+    USING (workspace_id = app_current_workspace())
+    WITH CHECK (workspace_id = app_current_workspace());
+SELECT exempt_table_owner('invitations');
+
+-- Invitees are not members yet, so they reach their invitation through these two functions,
+-- which only answer to the id and version of a token.
+CREATE OR REPLACE FUNCTION invitation_for_token(invitation_id TEXT, version INTEGER)
+    RETURNS TABLE
+            (
+                id             TEXT,
+                workspace_id   TEXT,
+                workspace_name TEXT,
+                email          TEXT,
+                username       TEXT,
+                role           TEXT,
+                status         TEXT,
+                expires_at     TIMESTAMPTZ
+            )
+    LANGUAGE sql
+    STABLE
+    SECURITY DEFINER
+    SET search_path = public, pg_temp AS
+$$
+SELECT i.id, i.workspace_id, w.name, i.email, i.username, i.role, i.status, i.expires_at
+FROM invitations i
+         JOIN workspaces w ON w.id = i.workspace_id
+WHERE i.id = invitation_id
+  AND i.token_version = version
+$$;
+
+-- Accepts (adding `responder` to the workspace) or declines a pending, unexpired invitation.
+-- Returns its workspace, or NULL when it could not be answered any more.
+CREATE OR REPLACE FUNCTION respond_to_invitation(invitation_id TEXT, version INTEGER, responder TEXT,
+                                                 accept BOOLEAN)
+    RETURNS TEXT
+    LANGUAGE plpgsql
+    SECURITY DEFINER
+    SET search_path = public, pg_temp AS
+$$
+DECLARE
+    joined_workspace TEXT;
+    joined_role      TEXT;
+BEGIN
+    UPDATE invitations i
+    SET status       = CASE WHEN accept THEN 'accepted' ELSE 'declined' END,
+        responded_at = now()
+    WHERE i.id = invitation_id
+      AND i.token_version = version
+      AND i.status = 'pending'
+      AND i.expires_at > now()
+    RETURNING i.workspace_id, i.role INTO joined_workspace, joined_role;
+    IF joined_workspace IS NOT NULL AND accept THEN
+        INSERT INTO memberships (workspace_id, user_id, role)
+        VALUES (joined_workspace, responder, joined_role)
+        ON CONFLICT (workspace_id, user_id) DO NOTHING;
+    END IF;
+    RETURN joined_workspace;
+END
+$$;
+
+REVOKE ALL ON FUNCTION invitation_for_token(TEXT, INTEGER) FROM PUBLIC;
+REVOKE ALL ON FUNCTION respond_to_invitation(TEXT, INTEGER, TEXT, BOOLEAN) FROM PUBLIC;
+GRANT EXECUTE ON FUNCTION invitation_for_token(TEXT, INTEGER) TO app_tenant;
+GRANT EXECUTE ON FUNCTION respond_to_invitation(TEXT, INTEGER, TEXT, BOOLEAN) TO app_tenant;
@@ -0,0 +1,45 @@
+-- Append-only record of who did what. Rows are inserted in the transaction of the change they
+-- describe, except failed logins, which are written on their own since their request rolls back.
+-- With `[audit] hash_chain` on, every row carries the SHA-256 of its content and of the previous
+-- chained row, so rewriting or removing one breaks the chain from there on.
+CREATE TABLE IF NOT EXISTS audit_events
+(
+    seq         BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    id          TEXT        NOT NULL UNIQUE,
+    occurred_at TIMESTAMPTZ NOT NULL,
+    -- No foreign keys: events outlive the users they mention.
+    actor_id    TEXT,
+    action      TEXT        NOT NULL,
+    target_type TEXT,
+    target_id   TEXT,
+    -- {"field": {"before": ..., "after": ...}} for the fields that changed.
+    diff        JSONB,
+    details     JSONB,
+    ip          TEXT,
+    user_agent  TEXT,
+    request_id  TEXT,
+    prev_hash   TEXT,
+    hash        TEXT
+);
+CREATE INDEX IF NOT EXISTS audit_events_occurred_at_idx ON audit_events (occurred_at);
+CREATE INDEX IF NOT EXISTS audit_events_actor_idx ON audit_events (actor_id, seq);
+CREATE INDEX IF NOT EXISTS audit_events_target_idx ON audit_events (target_type, target_id, seq);
+CREATE INDEX IF NOT EXISTS audit_events_action_idx ON audit_events (action, seq);
+CREATE INDEX IF NOT EXISTS audit_events_chain_idx ON audit_events (seq) WHERE hash IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION audit_events_append_only() RETURNS trigger
+    LANGUAGE plpgsql AS
+$$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
+CREATE TRIGGER audit_events_no_truncate
+    BEFORE TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
+
+REVOKE UPDATE, DELETE, TRUNCATE ON audit_events FROM app_tenant;
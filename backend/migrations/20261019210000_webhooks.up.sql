@@ -0,0 +1,44 @@
+-- Outbound webhooks. A subscription receives the events whose type it lists; every event sent
+-- to it is a row of webhook_deliveries, posted by a `deliver_webhook` job and retried by the job
+-- queue. A subscription whose endpoint keeps failing is disabled until an admin turns it back on.
+CREATE TABLE IF NOT EXISTS webhook_subscriptions
+(
+    id                   TEXT PRIMARY KEY NOT NULL,
+    url                  TEXT             NOT NULL,
+    event_types          TEXT[]           NOT NULL,
+    -- Key of the HMAC-SHA256 signature of every request.
+    secret               TEXT             NOT NULL,
+    description          TEXT,
+    active               BOOLEAN          NOT NULL DEFAULT TRUE,
+    -- Failed attempts since the last success; reaching `[webhooks] disable_after` turns the
+    -- subscription off.
+    consecutive_failures INTEGER          NOT NULL DEFAULT 0,
+    disabled_at          TIMESTAMPTZ,
+    created_by           TEXT             REFERENCES users (id) ON DELETE SET NULL,
+    created_at           TIMESTAMPTZ      NOT NULL DEFAULT now(),
+    updated_at           TIMESTAMPTZ      NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS webhook_subscriptions_event_types_idx
+    ON webhook_subscriptions USING gin (event_types) WHERE active;
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries
+(
+    id              TEXT PRIMARY KEY NOT NULL,
+    subscription_id TEXT             NOT NULL REFERENCES webhook_subscriptions (id) ON DELETE CASCADE,
+    event_type      TEXT             NOT NULL,
+    payload         JSONB            NOT NULL,
+    -- pending -> succeeded | failed. A failed attempt leaves it pending until the job runs out
+    -- of attempts or the subscription is disabled.
+    status          TEXT             NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'succeeded', 'failed')),
+    attempts        INTEGER          NOT NULL DEFAULT 0,
+    response_status INTEGER,
+    last_error      TEXT,
+    -- Delivery this one replays, if any.
+    replay_of       TEXT             REFERENCES webhook_deliveries (id) ON DELETE SET NULL,
+    created_at      TIMESTAMPTZ      NOT NULL DEFAULT now(),
+    last_attempt_at TIMESTAMPTZ,
+    delivered_at    TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_subscription_idx
+    ON webhook_deliveries (subscription_id, created_at DESC);
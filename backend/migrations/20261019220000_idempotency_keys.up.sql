@@ -0,0 +1,20 @@
+-- Responses to requests sent with an `Idempotency-Key` header, kept for `[idempotency] ttl`
+-- seconds so a retry gets the first response instead of running again. A row without a status
+-- is a request still in flight; its claim lapses after `[idempotency] lock_timeout` seconds.
+CREATE TABLE IF NOT EXISTS idempotency_keys
+(
+    -- Id of the user who sent the request, '' for anonymous requests such as logins.
+    scope        TEXT        NOT NULL,
+    key          TEXT        NOT NULL,
+    -- HMAC of the method, path and body, keyed by the JWT secret, to tell a retry from another
+    -- request.
+    request_hash TEXT        NOT NULL,
+    status_code  INTEGER,
+    -- Headers the handler set and body, encrypted under the JWT secret as those of logins
+    -- carry a bearer token.
+    response     BYTEA,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at   TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (scope, key)
+);
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
@@ -0,0 +1,15 @@
+-- Request counters of the `postgres` rate limit store, one row per route group and client.
+-- Unlogged: counters lost in a crash only give clients a fresh quota.
+CREATE UNLOGGED TABLE IF NOT EXISTS rate_limits
+(
+    key        TEXT PRIMARY KEY NOT NULL,
+    -- Tokens left (token bucket) or requests in the current window (sliding window).
+    count      DOUBLE PRECISION NOT NULL,
+    -- Requests in the previous window (sliding window).
+    previous   DOUBLE PRECISION NOT NULL,
+    -- Unix time of the last refill (token bucket) or start of the current window.
+    since      DOUBLE PRECISION NOT NULL,
+    -- When the counter is back to a full quota and can be pruned.
+    expires_at TIMESTAMPTZ      NOT NULL
+);
+CREATE INDEX IF NOT EXISTS rate_limits_expires_at_idx ON rate_limits (expires_at);
@@ -0,0 +1,2 @@
+-- Nothing to restore: development databases get the user back from `backend seed`.
+SELECT 1;
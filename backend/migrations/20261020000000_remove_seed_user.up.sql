@@ -0,0 +1,6 @@
+-- The first migration inserted the development user zhangsan, whose password `123` is public,
+-- into every database. It is only loaded by `backend seed` now; this removes it wherever its
+-- password was never changed. Run `backend seed` again in development.
+DELETE FROM users
+WHERE id = 'cdd0e080-5bb1-4442-b6f7-2ba60dbd0555'
+  AND password = '$argon2id$v=19$m=19456,t=2,p=1$rcosL5pOPdA2c7i4ZuLA4Q$s0JGh78UzMmu1qZMpVUA3b8kWYLXcZhw7uBfwhYDJ4A';
@@ -0,0 +1,5 @@
+-- Development fixtures, applied by `backend seed` in debug builds. Never part of the schema migrations.
+-- The password of zhangsan is `123`.
+INSERT INTO users (id, username, password, is_admin)
+VALUES ('cdd0e080-5bb1-4442-b6f7-2ba60dbd0555', 'zhangsan', '$argon2id$v=19$m=19456,t=2,p=1$rcosL5pOPdA2c7i4ZuLA4Q$s0JGh78UzMmu1qZMpVUA3b8kWYLXcZhw7uBfwhYDJ4A', TRUE)
+ON CONFLICT (id) DO NOTHING;
@@ -0,0 +1,119 @@
+//! Audit trail of security-relevant and data-changing actions.
+//!
+//! Handlers describe what they did with a [`NewAuditEvent`] and pass it to [`record`], which
+//! adds the actor and the [`AuditContext`] of the request and appends it through
+//! `repositories::audit`. Events are listed by admins at `GET /api/admin/audit`.
+
+use salvo::prelude::*;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::AppResult;
+use crate::hoops::jwt;
+use crate::repositories::{self, NewAuditEvent};
+
+pub const USER_CREATE: &str = "user.create";
+pub const USER_UPDATE: &str = "user.update";
+pub const USER_DELETE: &str = "user.delete";
+pub const USER_RESTORE: &str = "user.restore";
+pub const LOGIN: &str = "auth.login";
+pub const LOGIN_FAILED: &str = "auth.login_failed";
+
+/// Where a request came from, injected by the `audit_context` hoop.
+#[derive(Clone, Default, Debug)]
+pub struct AuditContext {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl AuditContext {
+    pub fn from_request(req: &Request) -> Self {
+        Self {
+            ip: req.remote_addr().ip().map(|ip| ip.to_string()),
+            user_agent: req.header::<String>("user-agent"),
+            request_id: req.header::<String>("x-request-id"),
+        }
+    }
+}
+
+/// `event` with the current user as actor unless it names one, and the request context.
+fn complete(depot: &Depot, mut event: NewAuditEvent) -> NewAuditEvent {
+    if event.actor_id.is_none() {
+        event.actor_id = jwt::current_uid(depot).map(str::to_owned);
+    }
+    let context = depot.obtain::<AuditContext>().cloned().unwrap_or_default();
+    event.ip = context.ip;
+    event.user_agent = context.user_agent;
+    event.request_id = context.request_id;
+    event
+}
+
+/// Appends `event` in the request transaction, so it is recorded exactly when the change it
+/// describes is committed.
+pub async fn record(depot: &mut Depot, event: NewAuditEvent) -> AppResult<()> {
+    let event = complete(depot, event);
+    repositories::audit(depot)?.record(event).await?;
+    Ok(())
+}
+
+/// Appends `event` at once, for failures that roll their request back. Errors are only logged,
+/// so they never hide the failure being recorded.
+pub async fn record_now(depot: &mut Depot, event: NewAuditEvent) {
+    let event = complete(depot, event);
+    let action = event.action.clone();
+    let recorded = match repositories::audit(depot) {
+        Ok(mut audit) => audit.record_now(event).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = recorded {
+        tracing::error!(error = ?e, action, "audit event lost");
+    }
+}
+
+/// `{"field": {"before": ..., "after": ...}}` for the top-level fields that differ between the
+/// two serialized states, `None` when nothing changed. A missing state counts as all `null`.
+pub fn diff<T: Serialize>(before: Option<&T>, after: Option<&T>) -> Option<Value> {
+    let fields = |state: Option<&T>| match state.map(serde_json::to_value) {
+        Some(Ok(Value::Object(fields))) => fields,
+        _ => Map::new(),
+    };
+    let (before, after) = (fields(before), fields(after));
+    let mut diff = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        let old = before.get(key).unwrap_or(&Value::Null);
+        let new = after.get(key).unwrap_or(&Value::Null);
+        if old != new && !diff.contains_key(key) {
+            diff.insert(key.clone(), json!({"before": old, "after": new}));
+        }
+    }
+    (!diff.is_empty()).then_some(Value::Object(diff))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::diff;
+
+    #[test]
+    fn test_diff() {
+        let before = json!({"id": "1", "username": "alice", "email": null});
+        let after = json!({"id": "1", "username": "alice2", "email": "a@example.com"});
+        assert_eq!(
+            diff(Some(&before), Some(&after)),
+            Some(json!({
+                "username": {"before": "alice", "after": "alice2"},
+                "email": {"before": null, "after": "a@example.com"},
+            }))
+        );
+        assert_eq!(diff(Some(&before), Some(&before)), None);
+        assert_eq!(
+            diff(Some(&before), None),
+            Some(json!({
+                "id": {"before": "1", "after": null},
+                "username": {"before": "alice", "after": null},
+            }))
+        );
+    }
+}
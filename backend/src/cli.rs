@@ -0,0 +1,64 @@
+use std::process::ExitCode;
+
+use sqlx::PgPool;
+
+use crate::config::ServerConfig;
+use crate::db::migrate;
+
+const USAGE: &str = "usage: backend [migrate up | migrate status | migrate revert | seed]";
+
+/// Runs a maintenance command against the configured database instead of starting the server.
+pub async fn run(args: &[String], config: &ServerConfig, pool: &PgPool) -> ExitCode {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["migrate", "up"] => migrate_up(pool, config).await,
+        ["migrate", "status"] => migrate_status(pool).await,
+        ["migrate", "revert"] => migrate_revert(pool).await,
+        #[cfg(debug_assertions)]
+        ["seed"] => seed(pool).await,
+        #[cfg(not(debug_assertions))]
+        ["seed"] => Err(anyhow::anyhow!(
+            "`seed` loads development fixtures and only exists in debug builds"
+        )),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("❌ {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn migrate_up(pool: &PgPool, config: &ServerConfig) -> anyhow::Result<()> {
+    migrate::up(pool, config).await?;
+    println!("✅ database schema is up to date");
+    Ok(())
+}
+
+async fn migrate_status(pool: &PgPool) -> anyhow::Result<()> {
+    for m in migrate::status(pool).await? {
+        let state = if m.applied { "applied" } else { "pending" };
+        println!("{} {:<8} {}", m.version, state, m.description);
+    }
+    Ok(())
+}
+
+async fn migrate_revert(pool: &PgPool) -> anyhow::Result<()> {
+    match migrate::revert(pool).await? {
+        Some(version) => println!("↩️  reverted migration {version}"),
+        None => println!("nothing to revert"),
+    }
+    Ok(())
+}
+
+#[cfg(debug_assertions)]
+async fn seed(pool: &PgPool) -> anyhow::Result<()> {
+    migrate::seed(pool).await?;
+    println!("🌱 development seed data loaded");
+    Ok(())
+}
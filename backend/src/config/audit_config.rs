@@ -0,0 +1,10 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct AuditConfig {
+    /// Whether every audit event carries a hash of its content and of the previous event, so
+    /// `GET /api/admin/audit/verify` can tell when rows were rewritten or removed. Chained
+    /// events are written one transaction at a time.
+    #[serde(default)]
+    pub hash_chain: bool,
+}
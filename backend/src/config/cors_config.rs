@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct CorsConfig {
+    #[serde(flatten)]
+    pub policy: CorsPolicy,
+    /// Policies of routes that differ from the one above, by a name of their choosing. Fields
+    /// left out are taken from the main policy.
+    #[serde(default)]
+    pub routers: HashMap<String, CorsRouterConfig>,
+}
+
+/// Which cross-origin requests browsers may send.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CorsPolicy {
+    /// Origins allowed, like `https://app.example.com`. `https://*.example.com` (or
+    /// `*.example.com`, for any scheme) allows the subdomains of a domain and `*` any origin.
+    /// Empty, the default, allows none.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// `*` allows any.
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed besides the CORS-safelisted ones. `*` allows any.
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts may read besides the CORS-safelisted ones. `*` exposes all.
+    #[serde(default = "default_exposed_headers")]
+    pub exposed_headers: Vec<String>,
+    /// How long browsers may cache a preflight answer, in seconds.
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+    /// Whether requests may carry cookies, such as the `jwt_token` set by login. Cannot be
+    /// combined with `*` in any of the lists above, nor with subdomain patterns other than
+    /// `https://*.domain`.
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: default_allowed_methods(),
+            allowed_headers: default_allowed_headers(),
+            exposed_headers: default_exposed_headers(),
+            max_age: default_max_age(),
+            allow_credentials: false,
+        }
+    }
+}
+
+impl CorsPolicy {
+    /// This policy with the fields set by `router` replaced.
+    pub fn with(&self, router: &CorsRouterConfig) -> Self {
+        Self {
+            allowed_origins: router
+                .allowed_origins
+                .clone()
+                .unwrap_or_else(|| self.allowed_origins.clone()),
+            allowed_methods: router
+                .allowed_methods
+                .clone()
+                .unwrap_or_else(|| self.allowed_methods.clone()),
+            allowed_headers: router
+                .allowed_headers
+                .clone()
+                .unwrap_or_else(|| self.allowed_headers.clone()),
+            exposed_headers: router
+                .exposed_headers
+                .clone()
+                .unwrap_or_else(|| self.exposed_headers.clone()),
+            max_age: router.max_age.unwrap_or(self.max_age),
+            allow_credentials: router.allow_credentials.unwrap_or(self.allow_credentials),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CorsRouterConfig {
+    /// Path prefix of the routes, like `/api/admin`. The longest matching prefix wins.
+    pub path: String,
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+    pub exposed_headers: Option<Vec<String>>,
+    pub max_age: Option<u64>,
+    pub allow_credentials: Option<bool>,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
+}
+fn default_allowed_headers() -> Vec<String> {
+    [
+        "authorization",
+        "content-type",
+        "idempotency-key",
+        "x-api-key",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+fn default_exposed_headers() -> Vec<String> {
+    [
+        "x-request-id",
+        "idempotent-replayed",
+        "retry-after",
+        "ratelimit-limit",
+        "ratelimit-remaining",
+        "ratelimit-reset",
+        "ratelimit-policy",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+fn default_max_age() -> u64 {
+    600
+}
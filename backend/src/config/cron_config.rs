@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::default_true;
+
+/// Schedule value that turns a task off.
+pub const SCHEDULE_OFF: &str = "off";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CronConfig {
+    /// Run the recurring tasks from this instance. Instances that share a database elect one
+    /// runner per tick, so leaving this on everywhere is safe.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Schedules replacing the built-in ones, by task name. Cron expressions with a leading
+    /// seconds field, in UTC (`"0 0 3 * * *"` is every day at 03:00), or `"off"`.
+    #[serde(default)]
+    pub schedules: HashMap<String, String>,
+}
+
+impl Default for CronConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            schedules: HashMap::new(),
+        }
+    }
+}
@@ -6,51 +6,92 @@ use super::default_false;
 pub struct DbConfig {
     /// Settings for the primary database. This is usually writeable, but will be read-only in
     /// some configurations.
-    /// An optional follower database. Always read-only.
     #[serde(alias = "database_url")]
     pub url: String,
+    /// Maximum number of connections in the pool.
     #[serde(default = "default_db_pool_size")]
     pub pool_size: u32,
+    /// Number of connections the pool keeps open even when idle.
     pub min_idle: Option<u32>,
-
-    /// Number of seconds to wait for unacknowledged TCP packets before treating the connection as
-    /// broken. This value will determine how long crates.io stays unavailable in case of full
-    /// packet loss between the application and the database: setting it too high will result in an
-    /// unnecessarily long outage (before the unhealthy database logic kicks in), while setting it
-    /// too low might result in healthy connections being dropped.
-    #[serde(default = "default_tcp_timeout")]
-    pub tcp_timeout: u64,
-    /// Time to wait for a connection to become available from the connection
-    /// pool before returning an error.
     /// Time to wait for a connection to become available from the connection
     /// pool before returning an error.
     #[serde(default = "default_connection_timeout")]
     pub connection_timeout: u64,
     /// Time to wait for a query response before canceling the query and
-    /// returning an error.
+    /// returning an error. Enforced by the server through `statement_timeout`.
     #[serde(default = "default_statement_timeout")]
     pub statement_timeout: u64,
-    /// Number of threads to use for asynchronous operations such as connection
-    /// creation.
-    #[serde(default = "default_helper_threads")]
-    pub helper_threads: usize,
+    /// Time a connection may sit idle in the pool before it is closed.
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Time after which a connection is closed and replaced, however busy it is.
+    #[serde(default = "default_max_lifetime")]
+    pub max_lifetime: u64,
     /// Whether to enforce that all the database connections are encrypted with TLS.
     #[serde(default = "default_false")]
     pub enforce_tls: bool,
-}
+    /// PEM file of the CA that signed the server certificate. When set together with
+    /// `enforce_tls`, the certificate and host name are verified as well.
+    pub ssl_root_cert: Option<String>,
+    /// How many times to try reaching the database at startup before giving up, each attempt
+    /// lasting up to `connection_timeout`. `0` keeps trying forever.
+    #[serde(default = "default_connect_attempts")]
+    pub connect_attempts: u32,
+    /// Wait before the second startup attempt, in milliseconds. Doubles after every failure, up
+    /// to 30 seconds.
+    #[serde(default = "default_connect_backoff")]
+    pub connect_backoff: u64,
+    /// Time `/readyz` gives each database check before reporting it down, in milliseconds.
+    #[serde(default = "default_health_timeout")]
+    pub health_timeout: u64,
 
-fn default_helper_threads() -> usize {
-    10
+    /// Settings for an optional follower database. Always read-only. Uses the pool and TLS
+    /// settings above.
+    pub replica_url: Option<String>,
+    /// Replication lag, in milliseconds, beyond which reads go back to the primary.
+    #[serde(default = "default_replica_max_lag")]
+    pub replica_max_lag: u64,
+    /// How often the follower's health and lag are checked, in milliseconds.
+    #[serde(default = "default_replica_check_interval")]
+    pub replica_check_interval: u64,
+    /// How long, in milliseconds, a user's reads stay on the primary after they write, so they
+    /// see their own changes. Writes are remembered by the instance that served them only: behind
+    /// a load balancer, the next read of the user can land on another instance and be served by
+    /// the follower, unless the balancer keeps each user on one instance.
+    #[serde(default = "default_read_your_writes_window")]
+    pub read_your_writes_window: u64,
 }
+
 fn default_db_pool_size() -> u32 {
     10
 }
-fn default_tcp_timeout() -> u64 {
-    10000
-}
 fn default_connection_timeout() -> u64 {
     30000
 }
 fn default_statement_timeout() -> u64 {
     30000
 }
+fn default_idle_timeout() -> u64 {
+    10 * 60 * 1000
+}
+fn default_max_lifetime() -> u64 {
+    30 * 60 * 1000
+}
+fn default_connect_attempts() -> u32 {
+    10
+}
+fn default_connect_backoff() -> u64 {
+    500
+}
+fn default_health_timeout() -> u64 {
+    2000
+}
+fn default_replica_max_lag() -> u64 {
+    5000
+}
+fn default_replica_check_interval() -> u64 {
+    5000
+}
+fn default_read_your_writes_window() -> u64 {
+    5000
+}
@@ -0,0 +1,56 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DocumentsConfig {
+    /// Length of every embedding, as produced by the embedding model in use.
+    #[serde(default = "default_dimension")]
+    pub dimension: usize,
+    /// Valid values: hnsw | ivfflat | none
+    #[serde(default = "default_index")]
+    pub index: String,
+    /// Distance the index is built for, and the default of searches that do not pick one.
+    /// Valid values: cosine | l2 | inner_product
+    #[serde(default = "default_metric")]
+    pub metric: String,
+    /// Number of lists of an `ivfflat` index. Around rows / 1000 up to a million rows.
+    #[serde(default = "default_lists")]
+    pub lists: u32,
+    /// Candidates an `hnsw` search keeps; more is slower and finds more true neighbours.
+    #[serde(default = "default_ef_search")]
+    pub ef_search: u32,
+    /// Lists an `ivfflat` search visits; more is slower and finds more true neighbours.
+    #[serde(default = "default_probes")]
+    pub probes: u32,
+}
+
+impl Default for DocumentsConfig {
+    fn default() -> Self {
+        Self {
+            dimension: default_dimension(),
+            index: default_index(),
+            metric: default_metric(),
+            lists: default_lists(),
+            ef_search: default_ef_search(),
+            probes: default_probes(),
+        }
+    }
+}
+
+fn default_dimension() -> usize {
+    1536
+}
+fn default_index() -> String {
+    "hnsw".into()
+}
+fn default_metric() -> String {
+    "cosine".into()
+}
+fn default_lists() -> u32 {
+    100
+}
+fn default_ef_search() -> u32 {
+    40
+}
+fn default_probes() -> u32 {
+    1
+}
@@ -0,0 +1,39 @@
+use serde::Deserialize;
+
+pub const STORE_MEMORY: &str = "memory";
+pub const STORE_POSTGRES: &str = "postgres";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct IdempotencyConfig {
+    /// Where keys and their responses are kept: `postgres`, so retries can land on any
+    /// instance, or `memory` for a single instance and tests.
+    #[serde(default = "default_store")]
+    pub store: String,
+    /// How long the response to a request with an `Idempotency-Key` is replayed, in seconds.
+    #[serde(default = "default_ttl")]
+    pub ttl: i64,
+    /// How long a request that never finished, because the server stopped or its commit failed,
+    /// holds its key, in seconds. Retries answer 409 until then.
+    #[serde(default = "default_lock_timeout")]
+    pub lock_timeout: i64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            store: default_store(),
+            ttl: default_ttl(),
+            lock_timeout: default_lock_timeout(),
+        }
+    }
+}
+
+fn default_store() -> String {
+    STORE_POSTGRES.into()
+}
+fn default_ttl() -> i64 {
+    24 * 60 * 60
+}
+fn default_lock_timeout() -> i64 {
+    60
+}
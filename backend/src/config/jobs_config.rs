@@ -0,0 +1,66 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct JobsConfig {
+    /// Number of worker tasks processing the `jobs` table. `0` leaves jobs queued, for
+    /// instances that should only serve requests.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// How long an idle worker waits before looking for due jobs again, in milliseconds.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+    /// Number of seconds a claimed job may run before another worker considers its worker dead
+    /// and runs it again. Must exceed the longest job.
+    #[serde(default = "default_lease")]
+    pub lease: u64,
+    /// Delay before the first retry of a failed job, in seconds. Doubles with every attempt.
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+    /// Longest delay between two retries, in seconds.
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+    /// Number of seconds running jobs get to finish at shutdown. Jobs still running after that
+    /// are picked up again once their lease expires.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Number of seconds finished jobs are kept before the `prune_jobs` task deletes them.
+    /// Dead jobs are kept until removed by hand.
+    #[serde(default = "default_retention")]
+    pub retention: i64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            workers: default_workers(),
+            poll_interval: default_poll_interval(),
+            lease: default_lease(),
+            backoff: default_backoff(),
+            max_backoff: default_max_backoff(),
+            shutdown_timeout: default_shutdown_timeout(),
+            retention: default_retention(),
+        }
+    }
+}
+
+fn default_workers() -> usize {
+    2
+}
+fn default_poll_interval() -> u64 {
+    1000
+}
+fn default_lease() -> u64 {
+    5 * 60
+}
+fn default_backoff() -> u64 {
+    10
+}
+fn default_max_backoff() -> u64 {
+    60 * 60
+}
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+fn default_retention() -> i64 {
+    7 * 24 * 60 * 60
+}
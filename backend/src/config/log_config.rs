@@ -26,6 +26,10 @@ pub struct LogConfig {
     pub file_name: String,
     #[serde(default = "default_rolling")]
     pub rolling: String,
+    /// Number of days rolled log files are kept in `directory` before the `rotate_logs` task
+    /// deletes them. `0` keeps them forever.
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u64,
     #[serde(default = "default_format")]
     pub format: String,
     #[serde(default = "default_true")]
@@ -51,6 +55,9 @@ fn default_file_name() -> String {
 fn default_rolling() -> String {
     "daily".into()
 }
+fn default_max_age_days() -> u64 {
+    14
+}
 fn default_format() -> String {
     FORMAT_FULL.into()
 }
@@ -64,6 +71,7 @@ impl Default for LogConfig {
             directory: default_directory(),
             file_name: default_file_name(),
             rolling: default_rolling(),
+            max_age_days: default_max_age_days(),
             format: default_format(),
             with_level: true,
             with_target: true,
@@ -1,5 +1,3 @@
-use std::sync::OnceLock;
-
 use figment::Figment;
 use figment::providers::{Env, Format, Toml};
 use serde::Deserialize;
@@ -8,10 +6,30 @@ mod log_config;
 pub use log_config::LogConfig;
 mod db_config;
 pub use db_config::DbConfig;
+pub mod user_config;
+pub use user_config::UserConfig;
+pub mod storage_config;
+pub use storage_config::{S3Config, StorageConfig};
+mod jobs_config;
+pub use jobs_config::JobsConfig;
+pub mod cron_config;
+pub use cron_config::CronConfig;
+pub mod documents_config;
+pub use documents_config::DocumentsConfig;
+mod audit_config;
+pub use audit_config::AuditConfig;
+mod webhooks_config;
+pub use webhooks_config::WebhooksConfig;
+pub mod idempotency_config;
+pub use idempotency_config::IdempotencyConfig;
+pub mod rate_limit_config;
+pub use rate_limit_config::{QuotaConfig, RateLimitConfig};
+mod cors_config;
+pub use cors_config::{CorsConfig, CorsPolicy};
 
-pub static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
-
-pub fn init() {
+/// Reads `config.toml` (or the file named by `APP_CONFIG`) overridden by `APP_*` variables,
+/// exiting the process when it is invalid.
+pub fn load() -> ServerConfig {
     let raw_config = Figment::new()
         .merge(Toml::file(
             Env::var("APP_CONFIG").as_deref().unwrap_or("config.toml"),
@@ -32,23 +50,51 @@ pub fn init() {
         eprintln!("DATABASE_URL is not set");
         std::process::exit(1);
     }
-    crate::config::CONFIG
-        .set(config)
-        .expect("config should be set");
+    config
 }
-pub fn get() -> &'static ServerConfig {
-    CONFIG.get().expect("config should be set")
+
+/// Config parsed from a TOML string alone, for tests that must not depend on the files and
+/// environment of the machine they run on.
+#[cfg(test)]
+pub fn from_toml(toml: &str) -> ServerConfig {
+    Figment::from(Toml::string(toml))
+        .extract()
+        .expect("test config should be valid")
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct ServerConfig {
     #[serde(default = "default_listen_addr")]
     pub listen_addr: String,
+    /// Apply pending migrations at startup. When off, the server refuses to start against a
+    /// database that is behind; run `backend migrate up` first.
+    #[serde(default = "default_false")]
+    pub auto_migrate: bool,
 
     pub db: DbConfig,
     pub log: LogConfig,
     pub jwt: JwtConfig,
     pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub user: UserConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub cron: CronConfig,
+    #[serde(default)]
+    pub documents: DocumentsConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub cors: CorsConfig,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -62,7 +108,6 @@ pub struct TlsConfig {
     pub key: String,
 }
 
-#[allow(dead_code)]
 pub fn default_false() -> bool {
     false
 }
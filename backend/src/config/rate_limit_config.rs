@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+pub const STORE_MEMORY: &str = "memory";
+pub const STORE_POSTGRES: &str = "postgres";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RateLimitConfig {
+    /// Where request counters are kept: `memory`, where every instance counts on its own, or
+    /// `postgres`, so limits hold across instances.
+    #[serde(default = "default_store")]
+    pub store: String,
+    /// Proxies or load balancers in front of the server, as addresses or CIDR ranges. Requests
+    /// they pass on are counted by the address they name in `x-forwarded-for`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Hex SHA-256 digests of the issued API keys. Groups counted by `api_key` count only the
+    /// keys listed here; requests with another key are counted like those without one.
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// Quotas by route group, as named where the routers mount `hoops::rate_limit`. Groups
+    /// without a quota are not limited.
+    #[serde(default)]
+    pub groups: HashMap<String, QuotaConfig>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            store: default_store(),
+            trusted_proxies: Vec::new(),
+            api_keys: Vec::new(),
+            groups: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct QuotaConfig {
+    /// Valid values: token_bucket | sliding_window
+    #[serde(default = "default_algorithm")]
+    pub algorithm: String,
+    /// What requests are counted by. Requests without a user, or without an `x-api-key`
+    /// header naming a key of `api_keys`, are counted by user or else by IP.
+    /// Valid values: ip | user | api_key
+    #[serde(default = "default_key")]
+    pub key: String,
+    /// Requests allowed per `period`, which is also the burst a token bucket allows.
+    pub limit: u32,
+    /// Length of the window, in seconds.
+    pub period: u64,
+}
+
+fn default_store() -> String {
+    STORE_MEMORY.into()
+}
+fn default_algorithm() -> String {
+    "sliding_window".into()
+}
+fn default_key() -> String {
+    "ip".into()
+}
@@ -0,0 +1,86 @@
+use serde::Deserialize;
+
+use super::default_false;
+
+pub const BACKEND_LOCAL: &str = "local";
+pub const BACKEND_S3: &str = "s3";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StorageConfig {
+    /// Valid values: local | s3
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    /// Directory uploaded files are written to by the local backend.
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    /// Settings for the S3 backend. Any S3-compatible service (MinIO, R2, ...) works when
+    /// `endpoint` is set.
+    pub s3: Option<S3Config>,
+    /// Secret used to sign download URLs. Falls back to the JWT secret.
+    pub signing_secret: Option<String>,
+    /// Number of seconds a signed download URL stays valid.
+    #[serde(default = "default_url_expiry")]
+    pub url_expiry: i64,
+    /// Largest accepted upload, in bytes.
+    #[serde(default = "default_max_size")]
+    pub max_size: usize,
+    /// MIME types accepted by `POST /api/files`, as detected from the file's magic bytes.
+    #[serde(default = "default_allowed_types")]
+    pub allowed_types: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct S3Config {
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Custom endpoint such as `http://127.0.0.1:9000`. Requests use path-style addressing.
+    pub endpoint: Option<String>,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Allow a plain-HTTP endpoint, for local stand-ins.
+    #[serde(default = "default_false")]
+    pub allow_http: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: default_backend(),
+            directory: default_directory(),
+            s3: None,
+            signing_secret: None,
+            url_expiry: default_url_expiry(),
+            max_size: default_max_size(),
+            allowed_types: default_allowed_types(),
+        }
+    }
+}
+
+fn default_backend() -> String {
+    BACKEND_LOCAL.into()
+}
+fn default_directory() -> String {
+    "./uploads".into()
+}
+fn default_url_expiry() -> i64 {
+    15 * 60
+}
+fn default_max_size() -> usize {
+    5 * 1024 * 1024
+}
+fn default_allowed_types() -> Vec<String> {
+    [
+        "image/png",
+        "image/jpeg",
+        "image/gif",
+        "image/webp",
+        "application/pdf",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+fn default_region() -> String {
+    "us-east-1".into()
+}
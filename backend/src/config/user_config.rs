@@ -0,0 +1,59 @@
+use serde::Deserialize;
+
+use super::default_false;
+
+pub const REPOSITORY_POSTGRES: &str = "postgres";
+pub const REPOSITORY_MEMORY: &str = "memory";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct UserConfig {
+    /// Where users are kept: `postgres`, or `memory` for demos and tests, which loses everything
+    /// on restart.
+    #[serde(default = "default_repository")]
+    pub repository: String,
+    /// Whether the username of a soft-deleted user may be taken by a new account. When this is
+    /// off the name stays reserved until the row is hard-deleted.
+    #[serde(default = "default_false")]
+    pub release_username_on_delete: bool,
+    /// Number of seconds a soft-deleted user can still be restored by an admin. After that the
+    /// purge job removes the row for good.
+    #[serde(default = "default_retention")]
+    pub retention: i64,
+    /// Number of rows committed per transaction by `POST /api/users/import`. `0` imports the
+    /// whole file in a single transaction, so one bad row rejects all of it.
+    #[serde(default)]
+    pub import_chunk_size: usize,
+    /// Largest import body accepted, in bytes.
+    #[serde(default = "default_import_max_size")]
+    pub import_max_size: usize,
+    /// Number of seconds an invitation to a workspace can be accepted, from when it was last
+    /// sent.
+    #[serde(default = "default_invitation_ttl")]
+    pub invitation_ttl: i64,
+}
+
+impl Default for UserConfig {
+    fn default() -> Self {
+        Self {
+            repository: default_repository(),
+            release_username_on_delete: false,
+            retention: default_retention(),
+            import_chunk_size: 0,
+            import_max_size: default_import_max_size(),
+            invitation_ttl: default_invitation_ttl(),
+        }
+    }
+}
+
+fn default_repository() -> String {
+    REPOSITORY_POSTGRES.into()
+}
+fn default_retention() -> i64 {
+    30 * 24 * 60 * 60
+}
+fn default_import_max_size() -> usize {
+    10 * 1024 * 1024
+}
+fn default_invitation_ttl() -> i64 {
+    7 * 24 * 60 * 60
+}
@@ -0,0 +1,28 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WebhooksConfig {
+    /// How long a receiver has to answer a delivery, in milliseconds.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Number of failed attempts in a row, across deliveries, after which a subscription is
+    /// disabled. `0` never disables one.
+    #[serde(default = "default_disable_after")]
+    pub disable_after: i32,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_timeout(),
+            disable_after: default_disable_after(),
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    10_000
+}
+fn default_disable_after() -> i32 {
+    20
+}
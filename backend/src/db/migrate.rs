@@ -0,0 +1,254 @@
+use sqlx::Connection;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::postgres::{PgConnection, PgPool};
+
+use crate::config::ServerConfig;
+
+/// Every file of `migrations/`, embedded at build time.
+///
+/// `run` and `undo` hold a Postgres advisory lock for their whole duration, so replicas
+/// starting at the same time apply each migration exactly once.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Development fixtures, with an admin whose password is public. Only debug builds embed them.
+#[cfg(debug_assertions)]
+const DEV_SEED: &str = include_str!("../../seeds/dev.sql");
+
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// Values migrations read with `current_setting('app.…', true)`, for schema whose shape comes
+/// from the config.
+fn settings(config: &ServerConfig) -> Vec<(&'static str, String)> {
+    let documents = &config.documents;
+    vec![
+        ("app.documents_dimension", documents.dimension.to_string()),
+        ("app.documents_index", documents.index.clone()),
+        ("app.documents_metric", documents.metric.clone()),
+        ("app.documents_lists", documents.lists.to_string()),
+    ]
+}
+
+/// Calls of functions that migrations define to create what needs an extension, which leave it
+/// out while the extension is missing and do nothing once it exists. `up` runs them after every
+/// run, so an extension installed later gets its tables without reverting anything.
+const DEFERRED: &[&str] = &[
+    "create_documents_table()",
+    "create_places_table()",
+    "scope_to_workspace('documents')",
+    "scope_to_workspace('places')",
+];
+
+/// The first migration, which databases created before migrations were tracked ran by hand.
+const BASELINE: i64 = 20231001143156;
+
+/// Applies every pending migration, then the [`DEFERRED`] steps. A database whose `users` table
+/// predates the bookkeeping table first gets the [`BASELINE`] recorded as applied.
+pub async fn up(pool: &PgPool, config: &ServerConfig) -> Result<(), MigrateError> {
+    // A connection of its own, closed afterwards, so the settings never reach the pool.
+    let mut conn = pool.acquire().await?.detach();
+    for (name, value) in settings(config) {
+        sqlx::query("SELECT set_config($1, $2, false)")
+            .bind(name)
+            .bind(value)
+            .execute(&mut conn)
+            .await?;
+    }
+    let result = match adopt_baseline(&mut conn).await {
+        Ok(()) => match MIGRATOR.run(&mut conn).await {
+            Ok(()) => run_deferred(&mut conn).await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    let _ = conn.close().await;
+    result
+}
+
+/// Records the [`BASELINE`] as applied, without running it, when its `users` table already
+/// exists but was never recorded. Running it again would fail on the user it inserts.
+async fn adopt_baseline(conn: &mut PgConnection) -> Result<(), MigrateError> {
+    conn.lock().await?;
+    let result = async {
+        conn.ensure_migrations_table().await?;
+        let untracked: bool = sqlx::query_scalar(
+            r#"
+            SELECT to_regclass('users') IS NOT NULL
+               AND NOT EXISTS (SELECT 1 FROM _sqlx_migrations WHERE version = $1)
+            "#,
+        )
+        .bind(BASELINE)
+        .fetch_one(&mut *conn)
+        .await?;
+        if !untracked {
+            return Ok(());
+        }
+        let baseline = MIGRATOR
+            .iter()
+            .find(|m| m.version == BASELINE)
+            .expect("the baseline migration is embedded");
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES ($1, $2, TRUE, $3, 0)
+            "#,
+        )
+        .bind(baseline.version)
+        .bind(&*baseline.description)
+        .bind(&*baseline.checksum)
+        .execute(&mut *conn)
+        .await?;
+        tracing::info!(
+            version = BASELINE,
+            "recorded the existing users table as migrated"
+        );
+        Ok(())
+    }
+    .await;
+    conn.unlock().await?;
+    result
+}
+
+async fn run_deferred(conn: &mut PgConnection) -> Result<(), MigrateError> {
+    for call in DEFERRED {
+        sqlx::query(&format!("SELECT {call}"))
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Known migrations in order, and whether each is applied. Fails if a migration was left
+/// half-applied.
+pub async fn status(pool: &PgPool) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(MigrateError::Dirty(version));
+    }
+    let applied = conn.list_applied_migrations().await?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.iter().any(|a| a.version == m.version),
+        })
+        .collect())
+}
+
+/// Version of the newest migration embedded in the binary.
+pub fn expected_version() -> Option<i64> {
+    MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| m.version)
+        .max()
+}
+
+/// Version of the newest applied migration. Unlike [`status`] it never creates the bookkeeping
+/// table, so it is safe for probes. Fails if a migration was left half-applied.
+pub async fn current_version(pool: &PgPool) -> Result<Option<i64>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(MigrateError::Dirty(version));
+    }
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .max())
+}
+
+/// Reverts the most recently applied migration, returning its version. Fails without touching
+/// anything when that migration has no down script, like the [`BASELINE`].
+pub async fn revert(pool: &PgPool) -> Result<Option<i64>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    // Hold the lock across picking the target and undoing it. Advisory locks are re-entrant, so
+    // `undo` taking it again is fine.
+    conn.lock().await?;
+    conn.ensure_migrations_table().await?;
+    let mut applied: Vec<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect();
+    applied.sort_unstable();
+    let reverted = applied.pop();
+    let reversible = |version| {
+        MIGRATOR
+            .iter()
+            .any(|m| m.version == version && m.migration_type.is_down_migration())
+    };
+    let result = match reverted {
+        Some(version) if !reversible(version) => Err(MigrateError::Source(
+            format!("migration {version} has no down script and cannot be reverted").into(),
+        )),
+        Some(_) => MIGRATOR
+            .undo(&mut *conn, applied.last().copied().unwrap_or(0))
+            .await
+            .map(|_| reverted),
+        None => Ok(None),
+    };
+    conn.unlock().await?;
+    result
+}
+
+/// Loads the development fixtures of `seeds/dev.sql`. Safe to run more than once.
+#[cfg(debug_assertions)]
+pub async fn seed(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::raw_sql(DEV_SEED).execute(pool).await?;
+    Ok(())
+}
+
+/// Startup check: applies pending migrations when `auto_migrate` is on, otherwise fails if the
+/// schema is behind the binary. Then fails if the role of `db.url` cannot see every workspace.
+pub async fn prepare(pool: &PgPool, config: &ServerConfig) -> anyhow::Result<()> {
+    if config.auto_migrate {
+        up(pool, config).await?;
+        return check_role(pool).await;
+    }
+    let pending: Vec<String> = status(pool)
+        .await?
+        .into_iter()
+        .filter(|m| !m.applied)
+        .map(|m| format!("{}_{}", m.version, m.description))
+        .collect();
+    if !pending.is_empty() {
+        anyhow::bail!(
+            "database schema is behind, pending migrations: {}. Run `backend migrate up` or set `auto_migrate`.",
+            pending.join(", ")
+        );
+    }
+    check_role(pool).await
+}
+
+/// Background jobs and scheduled tasks run as the role of `db.url`, which the row-level security
+/// of the `workspaces` migration only lets through to every workspace when it owns the tables
+/// (or is a member of their owner), is a superuser or has BYPASSRLS.
+async fn check_role(pool: &PgPool) -> anyhow::Result<()> {
+    let checked: Option<(String, bool)> = sqlx::query_as(
+        r#"
+        SELECT current_user::TEXT,
+               r.rolsuper OR r.rolbypassrls OR pg_has_role(current_user, c.relowner, 'MEMBER')
+        FROM pg_roles r, pg_class c
+        WHERE r.rolname = current_user AND c.oid = to_regclass('workspaces')
+        "#,
+    )
+    .fetch_optional(pool)
+    .await?;
+    match checked {
+        Some((role, false)) => anyhow::bail!(
+            "database role `{role}` does not own the tables and has neither SUPERUSER nor \
+             BYPASSRLS, so jobs would not see workspace data. Connect as the role that ran the \
+             migrations, or grant it to `{role}`."
+        ),
+        _ => Ok(()),
+    }
+}
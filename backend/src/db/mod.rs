@@ -1,18 +1,167 @@
-use std::sync::OnceLock;
-use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use salvo::http::StatusError;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions, PgSslMode};
+
+use crate::AppError;
 use crate::config::DbConfig;
 
-pub static SQLX_POOL: OnceLock<PgPool> = OnceLock::new();
+pub mod migrate;
+pub mod notify;
+pub mod replica;
+pub mod tenant;
+pub mod uow;
+pub use uow::{read_transaction, transaction};
+
+use notify::Notifications;
+use replica::Replica;
+
+/// Connection options for `config.url` with the TLS and timeout settings of `config` applied.
+fn connect_options(config: &DbConfig) -> Result<PgConnectOptions, sqlx::Error> {
+    let mut options = PgConnectOptions::from_str(&config.url)?.options([(
+        "statement_timeout",
+        format!("{}ms", config.statement_timeout),
+    )]);
+    if config.enforce_tls {
+        // sqlx never checks the certificate in `require` mode, so a configured CA only means
+        // something with `verify-full`.
+        options = match &config.ssl_root_cert {
+            Some(root_cert) => options
+                .ssl_mode(PgSslMode::VerifyFull)
+                .ssl_root_cert(root_cert),
+            None => options.ssl_mode(PgSslMode::Require),
+        };
+    }
+    Ok(options)
+}
+
+fn pool_options(config: &DbConfig) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(config.pool_size)
+        .min_connections(config.min_idle.unwrap_or(0))
+        .acquire_timeout(Duration::from_millis(config.connection_timeout))
+        .idle_timeout(Duration::from_millis(config.idle_timeout))
+        .max_lifetime(Duration::from_millis(config.max_lifetime))
+}
+
+/// Connections of the application: the primary and, when `replica_url` is set, the follower.
+/// Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Database {
+    primary: PgPool,
+    replica: Option<Arc<Replica>>,
+    notifications: Notifications,
+}
+
+/// Longest wait between two startup connection attempts.
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+impl Database {
+    /// Connects to the primary, retrying with exponential backoff up to `connect_attempts`
+    /// times, so the server can start before Postgres does.
+    pub async fn connect(config: &DbConfig) -> Result<Self, sqlx::Error> {
+        let options = connect_options(config)?;
+        tracing::info!(
+            pool_size = config.pool_size,
+            min_idle = config.min_idle.unwrap_or(0),
+            connection_timeout_ms = config.connection_timeout,
+            statement_timeout_ms = config.statement_timeout,
+            idle_timeout_ms = config.idle_timeout,
+            max_lifetime_ms = config.max_lifetime,
+            ssl_mode = ?options.get_ssl_mode(),
+            "connecting to database"
+        );
+        let mut backoff = Duration::from_millis(config.connect_backoff);
+        let mut attempt = 1;
+        let primary = loop {
+            match pool_options(config).connect_with(options.clone()).await {
+                Ok(pool) => break pool,
+                Err(e) if config.connect_attempts == 0 || attempt < config.connect_attempts => {
+                    tracing::warn!(
+                        error = %e,
+                        attempt,
+                        retry_in_ms = backoff.as_millis() as u64,
+                        "database connection failed, retrying"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        Ok(Self {
+            notifications: Notifications::new(primary.clone()),
+            primary,
+            replica: Replica::start(config),
+        })
+    }
+
+    /// A pool that only connects when first used, for tests that never reach the database.
+    #[cfg(test)]
+    pub fn connect_lazy(config: &DbConfig) -> Self {
+        let options = connect_options(config).expect("Invalid database url.");
+        let primary = pool_options(config).connect_lazy_with(options);
+        Self {
+            notifications: Notifications::new(primary.clone()),
+            primary,
+            replica: None,
+        }
+    }
+
+    /// The primary, for writes and for reads that must see them.
+    pub fn pool(&self) -> &PgPool {
+        &self.primary
+    }
+
+    /// Pool for a read-only query issued on behalf of `uid`: the follower when it is
+    /// configured, healthy and `uid` has not written recently, the primary otherwise.
+    pub fn read_pool(&self, uid: Option<&str>) -> &PgPool {
+        match &self.replica {
+            Some(replica) if replica.is_readable(uid) => replica.pool(),
+            _ => &self.primary,
+        }
+    }
+
+    /// Whether a follower is configured, and if so whether reads currently go to it.
+    pub fn replica_healthy(&self) -> Option<bool> {
+        self.replica
+            .as_ref()
+            .map(|replica| replica.is_readable(None))
+    }
+
+    /// `LISTEN`/`NOTIFY` on the primary.
+    pub fn notifications(&self) -> &Notifications {
+        &self.notifications
+    }
 
-pub async fn init(config: &DbConfig) {
-    let sqlx_pool = PgPool::connect(&config.url).await
-        .expect("Database connection failed.");
-    crate::db::SQLX_POOL
-        .set(sqlx_pool)
-        .expect("sqlx pool should be set")
+    /// Pins the reads of `uid` to the primary for the `read_your_writes_window`.
+    pub fn mark_write(&self, uid: &str) {
+        if let Some(replica) = &self.replica {
+            replica.mark_write(uid);
+        }
+    }
 }
 
-pub fn pool() -> &'static PgPool {
-    SQLX_POOL.get().expect("sqlx pool should be set")
+/// Reports an error caused by a missing `extension`, such as a table created only once it is
+/// installed or one of its types or functions, as `feature` being unavailable. Other errors
+/// pass through.
+pub fn extension_unavailable(e: sqlx::Error, feature: &str, extension: &str) -> AppError {
+    // undefined_table, undefined_object and undefined_function.
+    let missing = e
+        .as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| matches!(&*code, "42P01" | "42704" | "42883"));
+    if missing {
+        tracing::warn!(error = %e, "{feature} need the {extension} extension");
+        StatusError::service_unavailable()
+            .brief(format!(
+                "{feature} are unavailable: the database lacks the {extension} extension."
+            ))
+            .into()
+    } else {
+        e.into()
+    }
 }
@@ -0,0 +1,264 @@
+//! Postgres `LISTEN`/`NOTIFY` fan-out.
+//!
+//! One dedicated connection per process listens to every channel somebody subscribed to and
+//! hands the payloads to the subscribers of that channel. The connection is opened on the first
+//! subscription and reopened with backoff when it drops. Notifications sent while it is down are
+//! lost; subscribers get [`Notification::Missed`] and should reload whatever they keep.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use sqlx::postgres::{PgListener, PgPool};
+use tokio::sync::{broadcast, mpsc};
+
+use super::MAX_CONNECT_BACKOFF;
+
+/// Notifications a subscriber may fall behind by before it is told it missed some.
+const CAPACITY: usize = 256;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// A `NOTIFY` channel and the JSON payload sent on it.
+pub trait Channel: 'static {
+    const NAME: &'static str;
+    type Event: DeserializeOwned + Send;
+}
+
+#[derive(Debug)]
+pub enum Notification<E> {
+    Event(E),
+    /// Some notifications were lost, to a reconnect or to a slow subscriber.
+    Missed,
+}
+
+#[derive(Clone, Debug)]
+enum Message {
+    Payload(Arc<str>),
+    Missed,
+}
+
+/// Entry point of the subsystem. Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Notifications {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    pool: PgPool,
+    channels: Mutex<HashMap<&'static str, broadcast::Sender<Message>>>,
+    /// New channels for the listener task, which is spawned on first use.
+    listen: OnceLock<mpsc::UnboundedSender<&'static str>>,
+}
+
+impl Notifications {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                pool,
+                channels: Mutex::new(HashMap::new()),
+                listen: OnceLock::new(),
+            }),
+        }
+    }
+
+    /// Notifications of `C` sent from now on, by any instance.
+    pub fn subscribe<C: Channel>(&self) -> Subscription<C> {
+        let mut channels = self.inner.channels.lock().unwrap();
+        let sender = channels.entry(C::NAME).or_insert_with(|| {
+            let listen = self.inner.listen.get_or_init(|| {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                tokio::spawn(run(self.inner.clone(), receiver));
+                sender
+            });
+            // The task only stops with the runtime.
+            let _ = listen.send(C::NAME);
+            broadcast::channel(CAPACITY).0
+        });
+        Subscription {
+            receiver: sender.subscribe(),
+            channel: PhantomData,
+        }
+    }
+}
+
+pub struct Subscription<C: Channel> {
+    receiver: broadcast::Receiver<Message>,
+    channel: PhantomData<C>,
+}
+
+impl<C: Channel> Subscription<C> {
+    /// The next notification. Payloads that do not parse as `C::Event` are logged and skipped.
+    pub async fn recv(&mut self) -> Notification<C::Event> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(Message::Payload(payload)) => match serde_json::from_str(&payload) {
+                    Ok(event) => return Notification::Event(event),
+                    Err(e) => {
+                        tracing::warn!(channel = C::NAME, payload = &*payload, error = %e, "invalid notification")
+                    }
+                },
+                Ok(Message::Missed) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    return Notification::Missed;
+                }
+                // `Inner` keeps every sender alive.
+                Err(broadcast::error::RecvError::Closed) => unreachable!(),
+            }
+        }
+    }
+}
+
+impl Inner {
+    fn names(&self) -> Vec<&'static str> {
+        self.channels.lock().unwrap().keys().copied().collect()
+    }
+
+    fn dispatch(&self, channel: &str, message: Message) {
+        if let Some(sender) = self.channels.lock().unwrap().get(channel) {
+            // No receivers left is fine.
+            let _ = sender.send(message);
+        }
+    }
+
+    fn missed(&self) {
+        for sender in self.channels.lock().unwrap().values() {
+            let _ = sender.send(Message::Missed);
+        }
+    }
+}
+
+async fn connect(inner: &Inner) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(&inner.pool).await?;
+    listener.listen_all(inner.names()).await?;
+    Ok(listener)
+}
+
+async fn run(inner: Arc<Inner>, mut new_channels: mpsc::UnboundedReceiver<&'static str>) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut listener = None;
+    loop {
+        let current = match &mut listener {
+            Some(listener) => listener,
+            None => match connect(&inner).await {
+                Ok(connected) => listener.insert(connected),
+                Err(e) => {
+                    tracing::warn!(error = %e, retry_in_ms = backoff.as_millis() as u64, "notification listener cannot connect");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+                    continue;
+                }
+            },
+        };
+        tokio::select! {
+            Some(channel) = new_channels.recv() => {
+                if let Err(e) = current.listen(channel).await {
+                    // Reconnecting listens to every known channel again.
+                    tracing::warn!(channel, error = %e, "failed to listen, reconnecting");
+                    listener = None;
+                }
+            }
+            received = current.try_recv() => match received {
+                Ok(Some(notification)) => {
+                    backoff = INITIAL_BACKOFF;
+                    inner.dispatch(
+                        notification.channel(),
+                        Message::Payload(notification.payload().into()),
+                    );
+                }
+                // sqlx reconnected on its own.
+                Ok(None) => {
+                    tracing::warn!("notification listener lost its connection");
+                    inner.missed();
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, retry_in_ms = backoff.as_millis() as u64, "notification listener failed");
+                    inner.missed();
+                    listener = None;
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::marker::PhantomData;
+    use std::sync::{Mutex, OnceLock};
+
+    use sqlx::postgres::PgPool;
+    use tokio::sync::broadcast;
+
+    use super::{CAPACITY, Channel, Inner, Message, Notification, Subscription};
+
+    struct Numbers;
+    impl Channel for Numbers {
+        const NAME: &'static str = "test_numbers";
+        type Event = u32;
+    }
+
+    struct Words;
+    impl Channel for Words {
+        const NAME: &'static str = "test_words";
+        type Event = String;
+    }
+
+    /// `Inner` without its listener task, fed by calling `dispatch` and `missed` directly.
+    fn inner() -> Inner {
+        Inner {
+            pool: PgPool::connect_lazy("postgres://postgres@127.0.0.1/test").unwrap(),
+            channels: Mutex::new(HashMap::new()),
+            listen: OnceLock::new(),
+        }
+    }
+
+    fn subscribe<C: Channel>(inner: &Inner) -> Subscription<C> {
+        let mut channels = inner.channels.lock().unwrap();
+        let sender = channels
+            .entry(C::NAME)
+            .or_insert_with(|| broadcast::channel(CAPACITY).0);
+        Subscription {
+            receiver: sender.subscribe(),
+            channel: PhantomData,
+        }
+    }
+
+    fn payload(payload: &str) -> Message {
+        Message::Payload(payload.into())
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_and_missed() {
+        let inner = inner();
+        let mut numbers = subscribe::<Numbers>(&inner);
+        let mut words = subscribe::<Words>(&inner);
+
+        inner.dispatch(Numbers::NAME, payload("not a number"));
+        inner.dispatch(Numbers::NAME, payload("7"));
+        inner.dispatch("nobody_listens", payload("8"));
+        assert!(matches!(numbers.recv().await, Notification::Event(7)));
+
+        // A reconnect tells every channel, in order with what came before it.
+        inner.dispatch(Words::NAME, payload(r#""hello""#));
+        inner.missed();
+        assert!(matches!(numbers.recv().await, Notification::Missed));
+        assert!(matches!(words.recv().await, Notification::Event(word) if word == "hello"));
+        assert!(matches!(words.recv().await, Notification::Missed));
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_misses() {
+        let inner = inner();
+        let mut slow = subscribe::<Numbers>(&inner);
+        for n in 0..=CAPACITY {
+            inner.dispatch(Numbers::NAME, payload(&n.to_string()));
+        }
+        // The oldest notification was dropped; the rest are still there.
+        assert!(matches!(slow.recv().await, Notification::Missed));
+        assert!(matches!(slow.recv().await, Notification::Event(1)));
+    }
+}
@@ -0,0 +1,118 @@
+//! Routing of read-only queries to the follower database.
+//!
+//! A background task keeps track of whether the follower answers and how far it lags behind;
+//! `Database::read_pool` hands out the follower only while it is healthy and the caller has not
+//! written anything within `read_your_writes_window`. Those writes are kept in process memory,
+//! so the guarantee only holds for requests served by the instance that took the write.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use sqlx::postgres::PgPool;
+
+use crate::config::DbConfig;
+
+#[derive(Debug)]
+pub struct Replica {
+    pool: PgPool,
+    healthy: AtomicBool,
+    sticky_window: Duration,
+    /// Last write of each user through this instance, dropped once it is older than
+    /// `sticky_window`.
+    recent_writes: Mutex<HashMap<String, Instant>>,
+}
+
+impl Replica {
+    /// Connects lazily to `config.replica_url`, if any, and starts watching it. The follower
+    /// starts out unhealthy, so nothing is read from it before the first successful check.
+    pub(super) fn start(config: &DbConfig) -> Option<Arc<Self>> {
+        let url = config.replica_url.as_ref()?;
+        let options = super::connect_options(&DbConfig {
+            url: url.clone(),
+            ..config.clone()
+        })
+        .expect("Invalid replica database url.");
+        tracing::info!(ssl_mode = ?options.get_ssl_mode(), "using read replica");
+        let replica = Arc::new(Self {
+            pool: super::pool_options(config).connect_lazy_with(options),
+            healthy: AtomicBool::new(false),
+            sticky_window: Duration::from_millis(config.read_your_writes_window),
+            recent_writes: Mutex::new(HashMap::new()),
+        });
+        tokio::spawn(watch(
+            Arc::downgrade(&replica),
+            Duration::from_millis(config.replica_check_interval),
+            config.replica_max_lag as f64,
+        ));
+        Some(replica)
+    }
+
+    pub(super) fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub(super) fn is_readable(&self, uid: Option<&str>) -> bool {
+        self.healthy.load(Ordering::Relaxed) && !uid.is_some_and(|uid| self.is_sticky(uid))
+    }
+
+    fn is_sticky(&self, uid: &str) -> bool {
+        let recent_writes = self.recent_writes.lock().expect("replica lock poisoned");
+        recent_writes
+            .get(uid)
+            .is_some_and(|at| at.elapsed() < self.sticky_window)
+    }
+
+    pub(super) fn mark_write(&self, uid: &str) {
+        let mut recent_writes = self.recent_writes.lock().expect("replica lock poisoned");
+        recent_writes.insert(uid.to_owned(), Instant::now());
+    }
+}
+
+/// Checks the follower every `interval` until the last `Database` holding it is dropped.
+async fn watch(replica: Weak<Replica>, interval: Duration, max_lag: f64) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let Some(replica) = replica.upgrade() else {
+            return;
+        };
+        let healthy = match tokio::time::timeout(interval, lag(&replica.pool)).await {
+            Ok(Ok(lag)) if lag <= max_lag => true,
+            Ok(Ok(lag)) => {
+                tracing::debug!(lag_ms = lag, "read replica is lagging");
+                false
+            }
+            Ok(Err(e)) => {
+                tracing::debug!(error = ?e, "read replica check failed");
+                false
+            }
+            Err(_) => false,
+        };
+        if replica.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                tracing::info!("read replica is healthy, routing reads to it");
+            } else {
+                tracing::warn!("read replica is unhealthy or lagging, reading from the primary");
+            }
+        }
+        let mut recent_writes = replica.recent_writes.lock().expect("replica lock poisoned");
+        recent_writes.retain(|_, at| at.elapsed() < replica.sticky_window);
+    }
+}
+
+/// Replication lag of the follower in milliseconds. A follower that has replayed everything it
+/// received counts as caught up, however long ago the primary last wrote.
+async fn lag(pool: &PgPool) -> Result<f64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT CASE
+            WHEN NOT pg_is_in_recovery() OR pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+            ELSE COALESCE(EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp()) * 1000, 0)
+        END::float8 as "lag!: f64"
+        "#
+    )
+    .fetch_one(pool)
+    .await
+}
@@ -0,0 +1,37 @@
+//! Workspace isolation with row-level security.
+//!
+//! Request transactions run as the `app_tenant` role with the [`Tenant`] of the request in
+//! `app.current_user` and `app.current_workspace`. The policies of the `workspaces` migration
+//! read them, so a query only ever sees the rows of the current workspace, with or without a
+//! `WHERE` clause.
+
+use sqlx::PgConnection;
+
+/// Role the policies apply to, created by the `workspaces` migration.
+const TENANT_ROLE: &str = "app_tenant";
+
+/// Who a request acts for. Empty for requests without a token, which then see no workspace data.
+#[derive(Clone, Default, Debug)]
+pub struct Tenant {
+    pub user_id: Option<String>,
+    pub workspace_id: Option<String>,
+}
+
+impl Tenant {
+    /// Scopes the transaction open on `conn` to this tenant until it ends.
+    pub async fn enter(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            SELECT set_config('role', $1, true),
+                   set_config('app.current_user', $2, true),
+                   set_config('app.current_workspace', $3, true)
+            "#,
+        )
+        .bind(TENANT_ROLE)
+        .bind(self.user_id.as_deref().unwrap_or_default())
+        .bind(self.workspace_id.as_deref().unwrap_or_default())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! Per-request unit of work.
+//!
+//! The `unit_of_work` hoop puts an empty [`UnitOfWork`] in the `Depot`. The first call to
+//! [`transaction`] opens a transaction on the primary, which the hoop commits when the handler
+//! succeeds and rolls back when it fails or panics. Requests that never ask for one cost nothing.
+//!
+//! Every transaction is scoped to the [`Tenant`] of the request, so row-level security applies.
+
+use sqlx::{Acquire, PgConnection, PgPool, Postgres, Transaction};
+
+use super::tenant::Tenant;
+use crate::hoops::jwt;
+use crate::state::AppStateDepotExt;
+use crate::{AppError, AppResult};
+
+pub struct UnitOfWork {
+    pool: PgPool,
+    tx: Option<Transaction<'static, Postgres>>,
+    detached: bool,
+    tenant: Tenant,
+}
+
+impl UnitOfWork {
+    /// A unit of work whose transaction will be opened on `pool`.
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            tx: None,
+            detached: false,
+            tenant: Tenant::default(),
+        }
+    }
+
+    pub fn tenant(&self) -> &Tenant {
+        &self.tenant
+    }
+
+    /// Scopes the request transaction to `tenant`, from now on if it is already open.
+    pub async fn enter(&mut self, tenant: Tenant) -> AppResult<()> {
+        if let Some(tx) = &mut self.tx {
+            tenant.enter(tx).await?;
+        }
+        self.tenant = tenant;
+        Ok(())
+    }
+
+    /// The request transaction, opened on first use.
+    pub async fn tx(&mut self) -> AppResult<&mut Transaction<'static, Postgres>> {
+        if self.detached {
+            return Err(AppError::internal(
+                "the unit of work of this request was detached",
+            ));
+        }
+        match &mut self.tx {
+            Some(tx) => Ok(tx),
+            tx => {
+                let mut opened = self.pool.begin().await?;
+                self.tenant.enter(&mut opened).await?;
+                Ok(tx.insert(opened))
+            }
+        }
+    }
+
+    /// A nested transaction backed by a `SAVEPOINT`. Dropping it without committing rolls back
+    /// only the work done through it; the request transaction carries on.
+    #[allow(dead_code)]
+    pub async fn savepoint(&mut self) -> AppResult<Transaction<'_, Postgres>> {
+        Ok(self.tx().await?.begin().await?)
+    }
+
+    /// Commits the work done so far and stops the hoop from managing this request. Meant for
+    /// handlers that stream their body, which is only produced after the hoop has finished;
+    /// they read through the pool instead.
+    pub async fn detach(&mut self) -> AppResult<()> {
+        self.detached = true;
+        if let Some(tx) = self.tx.take() {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn commit(&mut self) -> Result<(), sqlx::Error> {
+        match self.tx.take() {
+            Some(tx) => tx.commit().await,
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn rollback(&mut self) -> Result<(), sqlx::Error> {
+        match self.tx.take() {
+            Some(tx) => tx.rollback().await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// The unit of work the `unit_of_work` hoop stored in `depot`.
+pub fn unit_of_work(depot: &mut salvo::Depot) -> AppResult<&mut UnitOfWork> {
+    depot
+        .obtain_mut::<UnitOfWork>()
+        .map_err(|_| AppError::internal("the unit_of_work hoop is not mounted"))
+}
+
+/// Connection of the request transaction, opened on first use.
+pub async fn transaction(depot: &mut salvo::Depot) -> AppResult<&mut PgConnection> {
+    Ok(&mut **unit_of_work(depot)?.tx().await?)
+}
+
+/// A transaction of its own on the read pool of the request (see `Database::read_pool`), scoped
+/// to the same tenant as the request transaction. Only for reads: it is rolled back when dropped.
+pub async fn read_transaction(
+    depot: &mut salvo::Depot,
+) -> AppResult<Transaction<'static, Postgres>> {
+    let pool = depot.state().db.read_pool(jwt::current_uid(depot)).clone();
+    let tenant = unit_of_work(depot)?.tenant().clone();
+    let mut tx = pool.begin().await?;
+    tenant.enter(&mut tx).await?;
+    Ok(tx)
+}
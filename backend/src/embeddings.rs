@@ -0,0 +1,190 @@
+//! Documents with an embedding, and nearest-neighbour search over them, on pgvector.
+//!
+//! The queries are checked at runtime rather than by `query!`: the `vector` type only exists
+//! where the extension is installed, which building the crate does not require. On a database
+//! without it the `documents` table is missing and every call answers 503.
+
+use chrono::{DateTime, Utc};
+use pgvector::Vector;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgConnection};
+
+use crate::config::DocumentsConfig;
+use crate::{AppError, AppResult, db};
+
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Cosine,
+    L2,
+    /// Ranks by inner product; the distance reported is its negation, so smaller is closer as
+    /// for the other metrics.
+    InnerProduct,
+}
+
+impl Metric {
+    /// The metric named by `[documents] metric`. Panics on an unknown name.
+    pub fn from_config(config: &DocumentsConfig) -> Self {
+        match config.metric.as_str() {
+            "cosine" => Self::Cosine,
+            "l2" => Self::L2,
+            "inner_product" => Self::InnerProduct,
+            other => panic!("Unknown documents metric `{other}`"),
+        }
+    }
+
+    fn operator(self) -> &'static str {
+        match self {
+            Self::Cosine => "<=>",
+            Self::L2 => "<->",
+            Self::InnerProduct => "<#>",
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct DocumentRow {
+    id: String,
+    content: String,
+    metadata: Value,
+    embedding: Vector,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Document {
+    pub id: String,
+    pub content: String,
+    pub metadata: Value,
+    pub embedding: Vec<f32>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<DocumentRow> for Document {
+    fn from(row: DocumentRow) -> Self {
+        Self {
+            id: row.id,
+            content: row.content,
+            metadata: row.metadata,
+            embedding: row.embedding.to_vec(),
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema, FromRow, Debug)]
+pub struct Neighbour {
+    pub id: String,
+    pub content: String,
+    pub metadata: Value,
+    /// Distance to the query under the chosen metric, smallest first.
+    pub distance: f64,
+}
+
+pub struct Search {
+    pub embedding: Vec<f32>,
+    pub k: i64,
+    pub metric: Metric,
+    /// Only documents whose metadata contains this object (`@>`) are considered.
+    pub filter: Option<Value>,
+}
+
+/// Reports a missing `documents` table or `vector` type as the service being unavailable.
+fn unavailable(e: sqlx::Error) -> AppError {
+    db::extension_unavailable(e, "Documents", "pgvector")
+}
+
+/// Creates the document `id` or replaces its content, metadata and embedding.
+pub async fn upsert(
+    conn: &mut PgConnection,
+    id: &str,
+    content: &str,
+    metadata: &Value,
+    embedding: Vec<f32>,
+) -> AppResult<Document> {
+    let row = sqlx::query_as::<_, DocumentRow>(
+        r#"
+        INSERT INTO documents (id, content, metadata, embedding)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (workspace_id, id) DO UPDATE
+        SET content = EXCLUDED.content, metadata = EXCLUDED.metadata,
+            embedding = EXCLUDED.embedding, updated_at = now()
+        RETURNING id, content, metadata, embedding, created_at, updated_at
+        "#,
+    )
+    .bind(id)
+    .bind(content)
+    .bind(metadata)
+    .bind(Vector::from(embedding))
+    .fetch_one(conn)
+    .await
+    .map_err(unavailable)?;
+    Ok(row.into())
+}
+
+pub async fn find(conn: &mut PgConnection, id: &str) -> AppResult<Option<Document>> {
+    let row = sqlx::query_as::<_, DocumentRow>(
+        r#"
+        SELECT id, content, metadata, embedding, created_at, updated_at
+        FROM documents WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+    .map_err(unavailable)?;
+    Ok(row.map(Document::from))
+}
+
+pub async fn delete(conn: &mut PgConnection, id: &str) -> AppResult<bool> {
+    let deleted = sqlx::query("DELETE FROM documents WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await
+        .map_err(unavailable)?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+/// The `search.k` documents closest to `search.embedding`. Approximate when an index serves
+/// the metric: `ef_search` and `probes` trade speed for recall. `conn` must be in a transaction,
+/// which those settings last for.
+pub async fn search(
+    conn: &mut PgConnection,
+    config: &DocumentsConfig,
+    search: Search,
+) -> AppResult<Vec<Neighbour>> {
+    sqlx::query(
+        "SELECT set_config('hnsw.ef_search', $1, true), set_config('ivfflat.probes', $2, true)",
+    )
+    .bind(config.ef_search.to_string())
+    .bind(config.probes.to_string())
+    .execute(&mut *conn)
+    .await?;
+    // The operator comes from the enum, never from the request.
+    let sql = format!(
+        r#"
+        SELECT id, content, metadata, (embedding {op} $1)::float8 as distance
+        FROM documents
+        WHERE $2::jsonb IS NULL OR metadata @> $2
+        ORDER BY embedding {op} $1
+        LIMIT $3
+        "#,
+        op = search.metric.operator()
+    );
+    let neighbours = sqlx::query_as::<_, Neighbour>(&sql)
+        .bind(Vector::from(search.embedding))
+        .bind(search.filter)
+        .bind(search.k)
+        .fetch_all(conn)
+        .await
+        .map_err(unavailable)?;
+    Ok(neighbours)
+}
@@ -15,6 +15,8 @@ pub enum AppError {
     HttpStatus(#[from] StatusError),
     #[error("http parse error:`{0}`")]
     HttpParse(#[from] ParseError),
+    #[error("io error:`{0}`")]
+    Io(#[from] std::io::Error),
     #[error("anyhow error:`{0}`")]
     Anyhow(#[from] anyhow::Error),
     #[error("sqlx::Error:`{0}`")]
@@ -37,6 +39,8 @@ impl Writer for AppError {
     async fn write(mut self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
         let code = match &self {
             Self::HttpStatus(e) => e.code,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::HttpParse(ParseError::PayloadTooLarge) => StatusCode::PAYLOAD_TOO_LARGE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
         res.status_code(code);
@@ -51,6 +55,8 @@ impl Writer for AppError {
                 StatusError::internal_server_error()
             }
             Self::HttpStatus(e) => e,
+            Self::Validation(e) => StatusError::bad_request().brief(e.to_string()),
+            Self::HttpParse(ParseError::PayloadTooLarge) => StatusError::payload_too_large(),
             e => StatusError::internal_server_error()
                 .brief(format!("Unknown error happened: {e}"))
                 .cause(e),
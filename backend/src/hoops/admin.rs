@@ -0,0 +1,21 @@
+use salvo::prelude::*;
+
+use crate::AppResult;
+use crate::hoops::jwt;
+use crate::repositories;
+
+/// Lets the request through only when the token set by `auth_hoop` belongs to a live admin.
+/// Must be mounted after `auth_hoop` and `unit_of_work`.
+#[handler]
+pub async fn admin_guard(depot: &mut Depot) -> AppResult<()> {
+    let Some(uid) = jwt::current_uid(depot).map(str::to_owned) else {
+        return Err(StatusError::unauthorized().into());
+    };
+    let is_admin = repositories::users(depot)?.is_admin(&uid).await?;
+    if !is_admin {
+        return Err(StatusError::forbidden()
+            .brief("Admin permission required.")
+            .into());
+    }
+    Ok(())
+}
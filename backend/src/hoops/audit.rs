@@ -0,0 +1,10 @@
+use salvo::prelude::*;
+
+use crate::audit::AuditContext;
+
+/// Puts the [`AuditContext`] of the request in the depot for `audit::record`. Must be mounted
+/// after the `RequestId` hoop to pick up the request id.
+#[handler]
+pub async fn audit_context(req: &mut Request, depot: &mut Depot) {
+    depot.inject(AuditContext::from_request(req));
+}
@@ -18,4 +18,9 @@ pub fn auth_hoop(config: &JwtConfig) -> JwtAuth<JwtClaims, ConstDecoder> {
     ))
         .finders(utils::get_token_finders())
         .force_passed(false)
-}
\ No newline at end of file
+}
+
+/// Like `auth_hoop`, but lets requests without a valid token through, unauthenticated.
+pub fn optional_auth_hoop(config: &JwtConfig) -> JwtAuth<JwtClaims, ConstDecoder> {
+    auth_hoop(config).force_passed(true)
+}
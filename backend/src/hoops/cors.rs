@@ -1,9 +1,257 @@
-use salvo::cors::{AllowHeaders, AllowMethods, AllowOrigin, Cors, CorsHandler};
+use std::cmp::Reverse;
 
-pub fn cors_hoop() -> CorsHandler {
+use salvo::cors::{AllowHeaders, AllowMethods, AllowOrigin, Cors, CorsHandler, ExposeHeaders};
+use salvo::http::Method;
+use salvo::http::header::HeaderName;
+use salvo::prelude::*;
+
+use crate::config::{CorsConfig, CorsPolicy};
+
+/// Answers cross-origin requests with the `[cors]` policy, or the one of the `[cors.routers]`
+/// entry with the longest path prefix of the request. Mounted on the service, so it also
+/// answers preflight requests, which no route handles. Panics on an invalid policy, such as any
+/// origin with credentials, so that it is rejected at startup.
+pub fn cors_hoop(config: &CorsConfig) -> CorsHoop {
+    let mut routers: Vec<(String, CorsHandler)> = config
+        .routers
+        .iter()
+        .map(|(name, router)| {
+            let path = router.path.trim_end_matches('/').to_owned();
+            (path, cors_handler(name, &config.policy.with(router)))
+        })
+        .collect();
+    routers.sort_by_key(|(path, _)| Reverse(path.len()));
+    CorsHoop {
+        routers,
+        default: cors_handler("cors", &config.policy),
+    }
+}
+
+pub struct CorsHoop {
+    routers: Vec<(String, CorsHandler)>,
+    default: CorsHandler,
+}
+
+#[async_trait]
+impl Handler for CorsHoop {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let path = req.uri().path();
+        let handler = self
+            .routers
+            .iter()
+            .find(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map_or(&self.default, |(_, handler)| handler);
+        handler.handle(req, depot, res, ctrl).await;
+    }
+}
+
+fn cors_handler(name: &str, policy: &CorsPolicy) -> CorsHandler {
+    let any = |list: &[String]| list.iter().any(|value| value == "*");
+    if policy.allow_credentials {
+        for (field, list) in [
+            ("allowed_origins", &policy.allowed_origins),
+            ("allowed_methods", &policy.allowed_methods),
+            ("allowed_headers", &policy.allowed_headers),
+            ("exposed_headers", &policy.exposed_headers),
+        ] {
+            if any(list) {
+                panic!("CORS policy `{name}` cannot combine `{field} = [\"*\"]` with credentials");
+            }
+        }
+    }
+    let header_names = |field: &str, list: &[String]| -> Vec<HeaderName> {
+        list.iter()
+            .map(|header| {
+                HeaderName::from_bytes(header.to_ascii_lowercase().as_bytes()).unwrap_or_else(
+                    |_| {
+                        panic!("CORS policy `{name}` has an invalid header `{header}` in `{field}`")
+                    },
+                )
+            })
+            .collect()
+    };
+
+    let allow_origin = if any(&policy.allowed_origins) {
+        AllowOrigin::any()
+    } else {
+        let patterns: Vec<OriginPattern> = policy
+            .allowed_origins
+            .iter()
+            .map(|pattern| {
+                let parsed = OriginPattern::parse(pattern).unwrap_or_else(|| {
+                    panic!("CORS policy `{name}` has an invalid origin `{pattern}`")
+                });
+                // Any subdomain over plain HTTP could be spoofed on the network and then send
+                // the cookies of the user along.
+                if policy.allow_credentials && !parsed.https_only() {
+                    panic!(
+                        "CORS policy `{name}` cannot combine the origin `{pattern}` with \
+                         credentials, subdomain patterns must start with `https://`"
+                    );
+                }
+                parsed
+            })
+            .collect();
+        AllowOrigin::dynamic(move |origin, _, _| {
+            let allowed = origin
+                .and_then(|origin| origin.to_str().ok())
+                .is_some_and(|origin| patterns.iter().any(|pattern| pattern.matches(origin)));
+            origin.filter(|_| allowed).cloned()
+        })
+    };
+    let allow_methods = if any(&policy.allowed_methods) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(policy.allowed_methods.iter().map(|method| {
+            Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                .unwrap_or_else(|_| panic!("CORS policy `{name}` has an invalid method `{method}`"))
+        }))
+    };
+    let allow_headers = if any(&policy.allowed_headers) {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(header_names("allowed_headers", &policy.allowed_headers))
+    };
+    let expose_headers = if any(&policy.exposed_headers) {
+        ExposeHeaders::any()
+    } else {
+        ExposeHeaders::list(header_names("exposed_headers", &policy.exposed_headers))
+    };
     Cors::new()
-        .allow_origin(AllowOrigin::any())
-        .allow_methods(AllowMethods::any())
-        .allow_headers(AllowHeaders::any())
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+        .expose_headers(expose_headers)
+        .max_age(policy.max_age)
+        .allow_credentials(policy.allow_credentials)
         .into_handler()
 }
+
+/// An entry of `allowed_origins` other than `*`.
+#[derive(PartialEq, Debug)]
+enum OriginPattern {
+    Exact(String),
+    /// `[scheme://]*.domain`, kept as the scheme and `.domain`.
+    Subdomains {
+        scheme: Option<String>,
+        suffix: String,
+    },
+}
+
+impl OriginPattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.to_ascii_lowercase();
+        let (scheme, host) = match pattern.split_once("://") {
+            Some((scheme, host)) => (Some(scheme), host),
+            None => (None, pattern.as_str()),
+        };
+        if !matches!(scheme, None | Some("http" | "https")) || host.is_empty() || host.contains('/')
+        {
+            return None;
+        }
+        if let Some(domain) = host.strip_prefix("*.") {
+            return (!domain.is_empty() && !domain.contains('*')).then(|| Self::Subdomains {
+                scheme: scheme.map(str::to_owned),
+                suffix: format!(".{domain}"),
+            });
+        }
+        (scheme.is_some() && !host.contains('*')).then(|| Self::Exact(pattern.clone()))
+    }
+
+    /// Whether the pattern only allows origins served over HTTPS, or is one exact origin.
+    fn https_only(&self) -> bool {
+        match self {
+            Self::Exact(_) => true,
+            Self::Subdomains { scheme, .. } => scheme.as_deref() == Some("https"),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            Self::Exact(exact) => origin == *exact,
+            Self::Subdomains { scheme, suffix } => {
+                origin
+                    .split_once("://")
+                    .is_some_and(|(origin_scheme, host)| {
+                        matches!(origin_scheme, "http" | "https")
+                            && scheme
+                                .as_deref()
+                                .is_none_or(|scheme| scheme == origin_scheme)
+                            && host.len() > suffix.len()
+                            && host.ends_with(suffix.as_str())
+                    })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OriginPattern, cors_hoop};
+    use crate::config::CorsConfig;
+
+    #[test]
+    fn test_origin_patterns() {
+        let subdomains = OriginPattern::parse("https://*.antinna.in").unwrap();
+        assert!(subdomains.matches("https://app.antinna.in"));
+        assert!(subdomains.matches("https://a.b.antinna.in"));
+        assert!(!subdomains.matches("http://app.antinna.in"));
+        assert!(!subdomains.matches("https://antinna.in"));
+        assert!(!subdomains.matches("https://evilantinna.in"));
+        assert!(!subdomains.matches("https://antinna.in.evil.com"));
+        assert!(
+            OriginPattern::parse("*.antinna.in")
+                .unwrap()
+                .matches("http://app.antinna.in")
+        );
+
+        let exact = OriginPattern::parse("https://Dashboard.example.com").unwrap();
+        assert!(exact.matches("https://dashboard.example.com"));
+        assert!(!exact.matches("https://dashboard.example.com:8443"));
+
+        for invalid in [
+            "example.com",
+            "https://example.com/",
+            "ftp://*.example.com",
+            "*.",
+        ] {
+            assert_eq!(OriginPattern::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot combine `allowed_origins")]
+    fn test_any_origin_with_credentials_is_rejected() {
+        let mut config = CorsConfig::default();
+        config.policy.allowed_origins = vec!["*".into()];
+        config.policy.allow_credentials = true;
+        cors_hoop(&config);
+    }
+
+    #[test]
+    fn test_subdomains_with_credentials_need_https() {
+        let mut config = CorsConfig::default();
+        config.policy.allowed_origins = vec![
+            "https://*.antinna.in".into(),
+            "http://localhost:3000".into(),
+        ];
+        config.policy.allow_credentials = true;
+        cors_hoop(&config);
+
+        for pattern in ["*.antinna.in", "http://*.antinna.in"] {
+            config.policy.allowed_origins = vec![pattern.into()];
+            let rejected = std::panic::catch_unwind(|| cors_hoop(&config));
+            assert!(rejected.is_err(), "{pattern}");
+        }
+    }
+}
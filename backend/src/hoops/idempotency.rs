@@ -0,0 +1,260 @@
+use std::collections::HashSet;
+
+use bytes::{Bytes, BytesMut};
+use salvo::http::header::{HeaderName, HeaderValue};
+use salvo::http::{Method, ResBody};
+use salvo::prelude::*;
+
+use crate::AppResult;
+use crate::hoops::jwt;
+use crate::idempotency::{self, Record};
+use crate::state::AppStateDepotExt;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// Set on responses replayed from an earlier request.
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+const MAX_KEY_LEN: usize = 255;
+
+/// Names and values of response headers.
+type Headers = Vec<(String, String)>;
+
+/// Makes a POST carrying an `Idempotency-Key` header safe to retry. The first request with a
+/// key runs and its successful response is stored with the request transaction; retries with
+/// the same method, path and body get that response back, 422 if the key comes with another
+/// request and 409 while the first one is still running. Failed requests release their key.
+/// Keys belong to the user of `auth_hoop` when mounted after it, else to anonymous requests.
+#[handler]
+pub async fn idempotency_key(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) -> AppResult<()> {
+    if req.method() != Method::POST {
+        return Ok(());
+    }
+    let Some(key) = req.header::<String>(IDEMPOTENCY_KEY) else {
+        return Ok(());
+    };
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(StatusError::bad_request()
+            .brief(format!(
+                "`Idempotency-Key` must be 1 to {MAX_KEY_LEN} characters long."
+            ))
+            .into());
+    }
+    let scope = jwt::current_uid(depot).unwrap_or_default().to_owned();
+    let method = req.method().clone();
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.to_string())
+        .unwrap_or_default();
+    let state = depot.state().clone();
+    let secret = &state.config.jwt.secret;
+    let request_hash = idempotency::fingerprint(secret, &method, &path, req.payload().await?);
+    let pool = state.db.pool();
+    let config = &state.config.idempotency;
+    let earlier = state
+        .idempotency
+        .claim(pool, &scope, &key, &request_hash, config)
+        .await?;
+
+    if let Some(earlier) = earlier {
+        return match earlier {
+            Record {
+                request_hash: earlier_hash,
+                ..
+            } if earlier_hash != request_hash => Err(StatusError::unprocessable_entity()
+                .brief("`Idempotency-Key` was already used for a different request.")
+                .into()),
+            Record {
+                status_code: Some(status_code),
+                response: Some(sealed),
+                ..
+            } => {
+                let Some((headers, body)) =
+                    idempotency::open(secret, &sealed).and_then(|plain| decode(&plain))
+                else {
+                    return Err(StatusError::conflict()
+                        .brief("The response to this `Idempotency-Key` cannot be replayed.")
+                        .into());
+                };
+                replay(res, status_code, headers, body);
+                ctrl.skip_rest();
+                Ok(())
+            }
+            _ => Err(StatusError::conflict()
+                .brief("A request with this `Idempotency-Key` is still in progress.")
+                .into()),
+        };
+    }
+
+    let headers_before: HashSet<HeaderName> = res.headers().keys().cloned().collect();
+    ctrl.call_next(req, depot, res).await;
+
+    let status = res.status_code.unwrap_or(StatusCode::OK);
+    let body = match &res.body {
+        ResBody::None => Some(Bytes::new()),
+        ResBody::Once(bytes) => Some(bytes.clone()),
+        ResBody::Chunks(chunks) => Some(
+            chunks
+                .iter()
+                .fold(BytesMut::new(), |mut all, chunk| {
+                    all.extend_from_slice(chunk);
+                    all
+                })
+                .freeze(),
+        ),
+        _ => None,
+    };
+    let (Some(body), true) = (body, status.is_success()) else {
+        if let Err(e) = state.idempotency.release(pool, &scope, &key).await {
+            tracing::error!(error = ?e, "idempotency key release failed");
+        }
+        return Ok(());
+    };
+    let mut headers: Headers = res
+        .headers()
+        .iter()
+        .filter(|(name, _)| !headers_before.contains(*name))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+        .collect();
+    headers.extend(
+        res.cookies()
+            .delta()
+            .map(|cookie| ("set-cookie".to_owned(), cookie.to_string())),
+    );
+    // Sealed, as the response of a login carries its bearer token.
+    let sealed = idempotency::seal(secret, &encode(&headers, &body));
+    let saved = state
+        .idempotency
+        .save(depot, &scope, &key, status.as_u16() as i32, sealed)
+        .await;
+    if let Err(e) = saved {
+        tracing::error!(error = ?e, "idempotent response not stored");
+    }
+    Ok(())
+}
+
+/// The headers and body of a response as one buffer: the length of the JSON of the headers,
+/// in four big-endian bytes, that JSON and the body.
+fn encode(headers: &[(String, String)], body: &[u8]) -> Vec<u8> {
+    let headers = serde_json::to_vec(headers).expect("headers serialize");
+    let mut plain = Vec::with_capacity(4 + headers.len() + body.len());
+    plain.extend_from_slice(&(headers.len() as u32).to_be_bytes());
+    plain.extend_from_slice(&headers);
+    plain.extend_from_slice(body);
+    plain
+}
+
+fn decode(plain: &[u8]) -> Option<(Headers, Vec<u8>)> {
+    let (len, rest) = plain.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (headers, body) = rest.split_at(len);
+    Some((serde_json::from_slice(headers).ok()?, body.to_vec()))
+}
+
+fn replay(res: &mut Response, status_code: i32, headers: Headers, body: Vec<u8>) {
+    res.status_code(
+        u16::try_from(status_code)
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::OK),
+    );
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            res.headers_mut().append(name, value);
+        }
+    }
+    res.headers_mut()
+        .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    res.body(ResBody::Once(Bytes::from(body)));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use salvo::http::Method;
+    use salvo::prelude::*;
+    use salvo::test::{ResponseExt, TestClient};
+
+    use super::{IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED, idempotency_key};
+    use crate::state::AppState;
+    use crate::{hoops, idempotency};
+
+    struct Counted(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Handler for Counted {
+        async fn handle(
+            &self,
+            _req: &mut Request,
+            _depot: &mut Depot,
+            res: &mut Response,
+            _ctrl: &mut FlowCtrl,
+        ) {
+            let runs = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            res.add_header("x-token", "secret-token", true).unwrap();
+            res.render(format!("run {runs}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_conflicts_in_memory() {
+        let state = AppState::for_tests();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let router = Router::with_path("api/things")
+            .hoop(affix_state::inject(state.clone()))
+            .hoop(hoops::unit_of_work)
+            .hoop(idempotency_key)
+            .post(Counted(runs.clone()));
+        let service = Service::new(router);
+        let post = |key: &'static str, body: &'static str| {
+            TestClient::post("http://127.0.0.1/api/things")
+                .add_header(IDEMPOTENCY_KEY, key, true)
+                .raw_form(body)
+                .send(&service)
+        };
+
+        let mut res = post("k1", "a").await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.headers().get(IDEMPOTENT_REPLAYED), None);
+        assert_eq!(res.take_string().await.unwrap(), "run 1");
+
+        // A retry gets the first response back without running the handler again.
+        let mut res = post("k1", "a").await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.headers()[IDEMPOTENT_REPLAYED], "true");
+        assert_eq!(res.headers()["x-token"], "secret-token");
+        assert_eq!(res.take_string().await.unwrap(), "run 1");
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let res = post("k1", "b").await;
+        assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+
+        // A key claimed by the same request, still running.
+        let secret = &state.config.jwt.secret;
+        let hash = idempotency::fingerprint(secret, &Method::POST, "/api/things", b"a");
+        let claimed = state
+            .idempotency
+            .claim(state.db.pool(), "", "k2", &hash, &state.config.idempotency)
+            .await
+            .unwrap();
+        assert!(claimed.is_none());
+        let res = post("k2", "a").await;
+        assert_eq!(res.status_code, Some(StatusCode::CONFLICT));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let mut res = post("k3", "a").await;
+        assert_eq!(res.take_string().await.unwrap(), "run 2");
+    }
+}
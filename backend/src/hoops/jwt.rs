@@ -1,39 +1,63 @@
 use anyhow::Result;
 use jsonwebtoken::{decode, Algorithm, DecodingKey, EncodingKey, Validation};
 
+use salvo::Depot;
+use salvo::jwt_auth::JwtAuthDepotExt;
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 
-use crate::config;
+use crate::config::JwtConfig;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JwtClaims {
-    uid: String,
+    pub uid: String,
+    /// Workspace the token acts in, if the user has picked one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wid: Option<String>,
     exp: i64,
 }
 
 
 
-pub fn generate_jwt_token(uid: impl Into<String>) -> Result<(String, i64)> {
-    let exp = OffsetDateTime::now_utc() + Duration::seconds(config::get().jwt.expiry);
+/// Id of the user whose token `auth_hoop` accepted for this request.
+pub fn current_uid(depot: &Depot) -> Option<&str> {
+    depot
+        .jwt_auth_data::<JwtClaims>()
+        .map(|data| data.claims.uid.as_str())
+}
+
+/// Workspace named by the token `auth_hoop` accepted for this request.
+pub fn current_workspace(depot: &Depot) -> Option<&str> {
+    depot
+        .jwt_auth_data::<JwtClaims>()
+        .and_then(|data| data.claims.wid.as_deref())
+}
+
+pub fn generate_jwt_token(
+    config: &JwtConfig,
+    uid: impl Into<String>,
+    wid: Option<String>,
+) -> Result<(String, i64)> {
+    let exp = OffsetDateTime::now_utc() + Duration::seconds(config.expiry);
     let claim = JwtClaims {
         uid: uid.into(),
+        wid,
         exp: exp.unix_timestamp(),
     };
     let token: String = jsonwebtoken::encode(
         &jsonwebtoken::Header::default(),
         &claim,
-        &EncodingKey::from_secret(config::get().jwt.secret.as_bytes()),
+        &EncodingKey::from_secret(config.secret.as_bytes()),
     )?;
     Ok((token, exp.unix_timestamp()))
 }
 
 #[allow(dead_code)]
-pub fn is_jwt_token_valid(token: &str) -> bool {
+pub fn is_jwt_token_valid(config: &JwtConfig, token: &str) -> bool {
     let validation = Validation::new(Algorithm::HS256);
     decode::<JwtClaims>(
         token,
-        &DecodingKey::from_secret(config::get().jwt.secret.as_bytes()),
+        &DecodingKey::from_secret(config.secret.as_bytes()),
         &validation,
     )
     .is_ok()
@@ -4,9 +4,24 @@ use salvo::prelude::*;
 
 pub mod custom_middleware_example;
 pub mod jwt;
-pub use auth::auth_hoop;
+pub use auth::{auth_hoop, optional_auth_hoop};
+mod admin;
+mod audit;
 mod cors;
 mod auth;
+mod idempotency;
+mod rate_limit;
+mod replica;
+mod transaction;
+mod workspace;
+
+pub use admin::admin_guard;
+pub use audit::audit_context;
+pub use idempotency::idempotency_key;
+pub use rate_limit::rate_limit;
+pub use replica::read_your_writes;
+pub use transaction::unit_of_work;
+pub use workspace::{CurrentWorkspace, workspace_admin, workspace_required, workspace_scope};
 
 pub use cors::cors_hoop;
 
@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use salvo::http::header::{HeaderValue, RETRY_AFTER};
+use salvo::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::config::RateLimitConfig;
+use crate::hoops::jwt;
+use crate::rate_limit::{Decision, KeyBy, Quota};
+use crate::state::AppStateDepotExt;
+
+/// Header whose value identifies the client of groups counted by `api_key`.
+pub const API_KEY: &str = "x-api-key";
+/// Header in which trusted proxies name the clients they pass requests on for.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Limits the requests of each client to the quota of `group` in `[rate_limit.groups]`, letting
+/// everything through when the group has none. Panics when the quota or a trusted proxy is
+/// invalid, so that mistakes show at startup. Groups counted by user must be mounted after
+/// `auth_hoop`.
+pub fn rate_limit(config: &RateLimitConfig, group: &str) -> RateLimit {
+    RateLimit {
+        group: group.to_owned(),
+        quota: config
+            .groups
+            .get(group)
+            .map(|quota| Quota::from_config(group, quota)),
+        trusted_proxies: config
+            .trusted_proxies
+            .iter()
+            .map(|proxy| {
+                proxy
+                    .parse::<IpNet>()
+                    .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                    .unwrap_or_else(|_| panic!("Invalid trusted proxy `{proxy}`"))
+            })
+            .collect(),
+        api_keys: config
+            .api_keys
+            .iter()
+            .map(|digest| digest.to_ascii_lowercase())
+            .collect(),
+    }
+}
+
+pub struct RateLimit {
+    group: String,
+    quota: Option<Quota>,
+    trusted_proxies: Vec<IpNet>,
+    /// Hex SHA-256 digests of the issued API keys.
+    api_keys: HashSet<String>,
+}
+
+impl RateLimit {
+    fn client_key(&self, quota: &Quota, req: &Request, depot: &Depot) -> String {
+        let ip = || {
+            let ip = self.client_ip(req).map(|ip| ip.to_string());
+            format!("ip:{}", ip.unwrap_or_default())
+        };
+        let user = || jwt::current_uid(depot).map_or_else(ip, |uid| format!("user:{uid}"));
+        let client = match quota.key_by {
+            KeyBy::Ip => ip(),
+            KeyBy::User => user(),
+            // Only issued keys are counted on their own: a client making keys up would get a
+            // fresh quota with each. Digests, as rows of `rate_limits` need not reveal keys.
+            KeyBy::ApiKey => req
+                .header::<String>(API_KEY)
+                .map(|key| hex::encode(Sha256::digest(key)))
+                .filter(|digest| self.api_keys.contains(digest))
+                .map_or_else(user, |digest| format!("api_key:{digest}")),
+        };
+        format!("{}:{client}", self.group)
+    }
+
+    /// The address of the client, which is the peer unless the peer is a trusted proxy.
+    fn client_ip(&self, req: &Request) -> Option<IpAddr> {
+        let peer = req.remote_addr().ip()?;
+        let forwarded = req
+            .headers()
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        Some(forwarded_client(peer, &forwarded, &self.trusted_proxies))
+    }
+}
+
+/// Walks `x-forwarded-for` back from `peer` while the hops are trusted proxies, stopping at the
+/// first hop that is not, whose own entries could be made up. A malformed entry ends the walk.
+fn forwarded_client(peer: IpAddr, forwarded: &str, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+    let mut client = peer;
+    for hop in forwarded.rsplit(',') {
+        if !trusted(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(hop) => client = hop,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[async_trait]
+impl Handler for RateLimit {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let Some(quota) = &self.quota else {
+            return;
+        };
+        let key = self.client_key(quota, req, depot);
+        let state = depot.state();
+        // A store that cannot be reached lets requests through rather than taking the routes
+        // it guards down with it.
+        let decision = match state.rate_limits.check(state.db.pool(), &key, quota).await {
+            Ok(decision) => decision,
+            Err(e) => {
+                tracing::error!(error = ?e, group = self.group, "rate limit check failed");
+                return;
+            }
+        };
+        set_headers(res, quota, &decision);
+        if !decision.allowed {
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(decision.reset));
+            res.render(
+                StatusError::too_many_requests()
+                    .brief(format!("Too many requests, retry in {}s.", decision.reset)),
+            );
+            ctrl.skip_rest();
+        }
+    }
+}
+
+/// The `RateLimit-*` headers of the IETF draft on rate limit fields.
+fn set_headers(res: &mut Response, quota: &Quota, decision: &Decision) {
+    let headers = res.headers_mut();
+    headers.insert("ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(decision.reset));
+    if let Ok(policy) = HeaderValue::from_str(&format!("{};w={}", quota.limit, quota.period)) {
+        headers.insert("ratelimit-policy", policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use ipnet::IpNet;
+
+    use super::forwarded_client;
+
+    #[test]
+    fn test_forwarded_client() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let trusted = ["10.0.0.0/8".parse::<IpNet>().unwrap()];
+        let client = |peer, forwarded| forwarded_client(ip(peer), forwarded, &trusted);
+        assert_eq!(client("203.0.113.9", "198.51.100.1"), ip("203.0.113.9"));
+        assert_eq!(client("10.0.0.1", ""), ip("10.0.0.1"));
+        assert_eq!(client("10.0.0.1", "198.51.100.1"), ip("198.51.100.1"));
+        assert_eq!(
+            client("10.0.0.1", "192.0.2.7, 198.51.100.1, 10.0.0.2"),
+            ip("198.51.100.1")
+        );
+        assert_eq!(client("10.0.0.1", "10.0.0.3, 10.0.0.2"), ip("10.0.0.3"));
+        assert_eq!(client("10.0.0.1", "198.51.100.1, junk"), ip("10.0.0.1"));
+    }
+}
@@ -0,0 +1,27 @@
+use salvo::http::Method;
+use salvo::prelude::*;
+
+use crate::hoops::jwt;
+use crate::state::AppStateDepotExt;
+
+/// Records successful writes of the authenticated user so `Database::read_pool` keeps their reads on
+/// the primary until the follower has caught up. Works for any `auth_hoop` mounted below it.
+#[handler]
+pub async fn read_your_writes(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    ctrl.call_next(req, depot, res).await;
+    let is_write = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let failed = res
+        .status_code
+        .is_some_and(|code| code.is_client_error() || code.is_server_error());
+    if is_write
+        && !failed
+        && let Some(uid) = jwt::current_uid(depot)
+    {
+        depot.state().db.mark_write(uid);
+    }
+}
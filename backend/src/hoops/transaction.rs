@@ -0,0 +1,44 @@
+use std::panic::AssertUnwindSafe;
+
+use futures_util::FutureExt;
+use salvo::prelude::*;
+
+use crate::AppError;
+use crate::db::uow::UnitOfWork;
+use crate::state::AppStateDepotExt;
+
+/// Gives the handlers below it a lazily opened request transaction (see `db::transaction`).
+/// It is committed when the response is a success and rolled back on an error status or a
+/// panic, which is then resumed. A failed commit turns the response into a 500.
+#[handler]
+pub async fn unit_of_work(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let pool = depot.state().db.pool().clone();
+    depot.inject(UnitOfWork::new(pool));
+    let outcome = AssertUnwindSafe(ctrl.call_next(req, depot, res))
+        .catch_unwind()
+        .await;
+    let Ok(mut uow) = depot.scrape::<UnitOfWork>() else {
+        return;
+    };
+    let failed = res
+        .status_code
+        .is_some_and(|code| code.is_client_error() || code.is_server_error());
+    if let Err(panic) = outcome {
+        if let Err(e) = uow.rollback().await {
+            tracing::error!(error = ?e, "rollback after panic failed");
+        }
+        std::panic::resume_unwind(panic);
+    }
+    if failed {
+        if let Err(e) = uow.rollback().await {
+            tracing::error!(error = ?e, "request transaction rollback failed");
+        }
+    } else if let Err(e) = uow.commit().await {
+        AppError::from(e).write(req, depot, res).await;
+    }
+}
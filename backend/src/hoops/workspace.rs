@@ -0,0 +1,138 @@
+use salvo::prelude::*;
+
+use crate::db::tenant::Tenant;
+use crate::hoops::jwt;
+use crate::workspaces::Role;
+use crate::{AppResult, db, workspaces};
+
+/// The workspace of the request and the role of its user there, put in the `Depot` by
+/// `workspace_scope`.
+#[derive(Clone, Debug)]
+pub struct CurrentWorkspace {
+    pub id: String,
+    pub role: Role,
+}
+
+impl CurrentWorkspace {
+    /// The workspace of the request, 403 when its token names none.
+    pub fn of(depot: &Depot) -> AppResult<&Self> {
+        depot.obtain::<Self>().map_err(|_| {
+            StatusError::forbidden()
+                .brief("Switch to a workspace first.")
+                .into()
+        })
+    }
+}
+
+/// Scopes the database work of the request to the user of the token and to the workspace it
+/// names, if any, by setting `app.current_user` and `app.current_workspace`. Answers 403 when
+/// the user no longer belongs to that workspace, so removing a member cuts them off at once.
+/// Must be mounted after `auth_hoop`.
+#[handler]
+pub async fn workspace_scope(depot: &mut Depot) -> AppResult<()> {
+    let Some(uid) = jwt::current_uid(depot).map(str::to_owned) else {
+        return Err(StatusError::unauthorized().into());
+    };
+    let wid = jwt::current_workspace(depot).map(str::to_owned);
+    let uow = db::uow::unit_of_work(depot)?;
+    uow.enter(Tenant {
+        user_id: Some(uid.clone()),
+        workspace_id: wid.clone(),
+    })
+    .await?;
+    let Some(wid) = wid else {
+        return Ok(());
+    };
+    let Some(role) = workspaces::role(uow.tx().await?, &wid, &uid).await? else {
+        return Err(StatusError::forbidden()
+            .brief("You are not a member of this workspace.")
+            .into());
+    };
+    depot.inject(CurrentWorkspace { id: wid, role });
+    Ok(())
+}
+
+/// Lets the request through only when its token names a workspace. Must be mounted after
+/// `workspace_scope`.
+#[handler]
+pub async fn workspace_required(depot: &mut Depot) -> AppResult<()> {
+    CurrentWorkspace::of(depot)?;
+    Ok(())
+}
+
+/// Lets the request through only when its user is an owner or admin of the current workspace.
+/// Must be mounted after `workspace_scope`.
+#[handler]
+pub async fn workspace_admin(depot: &mut Depot) -> AppResult<()> {
+    if !CurrentWorkspace::of(depot)?.role.can_manage() {
+        return Err(StatusError::forbidden()
+            .brief("Workspace admin permission required.")
+            .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::prelude::*;
+    use salvo::test::TestClient;
+
+    use super::CurrentWorkspace;
+    use crate::workspaces::Role;
+
+    /// Stands in for `workspace_scope`: the role comes from the `role` query parameter.
+    #[handler]
+    async fn scope(req: &mut Request, depot: &mut Depot) {
+        let role = match req.query::<String>("role").as_deref() {
+            Some("member") => Role::Member,
+            Some("admin") => Role::Admin,
+            Some("owner") => Role::Owner,
+            _ => return,
+        };
+        depot.inject(CurrentWorkspace {
+            id: "w1".into(),
+            role,
+        });
+    }
+
+    #[handler]
+    async fn ok() -> &'static str {
+        "ok"
+    }
+
+    #[tokio::test]
+    async fn test_workspace_guards() {
+        let router = Router::new()
+            .hoop(scope)
+            .push(
+                Router::with_path("required")
+                    .hoop(super::workspace_required)
+                    .get(ok),
+            )
+            .push(
+                Router::with_path("admin")
+                    .hoop(super::workspace_admin)
+                    .get(ok),
+            );
+        let service = Service::new(router);
+        let status = |path: &'static str, role: &'static str| {
+            let service = &service;
+            async move {
+                TestClient::get(format!("http://127.0.0.1/{path}?role={role}"))
+                    .send(service)
+                    .await
+                    .status_code
+            }
+        };
+
+        assert_eq!(
+            status("required", "none").await,
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(status("required", "member").await, Some(StatusCode::OK));
+        assert_eq!(status("admin", "none").await, Some(StatusCode::FORBIDDEN));
+        assert_eq!(status("admin", "member").await, Some(StatusCode::FORBIDDEN));
+        assert_eq!(status("admin", "admin").await, Some(StatusCode::OK));
+        assert_eq!(status("admin", "owner").await, Some(StatusCode::OK));
+    }
+}
@@ -0,0 +1,307 @@
+//! Keys of requests sent with an `Idempotency-Key` header.
+//!
+//! The `idempotency_key` hoop claims the key of a request in the [`IdempotencyStore`] of the
+//! server and, once the request succeeded, saves its response there so retries get it back.
+//! Requests are told apart by an HMAC of their method, path and body, and responses are kept
+//! encrypted, as those of logins carry a bearer token. Both are keyed by the JWT secret.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hmac::{Hmac, Mac};
+use salvo::Depot;
+use salvo::http::Method;
+use sha2::Sha256;
+use sqlx::PgPool;
+
+use crate::AppResult;
+use crate::config::IdempotencyConfig;
+use crate::config::idempotency_config::{STORE_MEMORY, STORE_POSTGRES};
+use crate::db;
+
+/// Seconds between two sweeps of the expired keys of the memory store.
+const MEMORY_PRUNE_INTERVAL: f64 = 60.0;
+const NONCE_LEN: usize = 12;
+
+/// What is kept of a key claimed by an earlier request.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub request_hash: String,
+    /// Unset while the request is still running.
+    pub status_code: Option<i32>,
+    /// The response, sealed by [`seal`].
+    pub response: Option<Vec<u8>>,
+}
+
+/// Where the keys of all routes are kept. Cheap to clone.
+#[derive(Clone)]
+pub enum IdempotencyStore {
+    Memory(Arc<Mutex<MemoryKeys>>),
+    Postgres,
+}
+
+/// Keys of one instance, dropped by the instance itself once expired.
+#[derive(Default)]
+pub struct MemoryKeys {
+    /// Records by scope and key, with the Unix times they were claimed and expire.
+    keys: HashMap<(String, String), (Record, f64, f64)>,
+    /// Unix time of the next sweep.
+    next_prune: f64,
+}
+
+impl MemoryKeys {
+    fn prune(&mut self, now: f64) {
+        self.keys.retain(|_, (_, _, expires)| *expires > now);
+        self.next_prune = now + MEMORY_PRUNE_INTERVAL;
+    }
+}
+
+impl IdempotencyStore {
+    pub fn from_config(config: &IdempotencyConfig) -> Self {
+        match config.store.as_str() {
+            STORE_MEMORY => Self::Memory(Arc::default()),
+            STORE_POSTGRES => Self::Postgres,
+            other => panic!("Unknown idempotency store `{other}`"),
+        }
+    }
+
+    /// Claims `key` for a request, returning `None` when it is claimed and the record of the
+    /// earlier request otherwise. A key past its `ttl`, or held longer than `lock_timeout` by a
+    /// request that never finished, is claimed again.
+    pub async fn claim(
+        &self,
+        pool: &PgPool,
+        scope: &str,
+        key: &str,
+        request_hash: &str,
+        config: &IdempotencyConfig,
+    ) -> AppResult<Option<Record>> {
+        match self {
+            Self::Memory(memory) => {
+                let now = unix_now();
+                let mut memory = memory.lock().expect("idempotency keys poisoned");
+                if now >= memory.next_prune {
+                    memory.prune(now);
+                }
+                let entry = (scope.to_owned(), key.to_owned());
+                if let Some((record, claimed_at, expires)) = memory.keys.get(&entry) {
+                    let lapsed = record.status_code.is_none()
+                        && *claimed_at <= now - config.lock_timeout as f64;
+                    if *expires > now && !lapsed {
+                        return Ok(Some(record.clone()));
+                    }
+                }
+                let record = Record {
+                    request_hash: request_hash.to_owned(),
+                    status_code: None,
+                    response: None,
+                };
+                memory
+                    .keys
+                    .insert(entry, (record, now, now + config.ttl as f64));
+                Ok(None)
+            }
+            Self::Postgres => {
+                let claimed = sqlx::query_scalar!(
+                    r#"
+                    INSERT INTO idempotency_keys (scope, key, request_hash, expires_at)
+                    VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+                    ON CONFLICT (scope, key) DO UPDATE
+                    SET request_hash = EXCLUDED.request_hash, status_code = NULL,
+                        response = NULL, created_at = now(), expires_at = EXCLUDED.expires_at
+                    WHERE idempotency_keys.expires_at <= now()
+                       OR (idempotency_keys.status_code IS NULL
+                           AND idempotency_keys.created_at <= now() - make_interval(secs => $5))
+                    RETURNING key
+                    "#,
+                    scope,
+                    key,
+                    request_hash,
+                    config.ttl as f64,
+                    config.lock_timeout as f64,
+                )
+                .fetch_optional(pool)
+                .await?;
+                if claimed.is_some() {
+                    return Ok(None);
+                }
+                let record = sqlx::query_as!(
+                    Record,
+                    r#"
+                    SELECT request_hash, status_code, response
+                    FROM idempotency_keys
+                    WHERE scope = $1 AND key = $2
+                    "#,
+                    scope,
+                    key
+                )
+                .fetch_optional(pool)
+                .await?;
+                // A row gone since the insert was released by its request, which failed; the
+                // retry is told to try again rather than racing whoever claims it next.
+                Ok(Some(record.unwrap_or(Record {
+                    request_hash: request_hash.to_owned(),
+                    status_code: None,
+                    response: None,
+                })))
+            }
+        }
+    }
+
+    /// Saves the response to the request holding `key`. Postgres saves it in the request
+    /// transaction, so it is replayed only if what it reports was committed.
+    pub async fn save(
+        &self,
+        depot: &mut Depot,
+        scope: &str,
+        key: &str,
+        status_code: i32,
+        response: Vec<u8>,
+    ) -> AppResult<()> {
+        match self {
+            Self::Memory(memory) => {
+                let mut memory = memory.lock().expect("idempotency keys poisoned");
+                if let Some((record, _, _)) =
+                    memory.keys.get_mut(&(scope.to_owned(), key.to_owned()))
+                {
+                    record.status_code = Some(status_code);
+                    record.response = Some(response);
+                }
+                Ok(())
+            }
+            Self::Postgres => {
+                sqlx::query!(
+                    r#"
+                    UPDATE idempotency_keys SET status_code = $3, response = $4
+                    WHERE scope = $1 AND key = $2
+                    "#,
+                    scope,
+                    key,
+                    status_code,
+                    response,
+                )
+                .execute(db::transaction(depot).await?)
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Lets the key be used again, after a request that failed and so changed nothing.
+    pub async fn release(&self, pool: &PgPool, scope: &str, key: &str) -> AppResult<()> {
+        match self {
+            Self::Memory(memory) => {
+                let mut memory = memory.lock().expect("idempotency keys poisoned");
+                let entry = (scope.to_owned(), key.to_owned());
+                if memory
+                    .keys
+                    .get(&entry)
+                    .is_some_and(|(record, _, _)| record.status_code.is_none())
+                {
+                    memory.keys.remove(&entry);
+                }
+                Ok(())
+            }
+            Self::Postgres => {
+                sqlx::query!(
+                    "DELETE FROM idempotency_keys WHERE scope = $1 AND key = $2 AND status_code IS NULL",
+                    scope,
+                    key
+                )
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Deletes the keys past their `[idempotency] ttl`, returning how many. The memory store
+    /// prunes itself in `claim`.
+    pub async fn prune(&self, pool: &PgPool) -> AppResult<u64> {
+        match self {
+            Self::Memory(_) => Ok(0),
+            Self::Postgres => {
+                let pruned = sqlx::query!("DELETE FROM idempotency_keys WHERE expires_at <= now()")
+                    .execute(pool)
+                    .await?
+                    .rows_affected();
+                Ok(pruned)
+            }
+        }
+    }
+}
+
+/// A key of its own for each use of `secret`.
+fn derive_key(secret: &str, purpose: &str) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+        .expect("hmac accepts any key length");
+    mac.update(purpose.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+/// What tells a retry from another request sent with the same key. Keyed, so that the stored
+/// hashes do not let anyone holding the table check guesses of a request body.
+pub fn fingerprint(secret: &str, method: &Method, path: &str, body: &[u8]) -> String {
+    let key = derive_key(secret, "idempotency fingerprint");
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).expect("hmac accepts any key length");
+    mac.update(method.as_str().as_bytes());
+    mac.update(b"\n");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Encrypts a response, as a random nonce followed by the AES-256-GCM ciphertext.
+pub fn seal(secret: &str, response: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(&derive_key(secret, "idempotency response").into());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, response)
+        .expect("aes-gcm encrypts any response");
+    [&nonce[..], &ciphertext].concat()
+}
+
+/// The response [`seal`] encrypted, or `None` when it was sealed under another secret or
+/// tampered with.
+pub fn open(secret: &str, sealed: &[u8]) -> Option<Vec<u8>> {
+    let (nonce, ciphertext) = sealed.split_first_chunk::<NONCE_LEN>()?;
+    let cipher = Aes256Gcm::new(&derive_key(secret, "idempotency response").into());
+    cipher.decrypt(&Nonce::from(*nonce), ciphertext).ok()
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::http::Method;
+
+    use super::{fingerprint, open, seal};
+
+    #[test]
+    fn test_fingerprint() {
+        let login = |secret, path, body| fingerprint(secret, &Method::POST, path, body);
+        let first = login("secret", "/api/login", br#"{"username":"a"}"#);
+        assert_eq!(first, login("secret", "/api/login", br#"{"username":"a"}"#));
+        assert_ne!(first, login("secret", "/api/login", br#"{"username":"b"}"#));
+        assert_ne!(first, login("secret", "/api/users", br#"{"username":"a"}"#));
+        assert_ne!(first, login("other", "/api/login", br#"{"username":"a"}"#));
+    }
+
+    #[test]
+    fn test_seal() {
+        let sealed = seal("secret", b"token");
+        assert!(!sealed.windows(5).any(|window| window == b"token"));
+        assert_ne!(sealed, seal("secret", b"token"));
+        assert_eq!(open("secret", &sealed).as_deref(), Some(&b"token"[..]));
+        assert_eq!(open("other", &sealed), None);
+        assert_eq!(open("secret", &sealed[..8]), None);
+    }
+}
@@ -0,0 +1,263 @@
+//! Invitations to join a workspace, and the signed tokens that answer them.
+//!
+//! Admins manage the invitations of the current workspace through row-level security like any
+//! workspace data. Invitees are not members yet: they only reach their invitation through a
+//! token naming its id and `token_version`, via the `invitation_for_token` and
+//! `respond_to_invitation` functions of the `invitations` migration.
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Validation};
+use salvo::http::StatusError;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::config::JwtConfig;
+use crate::workspaces::Role;
+use crate::{AppError, AppResult};
+
+/// Audience of invitation tokens, which keeps them apart from login tokens signed with the
+/// same secret.
+const AUDIENCE: &str = "invitation";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct InvitationClaims {
+    /// Invitation id.
+    iid: String,
+    /// `token_version` the token was issued for.
+    ver: i32,
+    aud: String,
+    exp: i64,
+}
+
+/// A token for `invitation`, valid until it expires or is resent.
+pub fn sign(config: &JwtConfig, invitation: &Invitation) -> AppResult<String> {
+    let claims = InvitationClaims {
+        iid: invitation.id.clone(),
+        ver: invitation.token_version,
+        aud: AUDIENCE.into(),
+        exp: invitation.expires_at.timestamp(),
+    };
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.secret.as_bytes()),
+    )
+    .map_err(|e| AppError::internal(e.to_string()))
+}
+
+/// Invitation id and version of a valid token, 400 for anything else.
+pub fn verify(config: &JwtConfig, token: &str) -> AppResult<(String, i32)> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[AUDIENCE]);
+    let claims = jsonwebtoken::decode::<InvitationClaims>(
+        token,
+        &DecodingKey::from_secret(config.secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|_| {
+        StatusError::bad_request().brief("The invitation token is invalid or has expired.")
+    })?
+    .claims;
+    Ok((claims.iid, claims.ver))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, sqlx::Type, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Accepted,
+    Declined,
+    Revoked,
+}
+
+/// Who is invited: exactly one of the two is set.
+#[derive(Clone, Debug)]
+pub enum Invitee {
+    Email(String),
+    Username(String),
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Invitation {
+    pub id: String,
+    pub email: Option<String>,
+    pub username: Option<String>,
+    pub role: Role,
+    pub status: Status,
+    pub invited_by: Option<String>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub expires_at: DateTime<Utc>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+    /// When the latest token was issued.
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub sent_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub token_version: i32,
+}
+
+/// Creates a pending invitation to `workspace_id`, or fails with 409 when the invitee already
+/// has one there.
+pub async fn create(
+    conn: &mut PgConnection,
+    workspace_id: &str,
+    id: &str,
+    invitee: &Invitee,
+    role: Role,
+    invited_by: &str,
+    ttl: i64,
+) -> AppResult<Invitation> {
+    let (email, username) = match invitee {
+        Invitee::Email(email) => (Some(email.as_str()), None),
+        Invitee::Username(username) => (None, Some(username.as_str())),
+    };
+    sqlx::query_as!(
+        Invitation,
+        r#"
+        INSERT INTO invitations (id, workspace_id, email, username, role, invited_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7))
+        RETURNING id, email, username, role as "role: Role", status as "status: Status",
+                  invited_by, expires_at, created_at, sent_at, token_version
+        "#,
+        id,
+        workspace_id,
+        email,
+        username,
+        role as Role,
+        invited_by,
+        ttl as f64,
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(|e| {
+        if e.as_database_error()
+            .is_some_and(|e| e.is_unique_violation())
+        {
+            StatusError::conflict()
+                .brief("This person already has a pending invitation; resend it instead.")
+                .into()
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// Pending invitations of `workspace_id`, expired ones included, newest first.
+pub async fn list_pending(
+    conn: &mut PgConnection,
+    workspace_id: &str,
+) -> AppResult<Vec<Invitation>> {
+    Ok(sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT id, email, username, role as "role: Role", status as "status: Status",
+               invited_by, expires_at, created_at, sent_at, token_version
+        FROM invitations
+        WHERE workspace_id = $1 AND status = 'pending'
+        ORDER BY created_at DESC
+        "#,
+        workspace_id,
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Gives a pending invitation a new version and expiry, so only a token signed from the result
+/// is valid. `None` when there is no such pending invitation.
+pub async fn resend(
+    conn: &mut PgConnection,
+    workspace_id: &str,
+    id: &str,
+    ttl: i64,
+) -> AppResult<Option<Invitation>> {
+    Ok(sqlx::query_as!(
+        Invitation,
+        r#"
+        UPDATE invitations
+        SET token_version = token_version + 1,
+            expires_at = now() + make_interval(secs => $3),
+            sent_at = now()
+        WHERE workspace_id = $1 AND id = $2 AND status = 'pending'
+        RETURNING id, email, username, role as "role: Role", status as "status: Status",
+                  invited_by, expires_at, created_at, sent_at, token_version
+        "#,
+        workspace_id,
+        id,
+        ttl as f64,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+/// Revokes a pending invitation, returning whether there was one.
+pub async fn revoke(conn: &mut PgConnection, workspace_id: &str, id: &str) -> AppResult<bool> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE invitations SET status = 'revoked', responded_at = now()
+        WHERE workspace_id = $1 AND id = $2 AND status = 'pending'
+        "#,
+        workspace_id,
+        id,
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+    Ok(revoked > 0)
+}
+
+/// An invitation as its invitee sees it.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Received {
+    pub id: String,
+    pub workspace_id: String,
+    pub workspace_name: String,
+    pub email: Option<String>,
+    pub username: Option<String>,
+    pub role: Role,
+    pub status: Status,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The invitation a token was issued for, if that token is still its latest.
+pub async fn for_token(
+    conn: &mut PgConnection,
+    id: &str,
+    version: i32,
+) -> AppResult<Option<Received>> {
+    Ok(sqlx::query_as!(
+        Received,
+        r#"
+        SELECT id as "id!", workspace_id as "workspace_id!", workspace_name as "workspace_name!",
+               email, username, role as "role!: Role", status as "status!: Status",
+               expires_at as "expires_at!"
+        FROM invitation_for_token($1, $2)
+        "#,
+        id,
+        version,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+/// Accepts the invitation for `responder`, or declines it. Returns its workspace, `None` when it
+/// is no longer pending or has expired.
+pub async fn respond(
+    conn: &mut PgConnection,
+    id: &str,
+    version: i32,
+    responder: Option<&str>,
+    accept: bool,
+) -> AppResult<Option<String>> {
+    Ok(sqlx::query_scalar!(
+        "SELECT respond_to_invitation($1, $2, $3, $4)",
+        id,
+        version,
+        responder,
+        accept,
+    )
+    .fetch_one(conn)
+    .await?)
+}
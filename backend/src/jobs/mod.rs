@@ -0,0 +1,112 @@
+//! Background jobs kept in the `jobs` table.
+//!
+//! A job is a [`Job`] type serialized into a row. [`enqueue`] takes the connection of the
+//! caller's transaction, usually `db::transaction(depot)`, so the job is committed or rolled
+//! back together with the write that asked for it. The [`Workers`] spawned in `main` claim due
+//! rows with `FOR UPDATE SKIP LOCKED`, run them through the [`Registry`] and retry failures with
+//! exponential backoff until `max_attempts`, after which the row is left `dead` for inspection.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use salvo::async_trait;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sqlx::PgConnection;
+
+use crate::state::AppState;
+use crate::{AppError, AppResult};
+
+mod worker;
+
+pub use worker::Workers;
+
+#[async_trait]
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Stored in `jobs.kind` to find the type again. Must be unique and never change while
+    /// rows of the kind may be queued.
+    const KIND: &'static str;
+    /// Runs before the job is dead-lettered.
+    const MAX_ATTEMPTS: i32 = 5;
+
+    /// Does the work. May run more than once, after a failure or a lost lease, so it must be
+    /// idempotent. Errors are logged and stored in `jobs.last_error`.
+    async fn run(self, state: &AppState) -> AppResult<()>;
+}
+
+/// Queues `job` to run once `delay` has passed (`Duration::ZERO` for as soon as a worker is
+/// free), returning its id.
+pub async fn enqueue<J: Job>(conn: &mut PgConnection, job: &J, delay: Duration) -> AppResult<i64> {
+    let payload = serde_json::to_string(job).map_err(|e| AppError::internal(e.to_string()))?;
+    Ok(sqlx::query_scalar!(
+        r#"
+        INSERT INTO jobs (kind, payload, max_attempts, run_at)
+        VALUES ($1, $2::text::jsonb, $3, now() + make_interval(secs => $4))
+        RETURNING id
+        "#,
+        J::KIND,
+        payload,
+        J::MAX_ATTEMPTS,
+        delay.as_secs_f64(),
+    )
+    .fetch_one(conn)
+    .await?)
+}
+
+type Runner = Box<dyn Fn(&str, AppState) -> BoxFuture<'static, AppResult<()>> + Send + Sync>;
+
+/// The job types workers know how to run, by [`Job::KIND`].
+#[derive(Default, Clone)]
+pub struct Registry {
+    runners: HashMap<&'static str, Arc<Runner>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<J: Job>(mut self) -> Self {
+        let runner: Runner = Box::new(|payload, state| {
+            let job = serde_json::from_str::<J>(payload);
+            Box::pin(async move {
+                let job = job.map_err(|e| AppError::internal(format!("invalid payload: {e}")))?;
+                job.run(&state).await
+            })
+        });
+        if self.runners.insert(J::KIND, Arc::new(runner)).is_some() {
+            panic!("job kind `{}` is registered twice", J::KIND);
+        }
+        self
+    }
+
+    fn runner(&self, kind: &str) -> Option<Arc<Runner>> {
+        self.runners.get(kind).cloned()
+    }
+}
+
+/// Delay before retrying a job that failed its `attempt`-th run.
+fn backoff(attempt: i32, base: Duration, max: Duration) -> Duration {
+    let exponent = attempt.saturating_sub(1).clamp(0, 30) as u32;
+    base.saturating_mul(2u32.saturating_pow(exponent)).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::backoff;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let base = Duration::from_secs(10);
+        let max = Duration::from_secs(60);
+        assert_eq!(backoff(1, base, max), Duration::from_secs(10));
+        assert_eq!(backoff(2, base, max), Duration::from_secs(20));
+        assert_eq!(backoff(3, base, max), Duration::from_secs(40));
+        assert_eq!(backoff(4, base, max), max);
+        assert_eq!(backoff(i32::MAX, base, max), max);
+    }
+}
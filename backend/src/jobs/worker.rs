@@ -0,0 +1,200 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use super::{Registry, backoff};
+use crate::AppResult;
+use crate::config::JobsConfig;
+use crate::state::AppState;
+
+/// The worker tasks processing the `jobs` table.
+pub struct Workers {
+    stop: watch::Sender<bool>,
+    tasks: JoinSet<()>,
+    shutdown_timeout: Duration,
+}
+
+struct Claimed {
+    id: i64,
+    kind: String,
+    payload: String,
+    attempts: i32,
+    max_attempts: i32,
+}
+
+impl Workers {
+    /// Spawns `config.workers` workers running the jobs known to `registry`.
+    pub fn spawn(config: &JobsConfig, state: AppState, registry: Registry) -> Self {
+        let (stop, stopped) = watch::channel(false);
+        let registry = Arc::new(registry);
+        let mut tasks = JoinSet::new();
+        for worker in 0..config.workers {
+            tasks.spawn(work(
+                worker,
+                config.clone(),
+                state.clone(),
+                registry.clone(),
+                stopped.clone(),
+            ));
+        }
+        if config.workers > 0 {
+            tracing::info!(workers = config.workers, "job workers started");
+        }
+        Self {
+            stop,
+            tasks,
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout),
+        }
+    }
+
+    /// Tells the workers to stop claiming jobs. The ones they are running carry on.
+    pub fn stopper(&self) -> watch::Sender<bool> {
+        self.stop.clone()
+    }
+
+    /// Stops the workers and waits up to `shutdown_timeout` for their running jobs.
+    pub async fn drain(mut self) {
+        self.stop.send_replace(true);
+        let finished = tokio::time::timeout(self.shutdown_timeout, async {
+            while self.tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if finished.is_err() {
+            tracing::warn!(
+                running = self.tasks.len(),
+                "job workers did not finish in time, their jobs will be retried after the lease"
+            );
+            self.tasks.abort_all();
+        }
+    }
+}
+
+async fn work(
+    worker: usize,
+    config: JobsConfig,
+    state: AppState,
+    registry: Arc<Registry>,
+    mut stopped: watch::Receiver<bool>,
+) {
+    let pool = state.db.pool().clone();
+    let poll_interval = Duration::from_millis(config.poll_interval);
+    while !*stopped.borrow() {
+        match claim(&pool, config.lease).await {
+            Ok(Some(job)) => {
+                run(job, &config, &state, &registry).await;
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!(worker, error = ?e, "failed to claim a job"),
+        }
+        tokio::select! {
+            changed = stopped.changed() => if changed.is_err() { break },
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+    }
+}
+
+/// Takes the oldest due job, or one whose worker let its lease expire.
+async fn claim(pool: &PgPool, lease: u64) -> AppResult<Option<Claimed>> {
+    Ok(sqlx::query_as!(
+        Claimed,
+        r#"
+        UPDATE jobs
+        SET status = 'running', locked_at = now(), attempts = attempts + 1
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE (status = 'pending' AND run_at <= now())
+               OR (status = 'running' AND locked_at <= now() - make_interval(secs => $1))
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, kind, payload::text as "payload!", attempts, max_attempts
+        "#,
+        lease as f64,
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+async fn run(job: Claimed, config: &JobsConfig, state: &AppState, registry: &Registry) {
+    let pool = state.db.pool();
+    let result = if job.attempts > job.max_attempts {
+        Err("lease expired on the last attempt".to_owned())
+    } else if let Some(runner) = registry.runner(&job.kind) {
+        // A task of its own turns a panicking job into a failed attempt.
+        let task = tokio::spawn(runner(&job.payload, state.clone()));
+        match task.await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(e) => Err(format!("job panicked: {e}")),
+        }
+    } else {
+        Err(format!("no job registered for kind `{}`", job.kind))
+    };
+
+    let recorded = match result {
+        Ok(()) => complete(pool, &job).await,
+        Err(error) => {
+            let retry_in = backoff(
+                job.attempts,
+                Duration::from_secs(config.backoff),
+                Duration::from_secs(config.max_backoff),
+            );
+            let dead = job.attempts >= job.max_attempts;
+            tracing::warn!(
+                job = job.id,
+                kind = job.kind,
+                attempt = job.attempts,
+                dead,
+                error,
+                "job failed"
+            );
+            fail(pool, &job, &error, retry_in).await
+        }
+    };
+    if let Err(e) = recorded {
+        tracing::error!(job = job.id, error = ?e, "failed to record the outcome of a job");
+    }
+}
+
+// Both updates check `attempts` so a worker whose lease was taken over cannot overwrite the
+// outcome of the newer attempt.
+
+async fn complete(pool: &PgPool, job: &Claimed) -> AppResult<()> {
+    sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = 'done', locked_at = NULL, last_error = NULL, finished_at = now()
+        WHERE id = $1 AND attempts = $2
+        "#,
+        job.id,
+        job.attempts,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn fail(pool: &PgPool, job: &Claimed, error: &str, retry_in: Duration) -> AppResult<()> {
+    sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = CASE WHEN attempts >= max_attempts THEN 'dead' ELSE 'pending' END,
+            finished_at = CASE WHEN attempts >= max_attempts THEN now() END,
+            run_at = now() + make_interval(secs => $3),
+            locked_at = NULL,
+            last_error = $4
+        WHERE id = $1 AND attempts = $2
+        "#,
+        job.id,
+        job.attempts,
+        retry_in.as_secs_f64(),
+        error,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
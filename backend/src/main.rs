@@ -3,15 +3,30 @@ use salvo::conn::rustls::{Keycert, RustlsConfig};
 use salvo::prelude::*;
 use salvo::server::ServerHandle;
 use serde::Serialize;
+use std::process::ExitCode;
 use tokio::signal;
 use tracing::info;
 
+mod audit;
+mod cli;
 mod config;
 mod db;
+mod embeddings;
 mod hoops;
+mod idempotency;
+mod invitations;
+mod jobs;
 mod models;
+mod places;
+mod rate_limit;
+mod repositories;
 mod routers;
+mod state;
+mod storage;
+mod tasks;
 mod utils;
+mod webhooks;
+mod workspaces;
 
 mod error;
 pub use error::AppError;
@@ -30,18 +45,42 @@ pub fn empty_ok() -> JsonResult<Empty> {
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     rustls::crypto::ring::default_provider().install_default().expect("Failed to install rustls crypto provider");
-    crate::config::init();
-    let config = crate::config::get();
-    crate::db::init(&config.db).await;
-
+    let config = crate::config::load();
     let _guard = config.log.guard();
     tracing::info!("log level: {}", &config.log.filter_level);
 
-    let service = Service::new(routers::root())
+    let db = match crate::db::Database::connect(&config.db).await {
+        Ok(db) => db,
+        Err(e) => {
+            tracing::error!(error = %e, "database connection failed");
+            eprintln!("❌ database connection failed: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&args, &config, db.pool()).await;
+    }
+    if let Err(e) = crate::db::migrate::prepare(db.pool(), &config).await {
+        tracing::error!(error = %e, "database is not ready");
+        eprintln!("❌ {e}");
+        return ExitCode::FAILURE;
+    }
+
+    // Fails now rather than on the first search.
+    embeddings::Metric::from_config(&config.documents);
+    let router = routers::root(&config);
+    let cors = hoops::cors_hoop(&config.cors);
+    let state = state::AppState::new(config, db);
+    let config = state.config.clone();
+    let workers = jobs::Workers::spawn(&config.jobs, state.clone(), tasks::jobs());
+    tasks::scheduler().spawn(&config.cron, state.clone());
+    let service = Service::new(router)
+        .hoop(affix_state::inject(state))
         .catcher(Catcher::default().hoop(hoops::error_404))
-        .hoop(hoops::cors_hoop());
+        .hoop(cors);
     println!("🔄 listen on {}", &config.listen_addr);
     println!("Debug: TLS config is {:?}", config.tls); // Add this
     //Acme support, automatically get TLS certificate from Let's Encrypt. For example, see https://github.com/salvo-rs/salvo/blob/main/examples/acme-http01-quinn/src/main.rs
@@ -61,9 +100,9 @@ async fn main() {
             ).key(
                 std::fs::read(tls.key.clone()).expect("key file not found")
             ));
-        let acceptor = QuinnListener::new(config.clone().build_quinn_config().unwrap(),listen_addr).join(TcpListener::new(listen_addr).rustls(config)).bind().await;
+        let acceptor = QuinnListener::new(config.clone().build_quinn_config().unwrap(),listen_addr.clone()).join(TcpListener::new(listen_addr.clone()).rustls(config)).bind().await;
         let server = Server::new(acceptor);
-        tokio::spawn(shutdown_signal(server.handle()));
+        tokio::spawn(shutdown_signal(server.handle(), workers.stopper()));
         server.serve(service).await;
     } else {
         println!(
@@ -74,14 +113,16 @@ async fn main() {
             "🔑 Login Page: http://{}/login",
             config.listen_addr.replace("0.0.0.0", "127.0.0.1")
         );
-        let acceptor =TcpListener::new(&config.listen_addr).bind().await;
+        let acceptor =TcpListener::new(config.listen_addr.clone()).bind().await;
         let server = Server::new(acceptor);
-        tokio::spawn(shutdown_signal(server.handle()));
+        tokio::spawn(shutdown_signal(server.handle(), workers.stopper()));
         server.serve(service).await;
     }
+    workers.drain().await;
+    ExitCode::SUCCESS
 }
 
-async fn shutdown_signal(handle: ServerHandle) {
+async fn shutdown_signal(handle: ServerHandle, workers: tokio::sync::watch::Sender<bool>) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -103,6 +144,7 @@ async fn shutdown_signal(handle: ServerHandle) {
         _ = ctrl_c => info!("ctrl_c signal received"),
         _ = terminate => info!("terminate signal received"),
     }
+    workers.send_replace(true);
     handle.stop_graceful(std::time::Duration::from_secs(60));
 }
 
@@ -111,17 +153,17 @@ mod tests {
     use salvo::prelude::*;
     use salvo::test::{ResponseExt, TestClient};
 
-    use crate::config;
+    use crate::state::AppState;
 
     #[tokio::test]
     async fn test_hello_world() {
-        config::init();
-
-        let service = Service::new(crate::routers::root());
+        let state = AppState::for_tests();
+        let service = Service::new(crate::routers::root(&state.config))
+            .hoop(affix_state::inject(state.clone()));
 
         let content = TestClient::get(format!(
             "http://{}",
-            config::get().listen_addr.replace("0.0.0.0", "127.0.0.1")
+            state.config.listen_addr.replace("0.0.0.0", "127.0.0.1")
         ))
         .send(&service)
         .await
@@ -2,6 +2,8 @@ use salvo::prelude::*;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::*;
 
+use crate::db::notify::Channel;
+
 #[derive(FromRow, Serialize, Deserialize, Extractible, Debug)]
 #[salvo(extract(default_source(from = "body", parse = "json")))]
 pub struct User {
@@ -11,8 +13,61 @@ pub struct User {
     pub password: String,
 }
 
-#[derive(FromRow, Serialize, ToSchema, Debug)]
+#[derive(FromRow, Serialize, ToSchema, Clone, Debug)]
 pub struct SafeUser {
     pub id: String,
     pub username: String,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserListItem {
+    #[serde(flatten)]
+    pub user: SafeUser,
+    /// Relevance of a `q` match, higher is better.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rank: Option<f32>,
+    /// HTML-escaped username, email and display name with the matched terms wrapped in `<mark>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+impl From<SafeUser> for UserListItem {
+    fn from(user: SafeUser) -> Self {
+        Self {
+            user,
+            rank: None,
+            snippet: None,
+        }
+    }
+}
+
+/// `user_changes` notifications, sent by a trigger on `users` when a change commits.
+pub struct UserEvents;
+
+impl Channel for UserEvents {
+    const NAME: &'static str = "user_changes";
+    type Event = UserChange;
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct UserChange {
+    pub op: UserChangeOp,
+    pub id: String,
+    /// `version` of the user after the change.
+    pub version: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UserChangeOp {
+    Created,
+    Updated,
+    /// Soft-deleted, still restorable.
+    Deleted,
+    Restored,
+    /// Removed for good.
+    Purged,
 }
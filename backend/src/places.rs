@@ -0,0 +1,242 @@
+//! Named locations, and radius, bounding-box and nearest-first queries over them, on PostGIS.
+//!
+//! Points are WGS 84 (SRID 4326) longitude/latitude, read and written as GeoJSON. Distances are
+//! computed on the geography type, so radii and results are in metres. As for documents, the
+//! queries are checked at runtime: without PostGIS the `places` table is missing and every call
+//! answers 503.
+
+use chrono::{DateTime, Utc};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{FromRow, PgConnection};
+
+use crate::{AppError, AppResult, db};
+
+/// A GeoJSON geometry. Only points are stored.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point {
+        /// `[longitude, latitude]`, in that order.
+        coordinates: [f64; 2],
+    },
+}
+
+impl Geometry {
+    pub fn point(lng: f64, lat: f64) -> Self {
+        Self::Point {
+            coordinates: [lng, lat],
+        }
+    }
+
+    pub fn lng_lat(self) -> (f64, f64) {
+        let Self::Point {
+            coordinates: [lng, lat],
+        } = self;
+        (lng, lat)
+    }
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, Debug)]
+pub enum FeatureType {
+    Feature,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, Debug)]
+pub enum FeatureCollectionType {
+    FeatureCollection,
+}
+
+/// A place as a GeoJSON feature: `properties` holds its `name` next to the stored properties.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Place {
+    #[serde(rename = "type")]
+    pub kind: FeatureType,
+    pub id: String,
+    pub geometry: Geometry,
+    pub properties: Map<String, Value>,
+    /// Metres from the searched point, for nearby searches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct FeatureCollection {
+    #[serde(rename = "type")]
+    pub kind: FeatureCollectionType,
+    pub features: Vec<Place>,
+}
+
+impl From<Vec<Place>> for FeatureCollection {
+    fn from(features: Vec<Place>) -> Self {
+        Self {
+            kind: FeatureCollectionType::FeatureCollection,
+            features,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct PlaceRow {
+    id: String,
+    name: String,
+    properties: Value,
+    lng: f64,
+    lat: f64,
+    distance: Option<f64>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<PlaceRow> for Place {
+    fn from(row: PlaceRow) -> Self {
+        let mut properties = match row.properties {
+            Value::Object(properties) => properties,
+            _ => Map::new(),
+        };
+        properties.insert("name".into(), Value::String(row.name));
+        Self {
+            kind: FeatureType::Feature,
+            id: row.id,
+            geometry: Geometry::point(row.lng, row.lat),
+            properties,
+            distance: row.distance,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// Edges of a map viewport, in degrees. `west` greater than `east` crosses the antimeridian.
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+/// Reports a missing `places` table or PostGIS function as the service being unavailable.
+fn unavailable(e: sqlx::Error) -> AppError {
+    db::extension_unavailable(e, "Places", "PostGIS")
+}
+
+/// Creates the place `id` or replaces its name, properties and location.
+pub async fn upsert(
+    conn: &mut PgConnection,
+    id: &str,
+    name: &str,
+    properties: &Value,
+    geometry: Geometry,
+) -> AppResult<Place> {
+    let (lng, lat) = geometry.lng_lat();
+    let row = sqlx::query_as::<_, PlaceRow>(
+        r#"
+        INSERT INTO places (id, name, properties, location)
+        VALUES ($1, $2, $3, ST_SetSRID(ST_MakePoint($4, $5), 4326))
+        ON CONFLICT (workspace_id, id) DO UPDATE
+        SET name = EXCLUDED.name, properties = EXCLUDED.properties,
+            location = EXCLUDED.location, updated_at = now()
+        RETURNING id, name, properties, ST_X(location) as lng, ST_Y(location) as lat,
+                  NULL::float8 as distance, created_at, updated_at
+        "#,
+    )
+    .bind(id)
+    .bind(name)
+    .bind(properties)
+    .bind(lng)
+    .bind(lat)
+    .fetch_one(conn)
+    .await
+    .map_err(unavailable)?;
+    Ok(row.into())
+}
+
+pub async fn find(conn: &mut PgConnection, id: &str) -> AppResult<Option<Place>> {
+    let row = sqlx::query_as::<_, PlaceRow>(
+        r#"
+        SELECT id, name, properties, ST_X(location) as lng, ST_Y(location) as lat,
+               NULL::float8 as distance, created_at, updated_at
+        FROM places WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+    .map_err(unavailable)?;
+    Ok(row.map(Place::from))
+}
+
+pub async fn delete(conn: &mut PgConnection, id: &str) -> AppResult<bool> {
+    let deleted = sqlx::query("DELETE FROM places WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await
+        .map_err(unavailable)?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+/// The `k` places nearest to `center`, closest first, only those within `radius` metres when
+/// it is set.
+pub async fn nearby(
+    conn: &mut PgConnection,
+    center: Geometry,
+    radius: Option<f64>,
+    k: i64,
+) -> AppResult<Vec<Place>> {
+    let (lng, lat) = center.lng_lat();
+    let rows = sqlx::query_as::<_, PlaceRow>(
+        r#"
+        WITH center AS (SELECT ST_SetSRID(ST_MakePoint($1, $2), 4326)::geography as point)
+        SELECT id, name, properties, ST_X(location) as lng, ST_Y(location) as lat,
+               ST_Distance(location::geography, center.point) as distance, created_at, updated_at
+        FROM places, center
+        WHERE $3::float8 IS NULL OR ST_DWithin(location::geography, center.point, $3)
+        ORDER BY location::geography <-> center.point
+        LIMIT $4
+        "#,
+    )
+    .bind(lng)
+    .bind(lat)
+    .bind(radius)
+    .bind(k)
+    .fetch_all(conn)
+    .await
+    .map_err(unavailable)?;
+    Ok(rows.into_iter().map(Place::from).collect())
+}
+
+/// Up to `limit` places inside `bbox`, by id.
+pub async fn within(
+    conn: &mut PgConnection,
+    bbox: BoundingBox,
+    limit: i64,
+) -> AppResult<Vec<Place>> {
+    // Across the antimeridian the box is split in two, each side served by the index.
+    let rows = sqlx::query_as::<_, PlaceRow>(
+        r#"
+        SELECT id, name, properties, ST_X(location) as lng, ST_Y(location) as lat,
+               NULL::float8 as distance, created_at, updated_at
+        FROM places
+        WHERE location && ST_MakeEnvelope($1, $2, CASE WHEN $1 <= $3 THEN $3 ELSE 180 END, $4, 4326)
+           OR ($1 > $3 AND location && ST_MakeEnvelope(-180, $2, $3, $4, 4326))
+        ORDER BY id
+        LIMIT $5
+        "#,
+    )
+    .bind(bbox.west)
+    .bind(bbox.south)
+    .bind(bbox.east)
+    .bind(bbox.north)
+    .bind(limit)
+    .fetch_all(conn)
+    .await
+    .map_err(unavailable)?;
+    Ok(rows.into_iter().map(Place::from).collect())
+}
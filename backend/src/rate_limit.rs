@@ -0,0 +1,341 @@
+//! Request quotas of route groups.
+//!
+//! The `rate_limit` hoop names a group whose [`Quota`] comes from `[rate_limit.groups]`, and
+//! asks the [`RateLimitStore`] of the server whether the client still has one request left.
+//! Counters are kept in process memory or, so that limits hold across instances, in the
+//! `rate_limits` table.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sqlx::PgPool;
+
+use crate::AppResult;
+use crate::config::rate_limit_config::{STORE_MEMORY, STORE_POSTGRES};
+use crate::config::{QuotaConfig, RateLimitConfig};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+    /// Refills `limit` tokens evenly over `period`, so bursts of up to `limit` requests pass.
+    TokenBucket,
+    /// Counts the requests of the current window plus the share of the previous window that
+    /// the sliding window still covers.
+    SlidingWindow,
+}
+
+/// What the requests of a group are counted by.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyBy {
+    Ip,
+    User,
+    ApiKey,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub algorithm: Algorithm,
+    pub key_by: KeyBy,
+    pub limit: u32,
+    /// Seconds.
+    pub period: f64,
+}
+
+/// State of one client's quota. See the columns of `rate_limits`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Counter {
+    pub count: f64,
+    pub previous: f64,
+    pub since: f64,
+}
+
+/// Outcome of one request, with the values of the `RateLimit-*` headers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the quota is whole again, or until the next request is allowed when
+    /// this one was not.
+    pub reset: u64,
+}
+
+impl Quota {
+    /// The quota of one group. Panics on an unknown algorithm or key, or an empty quota.
+    pub fn from_config(group: &str, config: &QuotaConfig) -> Self {
+        let algorithm = match config.algorithm.as_str() {
+            "token_bucket" => Algorithm::TokenBucket,
+            "sliding_window" => Algorithm::SlidingWindow,
+            other => panic!("Unknown rate limit algorithm `{other}` for group `{group}`"),
+        };
+        let key_by = match config.key.as_str() {
+            "ip" => KeyBy::Ip,
+            "user" => KeyBy::User,
+            "api_key" => KeyBy::ApiKey,
+            other => panic!("Unknown rate limit key `{other}` for group `{group}`"),
+        };
+        if config.limit == 0 || config.period == 0 {
+            panic!("Rate limit group `{group}` needs a positive limit and period");
+        }
+        Self {
+            algorithm,
+            key_by,
+            limit: config.limit,
+            period: config.period as f64,
+        }
+    }
+
+    /// The counter of a client seen for the first time at `now`.
+    pub fn fresh(&self, now: f64) -> Counter {
+        match self.algorithm {
+            Algorithm::TokenBucket => Counter {
+                count: self.limit as f64,
+                previous: 0.0,
+                since: now,
+            },
+            Algorithm::SlidingWindow => Counter {
+                count: 0.0,
+                previous: 0.0,
+                since: self.window_start(now),
+            },
+        }
+    }
+
+    fn window_start(&self, now: f64) -> f64 {
+        (now / self.period).floor() * self.period
+    }
+
+    /// Counts one request at `now` against `counter`, returning the updated counter.
+    pub fn check(&self, mut counter: Counter, now: f64) -> (Counter, Decision) {
+        let limit = self.limit as f64;
+        let (allowed, remaining, reset) = match self.algorithm {
+            Algorithm::TokenBucket => {
+                let rate = limit / self.period;
+                counter.count = (counter.count + (now - counter.since).max(0.0) * rate).min(limit);
+                counter.since = now;
+                let allowed = counter.count >= 1.0;
+                if allowed {
+                    counter.count -= 1.0;
+                }
+                let reset = if allowed {
+                    (limit - counter.count) / rate
+                } else {
+                    (1.0 - counter.count) / rate
+                };
+                (allowed, counter.count, reset)
+            }
+            Algorithm::SlidingWindow => {
+                let start = self.window_start(now);
+                if start > counter.since {
+                    let windows = ((start - counter.since) / self.period).round();
+                    counter.previous = if windows == 1.0 { counter.count } else { 0.0 };
+                    counter.count = 0.0;
+                    counter.since = start;
+                }
+                let elapsed = now - start;
+                let used = counter.previous * (1.0 - elapsed / self.period) + counter.count;
+                let allowed = used + 1.0 <= limit;
+                if allowed {
+                    counter.count += 1.0;
+                }
+                let remaining = limit - used - if allowed { 1.0 } else { 0.0 };
+                (allowed, remaining, self.period - elapsed)
+            }
+        };
+        let decision = Decision {
+            allowed,
+            limit: self.limit,
+            remaining: remaining.max(0.0).floor() as u32,
+            reset: reset.max(0.0).ceil() as u64,
+        };
+        (counter, decision)
+    }
+}
+
+/// Seconds between two sweeps of the expired counters of the memory store.
+const MEMORY_PRUNE_INTERVAL: f64 = 60.0;
+
+/// Where the counters of all groups are kept. Cheap to clone.
+#[derive(Clone)]
+pub enum RateLimitStore {
+    Memory(Arc<Mutex<MemoryCounters>>),
+    Postgres,
+}
+
+/// Counters of one instance. Each instance drops its own expired ones while counting, as
+/// the scheduled `prune_rate_limits` task only runs on one of them.
+#[derive(Default)]
+pub struct MemoryCounters {
+    /// Counters by key, with the Unix time after which they can be dropped.
+    counters: HashMap<String, (Counter, f64)>,
+    /// Unix time of the next sweep.
+    next_prune: f64,
+}
+
+impl MemoryCounters {
+    fn prune(&mut self, now: f64) {
+        self.counters.retain(|_, (_, expires)| *expires > now);
+        self.next_prune = now + MEMORY_PRUNE_INTERVAL;
+    }
+}
+
+impl RateLimitStore {
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        match config.store.as_str() {
+            STORE_MEMORY => Self::Memory(Arc::default()),
+            STORE_POSTGRES => Self::Postgres,
+            other => panic!("Unknown rate limit store `{other}`"),
+        }
+    }
+
+    /// Counts one request of the client `key` against `quota`.
+    pub async fn check(&self, pool: &PgPool, key: &str, quota: &Quota) -> AppResult<Decision> {
+        let now = unix_now();
+        let expires = now + 2.0 * quota.period;
+        match self {
+            Self::Memory(memory) => {
+                let mut memory = memory.lock().expect("rate limit counters poisoned");
+                if now >= memory.next_prune {
+                    memory.prune(now);
+                }
+                let counter = memory
+                    .counters
+                    .get(key)
+                    .map_or_else(|| quota.fresh(now), |(counter, _)| *counter);
+                let (counter, decision) = quota.check(counter, now);
+                memory.counters.insert(key.to_owned(), (counter, expires));
+                Ok(decision)
+            }
+            Self::Postgres => {
+                let mut tx = pool.begin().await?;
+                let fresh = quota.fresh(now);
+                // The no-op update locks the row, so concurrent requests count one at a time.
+                let counter = sqlx::query_as!(
+                    Counter,
+                    r#"
+                    INSERT INTO rate_limits (key, count, previous, since, expires_at)
+                    VALUES ($1, $2, $3, $4, to_timestamp($5))
+                    ON CONFLICT (key) DO UPDATE SET key = EXCLUDED.key
+                    RETURNING count, previous, since
+                    "#,
+                    key,
+                    fresh.count,
+                    fresh.previous,
+                    fresh.since,
+                    expires,
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                let (counter, decision) = quota.check(counter, now);
+                sqlx::query!(
+                    r#"
+                    UPDATE rate_limits
+                    SET count = $2, previous = $3, since = $4, expires_at = to_timestamp($5)
+                    WHERE key = $1
+                    "#,
+                    key,
+                    counter.count,
+                    counter.previous,
+                    counter.since,
+                    expires,
+                )
+                .execute(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                Ok(decision)
+            }
+        }
+    }
+
+    /// Drops the rows of `rate_limits` that are back to a full quota, returning how many. The
+    /// memory store prunes itself in `check`.
+    pub async fn prune(&self, pool: &PgPool) -> AppResult<u64> {
+        match self {
+            Self::Memory(_) => Ok(0),
+            Self::Postgres => {
+                let pruned = sqlx::query!("DELETE FROM rate_limits WHERE expires_at <= now()")
+                    .execute(pool)
+                    .await?
+                    .rows_affected();
+                Ok(pruned)
+            }
+        }
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, KeyBy, MEMORY_PRUNE_INTERVAL, MemoryCounters, Quota};
+
+    fn quota(algorithm: Algorithm) -> Quota {
+        Quota {
+            algorithm,
+            key_by: KeyBy::Ip,
+            limit: 3,
+            period: 60.0,
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let quota = quota(Algorithm::TokenBucket);
+        let mut counter = quota.fresh(1000.0);
+        for remaining in [2, 1, 0] {
+            let (next, decision) = quota.check(counter, 1000.0);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+            counter = next;
+        }
+        let (counter, decision) = quota.check(counter, 1000.0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.reset, 20);
+        // One token is back after a third of the period.
+        let (_, decision) = quota.check(counter, 1020.0);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let quota = quota(Algorithm::SlidingWindow);
+        let mut counter = quota.fresh(1200.0);
+        for _ in 0..3 {
+            let (next, decision) = quota.check(counter, 1230.0);
+            assert!(decision.allowed);
+            counter = next;
+        }
+        let (counter, decision) = quota.check(counter, 1230.0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.reset, 30);
+        // 40s into the next window a third of the previous 3 requests still counts.
+        let (counter, decision) = quota.check(counter, 1300.0);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+        let (counter, _) = quota.check(counter, 1300.0);
+        assert!(!quota.check(counter, 1300.0).1.allowed);
+        // Two windows later nothing is left of them.
+        assert_eq!(quota.check(counter, 1450.0).1.remaining, 2);
+    }
+
+    #[test]
+    fn test_memory_counters_prune() {
+        let quota = quota(Algorithm::TokenBucket);
+        let mut memory = MemoryCounters::default();
+        memory
+            .counters
+            .insert("old".into(), (quota.fresh(0.0), 100.0));
+        memory
+            .counters
+            .insert("new".into(), (quota.fresh(0.0), 200.0));
+        memory.prune(150.0);
+        assert!(memory.counters.contains_key("new"));
+        assert!(!memory.counters.contains_key("old"));
+        assert_eq!(memory.next_prune, 150.0 + MEMORY_PRUNE_INTERVAL);
+    }
+}
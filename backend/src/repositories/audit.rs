@@ -0,0 +1,255 @@
+use chrono::{DateTime, SubsecRound, Utc};
+use salvo::async_trait;
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+
+use crate::AppResult;
+
+/// An event to append. Its id, time and hashes are set by [`AuditEvent::chain`].
+#[derive(Clone, Default, Debug)]
+pub struct NewAuditEvent {
+    pub actor_id: Option<String>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub diff: Option<Value>,
+    pub details: Option<Value>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl NewAuditEvent {
+    pub fn new(action: &str) -> Self {
+        Self {
+            action: action.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    pub fn actor(mut self, actor_id: impl Into<String>) -> Self {
+        self.actor_id = Some(actor_id.into());
+        self
+    }
+
+    pub fn target(mut self, target_type: &str, target_id: impl Into<String>) -> Self {
+        self.target_type = Some(target_type.to_owned());
+        self.target_id = Some(target_id.into());
+        self
+    }
+
+    pub fn diff(mut self, diff: Option<Value>) -> Self {
+        self.diff = diff;
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+#[derive(Serialize, ToSchema, Clone, PartialEq, Debug)]
+pub struct AuditEvent {
+    pub id: String,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub occurred_at: DateTime<Utc>,
+    /// User who acted, none for anonymous requests such as failed logins.
+    pub actor_id: Option<String>,
+    /// What happened, like `user.update` or `auth.login_failed`.
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    /// `{"field": {"before": ..., "after": ...}}` for the fields that changed.
+    pub diff: Option<Value>,
+    pub details: Option<Value>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    /// Hash of the previous chained event, with `[audit] hash_chain` on.
+    pub prev_hash: Option<String>,
+    pub hash: Option<String>,
+}
+
+/// What the hash of an event covers: everything but the hash itself, in a fixed order.
+#[derive(Serialize)]
+struct Chained<'a> {
+    id: &'a str,
+    occurred_at: &'a DateTime<Utc>,
+    actor_id: &'a Option<String>,
+    action: &'a str,
+    target_type: &'a Option<String>,
+    target_id: &'a Option<String>,
+    diff: &'a Option<Value>,
+    details: &'a Option<Value>,
+    ip: &'a Option<String>,
+    user_agent: &'a Option<String>,
+    request_id: &'a Option<String>,
+    prev_hash: &'a Option<String>,
+}
+
+impl AuditEvent {
+    /// `event` as it is stored, hashed after `prev_hash` when `chained`. The time is cut to the
+    /// microseconds Postgres keeps, so the hash can be recomputed from the stored row.
+    pub fn chain(event: NewAuditEvent, chained: bool, prev_hash: Option<String>) -> Self {
+        let mut event = Self {
+            id: Ulid::new().to_string(),
+            occurred_at: Utc::now().trunc_subsecs(6),
+            actor_id: event.actor_id,
+            action: event.action,
+            target_type: event.target_type,
+            target_id: event.target_id,
+            diff: event.diff,
+            details: event.details,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            request_id: event.request_id,
+            prev_hash: None,
+            hash: None,
+        };
+        if chained {
+            event.prev_hash = prev_hash;
+            event.hash = Some(event.content_hash());
+        }
+        event
+    }
+
+    fn content_hash(&self) -> String {
+        let content = serde_json::to_vec(&Chained {
+            id: &self.id,
+            occurred_at: &self.occurred_at,
+            actor_id: &self.actor_id,
+            action: &self.action,
+            target_type: &self.target_type,
+            target_id: &self.target_id,
+            diff: &self.diff,
+            details: &self.details,
+            ip: &self.ip,
+            user_agent: &self.user_agent,
+            request_id: &self.request_id,
+            prev_hash: &self.prev_hash,
+        })
+        .expect("audit events serialize");
+        hex::encode(Sha256::digest(content))
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct AuditFilter {
+    pub actor_id: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    pub(super) fn matches(&self, event: &AuditEvent) -> bool {
+        fn eq(expected: &Option<String>, actual: Option<&str>) -> bool {
+            expected
+                .as_deref()
+                .is_none_or(|expected| actual == Some(expected))
+        }
+        eq(&self.actor_id, event.actor_id.as_deref())
+            && eq(&self.action, Some(&event.action))
+            && eq(&self.target_type, event.target_type.as_deref())
+            && eq(&self.target_id, event.target_id.as_deref())
+            && self.since.is_none_or(|since| event.occurred_at >= since)
+            && self.until.is_none_or(|until| event.occurred_at < until)
+    }
+}
+
+#[derive(Serialize, ToSchema, Default, Debug)]
+pub struct ChainReport {
+    /// Number of chained events checked.
+    pub checked: i64,
+    pub valid: bool,
+    /// First event whose hash or link to the previous one does not match.
+    pub broken_at: Option<String>,
+}
+
+/// Checks chained events one by one, oldest first.
+#[derive(Default, Debug)]
+pub(super) struct ChainVerifier {
+    last_hash: Option<String>,
+    report: ChainReport,
+}
+
+impl ChainVerifier {
+    /// Checks `event`, returning `false` once the chain is broken.
+    pub fn push(&mut self, event: &AuditEvent) -> bool {
+        self.report.checked += 1;
+        let intact = event.prev_hash == self.last_hash
+            && event.hash.as_deref() == Some(event.content_hash().as_str());
+        if !intact {
+            self.report.broken_at = Some(event.id.clone());
+            return false;
+        }
+        self.last_hash = event.hash.clone();
+        true
+    }
+
+    pub fn finish(mut self) -> ChainReport {
+        self.report.valid = self.report.broken_at.is_none();
+        self.report
+    }
+}
+
+/// Append-only storage of audit events.
+#[async_trait]
+pub trait AuditRepository: Send {
+    /// Appends `event` in the request transaction, so it is kept only if the request succeeds.
+    async fn record(&mut self, event: NewAuditEvent) -> AppResult<AuditEvent>;
+    /// Appends `event` at once, whatever becomes of the request. For failures, whose request
+    /// is rolled back.
+    async fn record_now(&mut self, event: NewAuditEvent) -> AppResult<AuditEvent>;
+    /// One page of the events matching `filter`, newest first, and their number.
+    async fn list(
+        &mut self,
+        filter: &AuditFilter,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<(Vec<AuditEvent>, i64)>;
+    /// Recomputes the hash chain from its first event.
+    async fn verify(&mut self) -> AppResult<ChainReport>;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{AuditEvent, ChainVerifier, NewAuditEvent};
+
+    #[test]
+    fn test_chain_detects_tampering() {
+        let first = AuditEvent::chain(NewAuditEvent::new("user.create"), true, None);
+        let second = AuditEvent::chain(
+            NewAuditEvent::new("user.update")
+                .target("user", "01")
+                .diff(Some(
+                    json!({"username": {"before": "alice", "after": "alice2"}}),
+                )),
+            true,
+            first.hash.clone(),
+        );
+
+        let mut verifier = ChainVerifier::default();
+        assert!(verifier.push(&first) && verifier.push(&second));
+        assert!(verifier.finish().valid);
+
+        let mut rewritten = second.clone();
+        rewritten.target_id = Some("02".into());
+        let mut verifier = ChainVerifier::default();
+        verifier.push(&first);
+        assert!(!verifier.push(&rewritten));
+        assert_eq!(verifier.finish().broken_at, Some(second.id.clone()));
+
+        // Removing an event breaks the link of the next one.
+        let mut verifier = ChainVerifier::default();
+        assert!(!verifier.push(&second));
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use futures_util::StreamExt;
+use futures_util::stream::{self, BoxStream};
+use salvo::async_trait;
+
+use super::audit::{
+    AuditEvent, AuditFilter, AuditRepository, ChainReport, ChainVerifier, NewAuditEvent,
+};
+use super::user::{
+    NewUser, UpdateOutcome, UserChanges, UserFilter, UserRepository, VersionedUser, snippet,
+};
+use crate::AppResult;
+use crate::models::{SafeUser, User, UserListItem};
+
+#[derive(Clone, Debug)]
+struct Row {
+    user: SafeUser,
+    password: String,
+    avatar_key: Option<String>,
+    version: i64,
+    deleted_at: Option<SystemTime>,
+}
+
+impl From<NewUser> for Row {
+    fn from(user: NewUser) -> Self {
+        Self {
+            user: SafeUser {
+                id: user.id,
+                username: user.username,
+                email: user.email,
+                display_name: user.display_name,
+                avatar_url: None,
+            },
+            password: user.password,
+            avatar_key: None,
+            version: 1,
+            deleted_at: None,
+        }
+    }
+}
+
+/// Users kept in process memory, shared by every clone. There are no transactions: a write is
+/// visible at once and survives a failed request. Search is a case-insensitive substring match
+/// where every hit ranks `1.0`.
+#[derive(Clone, Default, Debug)]
+pub struct MemoryUserRepository {
+    rows: Arc<Mutex<Vec<Row>>>,
+}
+
+impl MemoryUserRepository {
+    fn rows(&self) -> MutexGuard<'_, Vec<Row>> {
+        self.rows.lock().expect("user repository lock poisoned")
+    }
+}
+
+fn live<'a>(rows: &'a mut [Row], id: &str) -> Option<&'a mut Row> {
+    rows.iter_mut()
+        .find(|row| row.user.id == id && row.deleted_at.is_none())
+}
+
+fn contains_ignore_case(field: Option<&str>, needle: &str) -> bool {
+    field.is_some_and(|field| field.to_lowercase().contains(needle))
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn find_by_username(&mut self, username: &str) -> AppResult<Option<User>> {
+        Ok(self
+            .rows()
+            .iter()
+            .find(|row| row.user.username == username && row.deleted_at.is_none())
+            .map(|row| User {
+                id: row.user.id.clone(),
+                username: row.user.username.clone(),
+                password: row.password.clone(),
+            }))
+    }
+
+    async fn find(&mut self, id: &str) -> AppResult<Option<VersionedUser>> {
+        Ok(live(&mut self.rows(), id).map(|row| VersionedUser {
+            user: row.user.clone(),
+            version: row.version,
+        }))
+    }
+
+    async fn list(
+        &mut self,
+        filter: &UserFilter,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<(Vec<UserListItem>, i64)> {
+        let rows = self.rows();
+        let live = rows.iter().filter(|row| row.deleted_at.is_none());
+        let mut items: Vec<UserListItem> = match filter.q.as_deref().map(str::trim) {
+            Some(q) if !q.is_empty() => {
+                let needle = q.to_lowercase();
+                let mut items: Vec<_> = live
+                    .filter(|row| {
+                        let user = &row.user;
+                        contains_ignore_case(Some(&user.username), &needle)
+                            || contains_ignore_case(user.email.as_deref(), &needle)
+                            || contains_ignore_case(user.display_name.as_deref(), &needle)
+                    })
+                    .map(|row| UserListItem {
+                        snippet: Some(snippet(&row.user, q)),
+                        rank: Some(1.0),
+                        user: row.user.clone(),
+                    })
+                    .collect();
+                items.sort_by(|a, b| a.user.username.cmp(&b.user.username));
+                items
+            }
+            _ => {
+                let username = filter.username.as_deref().unwrap_or_default();
+                live.filter(|row| row.user.username.contains(username))
+                    .map(|row| UserListItem::from(row.user.clone()))
+                    .collect()
+            }
+        };
+        let total = items.len() as i64;
+        let page = items
+            .drain(..)
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect();
+        Ok((page, total))
+    }
+
+    async fn username_taken(
+        &mut self,
+        username: &str,
+        except_id: &str,
+        release_on_delete: bool,
+    ) -> AppResult<bool> {
+        Ok(self.rows().iter().any(|row| {
+            row.user.username == username
+                && row.user.id != except_id
+                && (row.deleted_at.is_none() || !release_on_delete)
+        }))
+    }
+
+    async fn usernames_taken(
+        &mut self,
+        usernames: &[String],
+        release_on_delete: bool,
+    ) -> AppResult<HashSet<String>> {
+        Ok(self
+            .rows()
+            .iter()
+            .filter(|row| {
+                usernames.contains(&row.user.username)
+                    && (row.deleted_at.is_none() || !release_on_delete)
+            })
+            .map(|row| row.user.username.clone())
+            .collect())
+    }
+
+    async fn create(&mut self, user: NewUser) -> AppResult<()> {
+        self.rows().push(user.into());
+        Ok(())
+    }
+
+    async fn create_many(&mut self, users: Vec<NewUser>) -> AppResult<()> {
+        self.rows().extend(users.into_iter().map(Row::from));
+        Ok(())
+    }
+
+    async fn update(
+        &mut self,
+        id: &str,
+        changes: UserChanges,
+        expected: Option<&[i64]>,
+    ) -> AppResult<UpdateOutcome> {
+        let mut rows = self.rows();
+        let Some(row) = live(&mut rows, id) else {
+            return Ok(UpdateOutcome::NotFound);
+        };
+        if expected.is_some_and(|expected| !expected.contains(&row.version)) {
+            return Ok(UpdateOutcome::Stale);
+        }
+        if let Some(username) = changes.username {
+            row.user.username = username;
+        }
+        if let Some(password) = changes.password {
+            row.password = password;
+        }
+        if let Some(email) = changes.email {
+            row.user.email = email;
+        }
+        if let Some(display_name) = changes.display_name {
+            row.user.display_name = display_name;
+        }
+        row.version += 1;
+        Ok(UpdateOutcome::Updated(VersionedUser {
+            user: row.user.clone(),
+            version: row.version,
+        }))
+    }
+
+    async fn delete(&mut self, id: &str) -> AppResult<bool> {
+        let mut rows = self.rows();
+        let Some(row) = live(&mut rows, id) else {
+            return Ok(false);
+        };
+        row.deleted_at = Some(SystemTime::now());
+        row.version += 1;
+        Ok(true)
+    }
+
+    async fn find_restorable(&mut self, id: &str, retention: i64) -> AppResult<Option<SafeUser>> {
+        let retention = Duration::from_secs(retention.max(0) as u64);
+        Ok(self
+            .rows()
+            .iter()
+            .find(|row| {
+                row.user.id == id
+                    && row
+                        .deleted_at
+                        .is_some_and(|at| at.elapsed().unwrap_or_default() < retention)
+            })
+            .map(|row| row.user.clone()))
+    }
+
+    async fn restore(&mut self, id: &str) -> AppResult<()> {
+        if let Some(row) = self.rows().iter_mut().find(|row| row.user.id == id) {
+            row.deleted_at = None;
+            row.version += 1;
+        }
+        Ok(())
+    }
+
+    fn export(&mut self, username: &str) -> BoxStream<'static, AppResult<SafeUser>> {
+        let mut users: Vec<SafeUser> = self
+            .rows()
+            .iter()
+            .filter(|row| row.deleted_at.is_none() && row.user.username.contains(username))
+            .map(|row| row.user.clone())
+            .collect();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        stream::iter(users.into_iter().map(Ok)).boxed()
+    }
+
+    /// Admins only come from the `users` table, so there are none here.
+    async fn is_admin(&mut self, _id: &str) -> AppResult<bool> {
+        Ok(false)
+    }
+
+    async fn set_avatar(&mut self, id: &str, key: &str, url: &str) -> AppResult<Option<SafeUser>> {
+        Ok(live(&mut self.rows(), id).map(|row| {
+            row.avatar_key = Some(key.to_owned());
+            row.user.avatar_url = Some(url.to_owned());
+            row.version += 1;
+            row.user.clone()
+        }))
+    }
+
+    async fn avatar_key(&mut self, id: &str) -> AppResult<Option<String>> {
+        Ok(live(&mut self.rows(), id).and_then(|row| row.avatar_key.clone()))
+    }
+}
+
+/// Audit events kept in process memory next to [`MemoryUserRepository`]. Every event is kept at
+/// once, even when its request then fails.
+#[derive(Clone, Default, Debug)]
+pub struct MemoryAuditRepository {
+    events: Arc<Mutex<Vec<AuditEvent>>>,
+    hash_chain: bool,
+}
+
+impl MemoryAuditRepository {
+    pub fn new(hash_chain: bool) -> Self {
+        Self {
+            hash_chain,
+            ..Self::default()
+        }
+    }
+
+    fn events(&self) -> MutexGuard<'_, Vec<AuditEvent>> {
+        self.events.lock().expect("audit repository lock poisoned")
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MemoryAuditRepository {
+    async fn record(&mut self, event: NewAuditEvent) -> AppResult<AuditEvent> {
+        self.record_now(event).await
+    }
+
+    async fn record_now(&mut self, event: NewAuditEvent) -> AppResult<AuditEvent> {
+        let mut events = self.events();
+        let prev_hash = events.iter().rev().find_map(|event| event.hash.clone());
+        let event = AuditEvent::chain(event, self.hash_chain, prev_hash);
+        events.push(event.clone());
+        Ok(event)
+    }
+
+    async fn list(
+        &mut self,
+        filter: &AuditFilter,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<(Vec<AuditEvent>, i64)> {
+        let events = self.events();
+        let matching: Vec<&AuditEvent> =
+            events.iter().rev().filter(|e| filter.matches(e)).collect();
+        let page = matching
+            .iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .map(|&event| event.clone())
+            .collect();
+        Ok((page, matching.len() as i64))
+    }
+
+    async fn verify(&mut self) -> AppResult<ChainReport> {
+        let mut verifier = ChainVerifier::default();
+        for event in self.events().iter().filter(|event| event.hash.is_some()) {
+            if !verifier.push(event) {
+                break;
+            }
+        }
+        Ok(verifier.finish())
+    }
+}
@@ -0,0 +1,85 @@
+//! Data access behind traits, so handlers can run against Postgres or process memory.
+//!
+//! `AppState` holds the [`UserStore`] picked from `[user] repository`; handlers ask [`users`]
+//! and [`audit`] for repositories scoped to the request.
+
+use std::time::Duration;
+
+use salvo::Depot;
+
+use crate::config::ServerConfig;
+use crate::config::user_config::{REPOSITORY_MEMORY, REPOSITORY_POSTGRES};
+use crate::hoops::jwt;
+use crate::state::AppStateDepotExt;
+use crate::{AppResult, db};
+
+mod audit;
+mod memory;
+mod postgres;
+mod user;
+
+pub use audit::{AuditEvent, AuditFilter, AuditRepository, ChainReport, NewAuditEvent};
+pub use memory::{MemoryAuditRepository, MemoryUserRepository};
+pub use postgres::{PgAuditRepository, PgUserRepository};
+pub use user::{NewUser, UpdateOutcome, UserChanges, UserFilter, UserRepository};
+
+/// Where users live, and with them the audit trail of what was done to them.
+#[derive(Clone, Debug)]
+pub enum UserStore {
+    Postgres,
+    Memory {
+        users: MemoryUserRepository,
+        audit: MemoryAuditRepository,
+    },
+}
+
+impl UserStore {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        match config.user.repository.as_str() {
+            REPOSITORY_POSTGRES => Self::Postgres,
+            REPOSITORY_MEMORY => Self::Memory {
+                users: MemoryUserRepository::default(),
+                audit: MemoryAuditRepository::new(config.audit.hash_chain),
+            },
+            other => panic!("Unknown user repository `{other}`"),
+        }
+    }
+}
+
+/// The user repository of this request. The Postgres one works through the request
+/// transaction, so the `unit_of_work` hoop must be mounted.
+pub fn users(depot: &mut Depot) -> AppResult<Box<dyn UserRepository + '_>> {
+    let state = depot.state();
+    match &state.users {
+        UserStore::Memory { users, .. } => Ok(Box::new(users.clone())),
+        UserStore::Postgres => {
+            let read_pool = state.db.read_pool(jwt::current_uid(depot)).clone();
+            let retention = Duration::from_secs(state.config.user.retention.max(0) as u64);
+            Ok(Box::new(PgUserRepository {
+                uow: db::uow::unit_of_work(depot)?,
+                read_pool,
+                retention,
+            }))
+        }
+    }
+}
+
+/// The audit repository of this request. Like [`users`], the Postgres one needs the
+/// `unit_of_work` hoop.
+pub fn audit(depot: &mut Depot) -> AppResult<Box<dyn AuditRepository + '_>> {
+    let state = depot.state();
+    match &state.users {
+        UserStore::Memory { audit, .. } => Ok(Box::new(audit.clone())),
+        UserStore::Postgres => {
+            let pool = state.db.pool().clone();
+            let read_pool = state.db.read_pool(jwt::current_uid(depot)).clone();
+            let hash_chain = state.config.audit.hash_chain;
+            Ok(Box::new(PgAuditRepository {
+                uow: db::uow::unit_of_work(depot)?,
+                pool,
+                read_pool,
+                hash_chain,
+            }))
+        }
+    }
+}
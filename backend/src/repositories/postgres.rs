@@ -0,0 +1,612 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use futures_util::stream::{self, BoxStream};
+use futures_util::{StreamExt, TryStreamExt};
+use salvo::async_trait;
+use sqlx::{PgConnection, PgPool};
+
+use super::audit::{
+    AuditEvent, AuditFilter, AuditRepository, ChainReport, ChainVerifier, NewAuditEvent,
+};
+use super::user::{
+    NewUser, UpdateOutcome, UserChanges, UserFilter, UserRepository, VersionedUser, snippet,
+};
+use crate::AppResult;
+use crate::db::uow::UnitOfWork;
+use crate::jobs;
+use crate::models::{SafeUser, User, UserListItem};
+use crate::tasks::PurgeDeletedUser;
+
+/// Users in the `users` table. Everything runs in the request transaction except `list` and
+/// `export`, which read from `read_pool` so they can be served by the follower.
+pub struct PgUserRepository<'a> {
+    pub uow: &'a mut UnitOfWork,
+    pub read_pool: PgPool,
+    /// How long a deleted user stays restorable before its purge job runs.
+    pub retention: Duration,
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository<'_> {
+    async fn find_by_username(&mut self, username: &str) -> AppResult<Option<User>> {
+        let conn = self.uow.tx().await?;
+        Ok(sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, password FROM users
+            WHERE username = $1 AND deleted_at IS NULL
+            "#,
+            username
+        )
+        .fetch_optional(&mut **conn)
+        .await?)
+    }
+
+    async fn find(&mut self, id: &str) -> AppResult<Option<VersionedUser>> {
+        let conn = self.uow.tx().await?;
+        let user = sqlx::query!(
+            r#"
+            SELECT id, username, email, display_name, avatar_url, version FROM users
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id,
+        )
+        .fetch_optional(&mut **conn)
+        .await?;
+        Ok(user.map(|user| VersionedUser {
+            user: SafeUser {
+                id: user.id,
+                username: user.username,
+                email: user.email,
+                display_name: user.display_name,
+                avatar_url: user.avatar_url,
+            },
+            version: user.version,
+        }))
+    }
+
+    async fn list(
+        &mut self,
+        filter: &UserFilter,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<(Vec<UserListItem>, i64)> {
+        match filter.q.as_deref().map(str::trim) {
+            Some(q) if !q.is_empty() => search_users(&self.read_pool, q, limit, offset).await,
+            _ => {
+                let username = filter.username.as_deref().unwrap_or_default();
+                filter_users(&self.read_pool, username, limit, offset).await
+            }
+        }
+    }
+
+    async fn username_taken(
+        &mut self,
+        username: &str,
+        except_id: &str,
+        release_on_delete: bool,
+    ) -> AppResult<bool> {
+        let conn = self.uow.tx().await?;
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM users
+                WHERE username = $1 AND id <> $2 AND (deleted_at IS NULL OR NOT $3)
+            ) as "taken!"
+            "#,
+            username,
+            except_id,
+            release_on_delete,
+        )
+        .fetch_one(&mut **conn)
+        .await?)
+    }
+
+    async fn usernames_taken(
+        &mut self,
+        usernames: &[String],
+        release_on_delete: bool,
+    ) -> AppResult<HashSet<String>> {
+        let conn = self.uow.tx().await?;
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT username FROM users
+            WHERE username = ANY($1) AND (deleted_at IS NULL OR NOT $2)
+            "#,
+            usernames,
+            release_on_delete,
+        )
+        .fetch_all(&mut **conn)
+        .await?
+        .into_iter()
+        .collect())
+    }
+
+    async fn create(&mut self, user: NewUser) -> AppResult<()> {
+        let conn = self.uow.tx().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id, username, password, email, display_name)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            user.id,
+            user.username,
+            user.password,
+            user.email,
+            user.display_name,
+        )
+        .execute(&mut **conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn create_many(&mut self, users: Vec<NewUser>) -> AppResult<()> {
+        let mut ids = Vec::with_capacity(users.len());
+        let mut usernames = Vec::with_capacity(users.len());
+        let mut passwords = Vec::with_capacity(users.len());
+        let mut emails = Vec::with_capacity(users.len());
+        let mut display_names = Vec::with_capacity(users.len());
+        for user in users {
+            ids.push(user.id);
+            usernames.push(user.username);
+            passwords.push(user.password);
+            emails.push(user.email);
+            display_names.push(user.display_name);
+        }
+        let conn = self.uow.tx().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id, username, password, email, display_name)
+            SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::text[])
+            "#,
+            &ids,
+            &usernames,
+            &passwords,
+            &emails as &[Option<String>],
+            &display_names as &[Option<String>],
+        )
+        .execute(&mut **conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn update(
+        &mut self,
+        id: &str,
+        changes: UserChanges,
+        expected: Option<&[i64]>,
+    ) -> AppResult<UpdateOutcome> {
+        let conn = self.uow.tx().await?;
+        let updated = sqlx::query!(
+            r#"
+            UPDATE users
+            SET username = COALESCE($1, username),
+                password = COALESCE($2, password),
+                email = CASE WHEN $3 THEN $4 ELSE email END,
+                display_name = CASE WHEN $5 THEN $6 ELSE display_name END,
+                version = version + 1
+            WHERE id = $7 AND deleted_at IS NULL
+              AND ($8::bigint[] IS NULL OR version = ANY($8))
+            RETURNING id, username, email, display_name, avatar_url, version
+            "#,
+            changes.username,
+            changes.password,
+            changes.email.is_some(),
+            changes.email.flatten(),
+            changes.display_name.is_some(),
+            changes.display_name.flatten(),
+            id,
+            expected,
+        )
+        .fetch_optional(&mut **conn)
+        .await?;
+        if let Some(user) = updated {
+            return Ok(UpdateOutcome::Updated(VersionedUser {
+                user: SafeUser {
+                    id: user.id,
+                    username: user.username,
+                    email: user.email,
+                    display_name: user.display_name,
+                    avatar_url: user.avatar_url,
+                },
+                version: user.version,
+            }));
+        }
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NULL) as "exists!"
+            "#,
+            id,
+        )
+        .fetch_one(&mut **conn)
+        .await?;
+        Ok(if exists {
+            UpdateOutcome::Stale
+        } else {
+            UpdateOutcome::NotFound
+        })
+    }
+
+    async fn delete(&mut self, id: &str) -> AppResult<bool> {
+        let conn = self.uow.tx().await?;
+        let deleted = sqlx::query!(
+            r#"
+            UPDATE users
+            SET deleted_at = now(), version = version + 1
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id,
+        )
+        .execute(&mut **conn)
+        .await?
+        .rows_affected();
+        if deleted == 0 {
+            return Ok(false);
+        }
+        let purge = PurgeDeletedUser { id: id.to_owned() };
+        jobs::enqueue(conn, &purge, self.retention).await?;
+        Ok(true)
+    }
+
+    async fn find_restorable(&mut self, id: &str, retention: i64) -> AppResult<Option<SafeUser>> {
+        let conn = self.uow.tx().await?;
+        Ok(sqlx::query_as!(
+            SafeUser,
+            r#"
+            SELECT id, username, email, display_name, avatar_url FROM users
+            WHERE id = $1 AND deleted_at IS NOT NULL
+              AND deleted_at > now() - make_interval(secs => $2)
+            "#,
+            id,
+            retention as f64,
+        )
+        .fetch_optional(&mut **conn)
+        .await?)
+    }
+
+    async fn restore(&mut self, id: &str) -> AppResult<()> {
+        let conn = self.uow.tx().await?;
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET deleted_at = NULL, version = version + 1
+            WHERE id = $1
+            "#,
+            id,
+        )
+        .execute(&mut **conn)
+        .await?;
+        Ok(())
+    }
+
+    fn export(&mut self, username: &str) -> BoxStream<'static, AppResult<SafeUser>> {
+        let pool = self.read_pool.clone();
+        let like_pattern = format!("%{}%", username);
+        // The rows are read by a task that owns its pool handle, as the stream outlives the
+        // request. The bounded channel keeps it from reading ahead of a slow consumer.
+        let (sender, receiver) = tokio::sync::mpsc::channel(64);
+        tokio::spawn(async move {
+            let mut users = sqlx::query_as!(
+                SafeUser,
+                r#"
+                SELECT id, username, email, display_name, avatar_url FROM users
+                WHERE username LIKE $1 AND deleted_at IS NULL
+                ORDER BY id
+                "#,
+                like_pattern
+            )
+            .fetch(&pool);
+            while let Some(user) = users.next().await {
+                if sender.send(user.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|user| (user, receiver))
+        })
+        .boxed()
+    }
+
+    async fn is_admin(&mut self, id: &str) -> AppResult<bool> {
+        let conn = self.uow.tx().await?;
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT is_admin FROM users
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_optional(&mut **conn)
+        .await?
+        .unwrap_or(false))
+    }
+
+    async fn set_avatar(&mut self, id: &str, key: &str, url: &str) -> AppResult<Option<SafeUser>> {
+        let conn = self.uow.tx().await?;
+        Ok(sqlx::query_as!(
+            SafeUser,
+            r#"
+            UPDATE users
+            SET avatar_key = $1, avatar_url = $2, version = version + 1
+            WHERE id = $3 AND deleted_at IS NULL
+            RETURNING id, username, email, display_name, avatar_url
+            "#,
+            key,
+            url,
+            id,
+        )
+        .fetch_optional(&mut **conn)
+        .await?)
+    }
+
+    async fn avatar_key(&mut self, id: &str) -> AppResult<Option<String>> {
+        let conn = self.uow.tx().await?;
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT avatar_key FROM users
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id,
+        )
+        .fetch_optional(&mut **conn)
+        .await?
+        .flatten())
+    }
+}
+
+async fn filter_users(
+    conn: &PgPool,
+    username: &str,
+    limit: i64,
+    offset: i64,
+) -> AppResult<(Vec<UserListItem>, i64)> {
+    let like_pattern = format!("%{}%", username);
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!: i64" FROM users
+        WHERE username LIKE $1 AND deleted_at IS NULL
+        "#,
+        like_pattern
+    )
+    .fetch_one(conn)
+    .await?;
+
+    let users = sqlx::query_as!(
+        SafeUser,
+        r#"
+        SELECT id, username, email, display_name, avatar_url FROM users
+        WHERE username LIKE $1 AND deleted_at IS NULL
+        LIMIT $2 OFFSET $3
+        "#,
+        like_pattern,
+        limit,
+        offset
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok((users.into_iter().map(UserListItem::from).collect(), total))
+}
+
+/// Matches `q` as whole words through `search_vector` and as (possibly misspelt) fragments
+/// through `pg_trgm` word similarity, best matches first.
+async fn search_users(
+    conn: &PgPool,
+    q: &str,
+    limit: i64,
+    offset: i64,
+) -> AppResult<(Vec<UserListItem>, i64)> {
+    let like_pattern = format!("%{}%", q);
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!: i64" FROM users
+        WHERE deleted_at IS NULL AND (
+            search_vector @@ websearch_to_tsquery('simple', $1)
+            OR $1 <% username OR $1 <% email OR $1 <% display_name
+            OR username ILIKE $2 OR email ILIKE $2 OR display_name ILIKE $2
+        )
+        "#,
+        q,
+        like_pattern
+    )
+    .fetch_one(conn)
+    .await?;
+
+    let hits = sqlx::query!(
+        r#"
+        SELECT id, username, email, display_name, avatar_url,
+            GREATEST(
+                word_similarity($1, username),
+                word_similarity($1, coalesce(email, '')),
+                word_similarity($1, coalesce(display_name, ''))
+            ) + ts_rank(search_vector, websearch_to_tsquery('simple', $1)) as "rank!: f32"
+        FROM users
+        WHERE deleted_at IS NULL AND (
+            search_vector @@ websearch_to_tsquery('simple', $1)
+            OR $1 <% username OR $1 <% email OR $1 <% display_name
+            OR username ILIKE $2 OR email ILIKE $2 OR display_name ILIKE $2
+        )
+        ORDER BY 6 DESC, username
+        LIMIT $3 OFFSET $4
+        "#,
+        q,
+        like_pattern,
+        limit,
+        offset
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let data = hits
+        .into_iter()
+        .map(|hit| {
+            let user = SafeUser {
+                id: hit.id,
+                username: hit.username,
+                email: hit.email,
+                display_name: hit.display_name,
+                avatar_url: hit.avatar_url,
+            };
+            UserListItem {
+                snippet: Some(snippet(&user, q)),
+                rank: Some(hit.rank),
+                user,
+            }
+        })
+        .collect();
+    Ok((data, total))
+}
+
+/// Key of the advisory lock that serializes chained audit events.
+const AUDIT_CHAIN_LOCK: i64 = 0x0061_7564_6974; // "audit"
+
+/// Audit events in the `audit_events` table. `record` writes in the request transaction,
+/// `record_now` in one of its own on the primary; listing reads from `read_pool`.
+pub struct PgAuditRepository<'a> {
+    pub uow: &'a mut UnitOfWork,
+    pub pool: PgPool,
+    pub read_pool: PgPool,
+    pub hash_chain: bool,
+}
+
+/// Inserts `event`. A chained event takes the chain lock until its transaction ends, so the
+/// next one links to it only once it is committed.
+async fn append(
+    conn: &mut PgConnection,
+    event: NewAuditEvent,
+    hash_chain: bool,
+) -> AppResult<AuditEvent> {
+    let mut prev_hash = None;
+    if hash_chain {
+        sqlx::query!("SELECT pg_advisory_xact_lock($1)", AUDIT_CHAIN_LOCK)
+            .execute(&mut *conn)
+            .await?;
+        prev_hash = sqlx::query_scalar!(
+            r#"
+            SELECT hash as "hash!" FROM audit_events
+            WHERE hash IS NOT NULL
+            ORDER BY seq DESC LIMIT 1
+            "#
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+    }
+    let event = AuditEvent::chain(event, hash_chain, prev_hash);
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (id, occurred_at, actor_id, action, target_type, target_id,
+                                  diff, details, ip, user_agent, request_id, prev_hash, hash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        "#,
+        event.id,
+        event.occurred_at,
+        event.actor_id,
+        event.action,
+        event.target_type,
+        event.target_id,
+        event.diff,
+        event.details,
+        event.ip,
+        event.user_agent,
+        event.request_id,
+        event.prev_hash,
+        event.hash,
+    )
+    .execute(conn)
+    .await?;
+    Ok(event)
+}
+
+#[async_trait]
+impl AuditRepository for PgAuditRepository<'_> {
+    async fn record(&mut self, event: NewAuditEvent) -> AppResult<AuditEvent> {
+        let conn = self.uow.tx().await?;
+        append(conn, event, self.hash_chain).await
+    }
+
+    async fn record_now(&mut self, event: NewAuditEvent) -> AppResult<AuditEvent> {
+        let mut tx = self.pool.begin().await?;
+        let event = append(&mut tx, event, self.hash_chain).await?;
+        tx.commit().await?;
+        Ok(event)
+    }
+
+    async fn list(
+        &mut self,
+        filter: &AuditFilter,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<(Vec<AuditEvent>, i64)> {
+        let total = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!" FROM audit_events
+            WHERE ($1::text IS NULL OR actor_id = $1)
+              AND ($2::text IS NULL OR action = $2)
+              AND ($3::text IS NULL OR target_type = $3)
+              AND ($4::text IS NULL OR target_id = $4)
+              AND ($5::timestamptz IS NULL OR occurred_at >= $5)
+              AND ($6::timestamptz IS NULL OR occurred_at < $6)
+            "#,
+            filter.actor_id,
+            filter.action,
+            filter.target_type,
+            filter.target_id,
+            filter.since,
+            filter.until,
+        )
+        .fetch_one(&self.read_pool)
+        .await?;
+        let events = sqlx::query_as!(
+            AuditEvent,
+            r#"
+            SELECT id, occurred_at, actor_id, action, target_type, target_id, diff, details,
+                   ip, user_agent, request_id, prev_hash, hash
+            FROM audit_events
+            WHERE ($1::text IS NULL OR actor_id = $1)
+              AND ($2::text IS NULL OR action = $2)
+              AND ($3::text IS NULL OR target_type = $3)
+              AND ($4::text IS NULL OR target_id = $4)
+              AND ($5::timestamptz IS NULL OR occurred_at >= $5)
+              AND ($6::timestamptz IS NULL OR occurred_at < $6)
+            ORDER BY seq DESC
+            LIMIT $7 OFFSET $8
+            "#,
+            filter.actor_id,
+            filter.action,
+            filter.target_type,
+            filter.target_id,
+            filter.since,
+            filter.until,
+            limit,
+            offset,
+        )
+        .fetch_all(&self.read_pool)
+        .await?;
+        Ok((events, total))
+    }
+
+    async fn verify(&mut self) -> AppResult<ChainReport> {
+        let mut events = sqlx::query_as!(
+            AuditEvent,
+            r#"
+            SELECT id, occurred_at, actor_id, action, target_type, target_id, diff, details,
+                   ip, user_agent, request_id, prev_hash, hash
+            FROM audit_events
+            WHERE hash IS NOT NULL
+            ORDER BY seq
+            "#
+        )
+        .fetch(&self.pool);
+        let mut verifier = ChainVerifier::default();
+        while let Some(event) = events.try_next().await? {
+            if !verifier.push(&event) {
+                break;
+            }
+        }
+        Ok(verifier.finish())
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use futures_util::stream::BoxStream;
+use salvo::async_trait;
+
+use crate::AppResult;
+use crate::models::{SafeUser, User, UserListItem};
+
+/// A live user together with the version its `ETag` is built from.
+#[derive(Clone, Debug)]
+pub struct VersionedUser {
+    pub user: SafeUser,
+    pub version: i64,
+}
+
+/// A user to insert. `password` must already be hashed.
+#[derive(Clone, Debug)]
+pub struct NewUser {
+    pub id: String,
+    pub username: String,
+    pub password: String,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+}
+
+/// Fields to change on an update; `None` keeps the current value. `password` must already be
+/// hashed.
+#[derive(Clone, Default, Debug)]
+pub struct UserChanges {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub email: Option<Option<String>>,
+    pub display_name: Option<Option<String>>,
+}
+
+#[derive(Debug)]
+pub enum UpdateOutcome {
+    Updated(VersionedUser),
+    /// The user exists but none of the expected versions matched.
+    Stale,
+    NotFound,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct UserFilter {
+    /// Substring the username must contain.
+    pub username: Option<String>,
+    /// Free-text search over username, email and display name; `username` is ignored when set.
+    pub q: Option<String>,
+}
+
+/// Storage of users. Soft-deleted users are invisible to everything but `find_restorable`,
+/// `restore` and `username_taken`.
+#[async_trait]
+pub trait UserRepository: Send {
+    /// The live user with this username, password hash included.
+    async fn find_by_username(&mut self, username: &str) -> AppResult<Option<User>>;
+    async fn find(&mut self, id: &str) -> AppResult<Option<VersionedUser>>;
+    /// One page of live users and the number of users matching `filter`.
+    async fn list(
+        &mut self,
+        filter: &UserFilter,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<(Vec<UserListItem>, i64)>;
+    /// Whether a user other than `except_id` holds `username`. Soft-deleted users only count
+    /// when `release_on_delete` is off.
+    async fn username_taken(
+        &mut self,
+        username: &str,
+        except_id: &str,
+        release_on_delete: bool,
+    ) -> AppResult<bool>;
+    /// Those of `usernames` that `username_taken` would call taken by anyone.
+    async fn usernames_taken(
+        &mut self,
+        usernames: &[String],
+        release_on_delete: bool,
+    ) -> AppResult<HashSet<String>>;
+    async fn create(&mut self, user: NewUser) -> AppResult<()>;
+    /// Creates all of `users` or, on error, none of them.
+    async fn create_many(&mut self, users: Vec<NewUser>) -> AppResult<()>;
+    /// Applies `changes` and bumps the version, provided the current version is one of
+    /// `expected` (any version when `None`).
+    async fn update(
+        &mut self,
+        id: &str,
+        changes: UserChanges,
+        expected: Option<&[i64]>,
+    ) -> AppResult<UpdateOutcome>;
+    /// Soft-deletes a live user. Returns whether there was one.
+    async fn delete(&mut self, id: &str) -> AppResult<bool>;
+    /// A user soft-deleted less than `retention` seconds ago.
+    async fn find_restorable(&mut self, id: &str, retention: i64) -> AppResult<Option<SafeUser>>;
+    async fn restore(&mut self, id: &str) -> AppResult<()>;
+    /// The live users whose username contains `username`, by id. The stream owns what it
+    /// needs, so it can outlive the request, and yields users as they are read.
+    fn export(&mut self, username: &str) -> BoxStream<'static, AppResult<SafeUser>>;
+    /// Whether a live user with this id is an admin.
+    async fn is_admin(&mut self, id: &str) -> AppResult<bool>;
+    /// Points the avatar of a live user at the stored file `key`, served from `url`, returning
+    /// the updated user.
+    async fn set_avatar(&mut self, id: &str, key: &str, url: &str) -> AppResult<Option<SafeUser>>;
+    /// Storage key of the avatar of a live user, if it has one.
+    async fn avatar_key(&mut self, id: &str) -> AppResult<Option<String>>;
+}
+
+/// HTML-escapes `text` and wraps every ASCII case-insensitive occurrence of a term in `<mark>`.
+pub(super) fn highlight(text: &str, terms: &[&str]) -> String {
+    let lower = text.to_ascii_lowercase();
+    let mut marked = vec![false; text.len()];
+    for term in terms {
+        let term = term.to_ascii_lowercase();
+        if term.is_empty() {
+            continue;
+        }
+        for (start, _) in lower.match_indices(&term) {
+            marked[start..start + term.len()].fill(true);
+        }
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut open = false;
+    for (i, c) in text.char_indices() {
+        if marked[i] != open {
+            out.push_str(if open { "</mark>" } else { "<mark>" });
+            open = marked[i];
+        }
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    if open {
+        out.push_str("</mark>");
+    }
+    out
+}
+
+/// Search snippet of `user` for the whitespace-separated terms of `q`.
+pub(super) fn snippet(user: &SafeUser, q: &str) -> String {
+    let terms: Vec<&str> = q.split_whitespace().collect();
+    let text = [
+        Some(user.username.as_str()),
+        user.email.as_deref(),
+        user.display_name.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" · ");
+    highlight(&text, &terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::highlight;
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            highlight("Zhang <San> · zhang@example.com", &["zhang", "san"]),
+            "<mark>Zhang</mark> &lt;<mark>San</mark>&gt; · <mark>zhang</mark>@example.com"
+        );
+        assert_eq!(highlight("zhangsan", &["xyz"]), "zhangsan");
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::repositories::{self, AuditEvent, AuditFilter, ChainReport};
+use crate::state::AppStateDepotExt;
+use crate::{JsonResult, json_ok, tasks};
+
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct TaskStatus {
+    pub name: String,
+    /// Effective cron expression, or `None` when the task is turned off.
+    pub schedule: Option<String>,
+    /// Run by every instance, which only log the outcome, so the fields below stay empty.
+    pub local: bool,
+    #[salvo(schema(value_type = Option<String>, format = DateTime))]
+    pub next_run: Option<DateTime<Utc>>,
+    #[salvo(schema(value_type = Option<String>, format = DateTime))]
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_duration_ms: Option<i64>,
+    /// `ok` or `error`.
+    pub last_status: Option<String>,
+    /// Summary of the last run, or its error.
+    pub last_result: Option<String>,
+    pub runs: i64,
+}
+
+/// Lists the recurring tasks with the outcome of their last run, whichever instance ran it.
+#[endpoint(tags("admin"))]
+pub async fn list_tasks(depot: &mut Depot) -> JsonResult<Vec<TaskStatus>> {
+    let state = depot.state();
+    let runs = sqlx::query!(
+        r#"
+        SELECT name, last_started_at, last_duration_ms, last_status, last_result, runs
+        FROM scheduled_tasks
+        "#
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+    let scheduled = tasks::scheduler().resolve(&state.config.cron);
+    let statuses = scheduled
+        .iter()
+        .map(|task| {
+            let run = runs.iter().find(|run| run.name == task.name);
+            TaskStatus {
+                name: task.name.to_owned(),
+                schedule: task.schedule.as_ref().map(|s| s.source().to_owned()),
+                local: task.local,
+                next_run: task.next_run().filter(|_| state.config.cron.enabled),
+                last_started_at: run.and_then(|run| run.last_started_at),
+                last_duration_ms: run.and_then(|run| run.last_duration_ms),
+                last_status: run.and_then(|run| run.last_status.clone()),
+                last_result: run.and_then(|run| run.last_result.clone()),
+                runs: run.map_or(0, |run| run.runs),
+            }
+        })
+        .collect();
+    json_ok(statuses)
+}
+
+#[derive(Debug, Deserialize, Extractible, ToSchema)]
+#[salvo(extract(default_source(from = "query")))]
+struct AuditQuery {
+    actor_id: Option<String>,
+    /// Like `user.update` or `auth.login_failed`.
+    action: Option<String>,
+    target_type: Option<String>,
+    target_id: Option<String>,
+    /// Only events at or after this time.
+    #[salvo(schema(value_type = Option<String>, format = DateTime))]
+    since: Option<DateTime<Utc>>,
+    /// Only events before this time.
+    #[salvo(schema(value_type = Option<String>, format = DateTime))]
+    until: Option<DateTime<Utc>>,
+    #[serde(default = "default_page")]
+    current_page: i64,
+    /// At most 100.
+    #[serde(default = "default_page_size")]
+    page_size: i64,
+}
+
+fn default_page() -> i64 {
+    1
+}
+fn default_page_size() -> i64 {
+    50
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditListResponse {
+    pub data: Vec<AuditEvent>,
+    pub total: i64,
+    pub current_page: i64,
+    pub page_size: i64,
+}
+
+/// Lists audit events, newest first.
+#[endpoint(tags("admin"))]
+pub async fn list_audit_events(
+    req: &mut Request,
+    depot: &mut Depot,
+) -> JsonResult<AuditListResponse> {
+    let query: AuditQuery = req.extract(depot).await?;
+    if query.current_page < 1 || !(1..=MAX_PAGE_SIZE).contains(&query.page_size) {
+        return Err(StatusError::bad_request()
+            .brief(format!(
+                "`current_page` must be positive and `page_size` between 1 and {MAX_PAGE_SIZE}."
+            ))
+            .into());
+    }
+    let filter = AuditFilter {
+        actor_id: query.actor_id,
+        action: query.action,
+        target_type: query.target_type,
+        target_id: query.target_id,
+        since: query.since,
+        until: query.until,
+    };
+    let offset = (query.current_page - 1) * query.page_size;
+    let (data, total) = repositories::audit(depot)?
+        .list(&filter, query.page_size, offset)
+        .await?;
+    json_ok(AuditListResponse {
+        data,
+        total,
+        current_page: query.current_page,
+        page_size: query.page_size,
+    })
+}
+
+/// Recomputes the hash chain of the audit events (see `[audit] hash_chain`) and reports the
+/// first event that does not match.
+#[endpoint(tags("admin"))]
+pub async fn verify_audit_chain(depot: &mut Depot) -> JsonResult<ChainReport> {
+    json_ok(repositories::audit(depot)?.verify().await?)
+}
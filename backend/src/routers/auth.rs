@@ -4,19 +4,22 @@ use salvo::oapi::extract::*;
 use salvo::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::db::tenant::Tenant;
 use crate::hoops::jwt;
 use crate::models::User;
-use crate::{db, json_ok, utils, AppResult, JsonResult};
+use crate::repositories::NewAuditEvent;
+use crate::state::AppStateDepotExt;
+use crate::{AppResult, JsonResult, audit, db, json_ok, repositories, utils, webhooks, workspaces};
 
 #[handler]
-pub async fn login_page(req: &mut Request,res: &mut Response) -> AppResult<()> {
+pub async fn login_page(req: &mut Request, depot: &mut Depot, res: &mut Response) -> AppResult<()> {
     #[derive(Template)]
     #[template(path = "login.html")]
     struct LoginTemplate {}
     // 1. Extract token using your universal finders (Header, Query, Cookies)
     if let Some(jwt_token) = utils::extract_jwt_token_manually(req).await { // check even from headers adn query
         // 2. Validate the token (checks signature + expiration)
-        if jwt::is_jwt_token_valid(&jwt_token) {
+        if jwt::is_jwt_token_valid(&depot.state().config.jwt, &jwt_token) {
             res.render(Redirect::other("/users")); //
             return Ok(());
         }
@@ -30,6 +33,9 @@ pub async fn login_page(req: &mut Request,res: &mut Response) -> AppResult<()> {
 pub struct LoginInData {
     pub username: String,
     pub password: String,
+    /// Workspace the token acts in; defaults to the one the user joined first.
+    #[serde(default)]
+    pub workspace_id: Option<String>,
 }
 #[derive(Serialize, ToSchema, Default, Debug)]
 pub struct LoginOutData {
@@ -37,29 +43,69 @@ pub struct LoginOutData {
     pub username: String,
     pub token: String,
     pub exp: i64,
+    /// Workspace of the token, none when the user belongs to none.
+    pub workspace_id: Option<String>,
+}
+
+/// Checks that `uid` belongs to `wid`, or picks the workspace they joined first.
+async fn login_workspace(
+    depot: &mut Depot,
+    uid: &str,
+    wid: Option<String>,
+) -> AppResult<Option<String>> {
+    let uow = db::uow::unit_of_work(depot)?;
+    uow.enter(Tenant {
+        user_id: Some(uid.to_owned()),
+        workspace_id: None,
+    })
+    .await?;
+    let conn = uow.tx().await?;
+    match wid {
+        Some(wid) => {
+            if workspaces::role(conn, &wid, uid).await?.is_none() {
+                return Err(StatusError::forbidden()
+                    .brief("You are not a member of this workspace.")
+                    .into());
+            }
+            Ok(Some(wid))
+        }
+        None => Ok(workspaces::list_for_user(conn, uid)
+            .await?
+            .into_iter()
+            .next()
+            .map(|workspace| workspace.id)),
+    }
+}
+
+/// Sets the `jwt_token` cookie that browser pages authenticate with.
+pub fn set_token_cookie(depot: &Depot, res: &mut Response, token: &str) {
+    let cookie = Cookie::build(("jwt_token", token.to_owned()))
+        .path("/")
+        .http_only(true)
+        // If is_secure_context() is true, browser only sends over HTTPS.
+        // If false (local dev), browser allows plain HTTP.
+        .secure(utils::is_secure_context(depot))
+        .build();
+    res.add_cookie(cookie);
 }
 #[endpoint(tags("auth"))]
 pub async fn post_login(
     idata: JsonBody<LoginInData>,
+    depot: &mut Depot,
     res: &mut Response,
 ) -> JsonResult<LoginOutData> {
     let idata = idata.into_inner();
-    let conn = db::pool();
+    let failed = NewAuditEvent::new(audit::LOGIN_FAILED);
     let Some(User {
         id,
         username,
         password,
-    }) = sqlx::query_as!(
-        User,
-        r#"
-            SELECT id, username, password FROM users
-            WHERE username = $1
-            "#,
-        idata.username
-    )
-    .fetch_optional(conn)
-    .await?
+    }) = repositories::users(depot)?
+        .find_by_username(&idata.username)
+        .await?
     else {
+        let details = serde_json::json!({"username": idata.username, "reason": "unknown_user"});
+        audit::record_now(depot, failed.details(details)).await;
         return Err(StatusError::unauthorized()
             .brief("User does not exist.")
             .into());
@@ -67,27 +113,31 @@ pub async fn post_login(
 
     if utils::verify_password(&idata.password, &password).is_err()
     {
+        let details = serde_json::json!({"username": username, "reason": "wrong_password"});
+        audit::record_now(depot, failed.target("user", &id).details(details)).await;
         return Err(StatusError::unauthorized()
             .brief("Account not exist or password is incorrect.")
             .into());
     }
 
-    let (token, exp) = jwt::generate_jwt_token(&id)?;
-    let odata = LoginOutData {
+    let workspace_id = login_workspace(depot, &id, idata.workspace_id).await?;
+    let event = NewAuditEvent::new(audit::LOGIN)
+        .actor(&id)
+        .target("user", &id)
+        .details(serde_json::json!({"workspace_id": workspace_id}));
+    audit::record(depot, event).await?;
+    let logged_in = serde_json::json!({"id": id, "username": username});
+    webhooks::publish(depot, webhooks::USER_LOGGED_IN, &logged_in).await?;
+    let (token, exp) =
+        jwt::generate_jwt_token(&depot.state().config.jwt, &id, workspace_id.clone())?;
+    set_token_cookie(depot, res, &token);
+    json_ok(LoginOutData {
         id,
         username,
         token,
         exp,
-    };
-    let cookie = Cookie::build(("jwt_token", odata.token.clone()))
-        .path("/")
-        .http_only(true)
-        // If is_secure_context() is true, browser only sends over HTTPS.
-        // If false (local dev), browser allows plain HTTP.
-        .secure(utils::is_secure_context())
-        .build();
-    res.add_cookie(cookie);
-    json_ok(odata)
+        workspace_id,
+    })
 }
 
 
@@ -0,0 +1,139 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use serde::Deserialize;
+use serde_json::Value;
+use validator::Validate;
+
+use crate::embeddings::{self, Document, Metric, Neighbour, Search};
+use crate::state::AppStateDepotExt;
+use crate::{AppResult, EmptyResult, JsonResult, db, empty_ok, json_ok};
+
+const MAX_K: i64 = 100;
+
+/// 400 unless `embedding` has the configured dimension and only finite values.
+fn check_embedding(embedding: &[f32], dimension: usize) -> AppResult<()> {
+    if embedding.len() != dimension {
+        return Err(StatusError::bad_request()
+            .brief(format!(
+                "Embedding has {} dimensions, expected {dimension}.",
+                embedding.len()
+            ))
+            .into());
+    }
+    if !embedding.iter().all(|x| x.is_finite()) {
+        return Err(StatusError::bad_request()
+            .brief("Embedding values must be finite.")
+            .into());
+    }
+    Ok(())
+}
+
+/// 400 unless `value` is absent or a JSON object.
+fn check_object(value: Option<&Value>, field: &str) -> AppResult<()> {
+    match value {
+        None | Some(Value::Object(_)) => Ok(()),
+        Some(_) => Err(StatusError::bad_request()
+            .brief(format!("`{field}` must be an object."))
+            .into()),
+    }
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+struct UpsertInData {
+    #[validate(length(min = 1, message = "content must not be empty"))]
+    content: String,
+    /// Free-form object that searches can filter on.
+    metadata: Option<Value>,
+    embedding: Vec<f32>,
+}
+
+/// Creates the document or replaces it.
+#[endpoint(tags("documents"), parameters(("document_id", description = "document id")))]
+pub async fn put_document(
+    document_id: PathParam<String>,
+    idata: JsonBody<UpsertInData>,
+    depot: &mut Depot,
+) -> JsonResult<Document> {
+    let document_id = document_id.into_inner();
+    let idata = idata.into_inner();
+    idata.validate()?;
+    check_embedding(&idata.embedding, depot.state().config.documents.dimension)?;
+    check_object(idata.metadata.as_ref(), "metadata")?;
+    let metadata = idata
+        .metadata
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    let conn = db::transaction(depot).await?;
+    json_ok(
+        embeddings::upsert(
+            conn,
+            &document_id,
+            &idata.content,
+            &metadata,
+            idata.embedding,
+        )
+        .await?,
+    )
+}
+
+#[endpoint(tags("documents"), parameters(("document_id", description = "document id")))]
+pub async fn get_document(
+    document_id: PathParam<String>,
+    depot: &mut Depot,
+) -> JsonResult<Document> {
+    let mut tx = db::read_transaction(depot).await?;
+    let Some(document) = embeddings::find(&mut tx, &document_id.into_inner()).await? else {
+        return Err(StatusError::not_found().brief("Document not found.").into());
+    };
+    json_ok(document)
+}
+
+#[endpoint(tags("documents"), parameters(("document_id", description = "document id")))]
+pub async fn delete_document(document_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
+    let conn = db::transaction(depot).await?;
+    if !embeddings::delete(conn, &document_id.into_inner()).await? {
+        return Err(StatusError::not_found().brief("Document not found.").into());
+    }
+    empty_ok()
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct SearchInData {
+    embedding: Vec<f32>,
+    /// Number of neighbours to return, at most 100.
+    #[serde(default = "default_k")]
+    k: i64,
+    /// Defaults to `[documents] metric`, the one the index is built for.
+    metric: Option<Metric>,
+    /// Object the metadata of every result must contain.
+    filter: Option<Value>,
+}
+
+fn default_k() -> i64 {
+    10
+}
+
+/// The documents nearest to an embedding, closest first.
+#[endpoint(tags("documents"))]
+pub async fn search_documents(
+    idata: JsonBody<SearchInData>,
+    depot: &mut Depot,
+) -> JsonResult<Vec<Neighbour>> {
+    let idata = idata.into_inner();
+    let config = depot.state().config.clone();
+    let config = &config.documents;
+    check_embedding(&idata.embedding, config.dimension)?;
+    check_object(idata.filter.as_ref(), "filter")?;
+    if !(1..=MAX_K).contains(&idata.k) {
+        return Err(StatusError::bad_request()
+            .brief(format!("`k` must be between 1 and {MAX_K}."))
+            .into());
+    }
+    let search = Search {
+        embedding: idata.embedding,
+        k: idata.k,
+        metric: idata.metric.unwrap_or_else(|| Metric::from_config(config)),
+        filter: idata.filter,
+    };
+    let mut tx = db::read_transaction(depot).await?;
+    json_ok(embeddings::search(&mut tx, config, search).await?)
+}
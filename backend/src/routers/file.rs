@@ -0,0 +1,157 @@
+use salvo::http::header::{CACHE_CONTROL, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::io::AsyncReadExt;
+
+use crate::hoops::jwt;
+use crate::models::SafeUser;
+use crate::repositories;
+use crate::state::{AppState, AppStateDepotExt};
+use crate::{AppResult, JsonResult, json_ok, storage};
+
+/// Bytes read from the start of an upload to tell its type.
+const SNIFF_LEN: usize = 8 * 1024;
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct FileOutData {
+    pub key: String,
+    pub content_type: String,
+    pub size: usize,
+    /// Signed download URL, valid for `storage.url_expiry` seconds.
+    pub url: String,
+}
+
+/// Stores the `file` part of a multipart body under its content hash. The type is taken from
+/// the file's magic bytes, never from the client, and must pass `allowed`.
+async fn store_upload(
+    state: &AppState,
+    req: &mut Request,
+    allowed: impl Fn(&str) -> bool,
+) -> AppResult<FileOutData> {
+    let max_size = state.config.storage.max_size;
+    // Leave room for the multipart framing around the file itself.
+    let form = req.form_data_max_size(max_size + 16 * 1024).await?;
+    let Some(file) = form.files.get("file") else {
+        return Err(StatusError::bad_request()
+            .brief("Missing `file` part.")
+            .into());
+    };
+    if file.size() > max_size as u64 {
+        return Err(StatusError::payload_too_large().into());
+    }
+    // The form already spooled the file to disk; only its first bytes are read to sniff its
+    // type, and it is hashed and stored a chunk at a time.
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    tokio::fs::File::open(file.path())
+        .await?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .await?;
+    let Some(kind) = infer::get(&head) else {
+        return Err(StatusError::unsupported_media_type()
+            .brief("Unrecognized file type.")
+            .into());
+    };
+    if !allowed(kind.mime_type()) {
+        return Err(StatusError::unsupported_media_type()
+            .brief(format!("File type `{}` is not allowed.", kind.mime_type()))
+            .into());
+    }
+
+    let key = storage::content_key(&storage::file_digest(file.path()).await?, kind.extension());
+    if !state.storage.exists(&key).await? {
+        state.storage.put_file(&key, file.path()).await?;
+    }
+    Ok(FileOutData {
+        url: storage::signed_url(&state.config, &key),
+        key,
+        content_type: kind.mime_type().to_owned(),
+        size: file.size() as usize,
+    })
+}
+
+/// Stores an uploaded file for any logged-in user. Files are shared by content rather than
+/// owned: whoever uploads the same bytes gets the same key, and the signed URL works for
+/// anyone holding it until it expires.
+#[endpoint(tags("files"))]
+pub async fn upload_file(req: &mut Request, depot: &mut Depot) -> JsonResult<FileOutData> {
+    let state = depot.state();
+    let allowed = &state.config.storage.allowed_types;
+    json_ok(store_upload(state, req, |mime| allowed.iter().any(|t| t == mime)).await?)
+}
+
+/// Serves a stored file to anyone holding a valid, unexpired signed URL.
+#[handler]
+pub async fn download_file(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> AppResult<()> {
+    let state = depot.state();
+    let key = req.param::<String>("key").unwrap_or_default();
+    let expires = req.query::<i64>("expires").unwrap_or_default();
+    let signature = req.query::<String>("signature").unwrap_or_default();
+    if !storage::is_valid_key(&key)
+        || !storage::verify_signature(&state.config, &key, expires, &signature)
+    {
+        return Err(StatusError::forbidden()
+            .brief("Invalid or expired link.")
+            .into());
+    }
+    let Some(data) = state.storage.get(&key).await? else {
+        return Err(StatusError::not_found().into());
+    };
+
+    let content_type = infer::get(&data)
+        .map(|kind| kind.mime_type())
+        .unwrap_or("application/octet-stream");
+    let max_age = (expires - OffsetDateTime::now_utc().unix_timestamp()).max(0);
+    res.add_header(CONTENT_TYPE, content_type, true)?;
+    res.add_header(X_CONTENT_TYPE_OPTIONS, "nosniff", true)?;
+    res.add_header(CACHE_CONTROL, format!("private, max-age={max_age}"), true)?;
+    res.body(data);
+    Ok(())
+}
+
+/// Sets the current user's avatar from an uploaded image.
+#[endpoint(tags("users"))]
+pub async fn upload_avatar(req: &mut Request, depot: &mut Depot) -> JsonResult<SafeUser> {
+    let Some(uid) = jwt::current_uid(depot).map(str::to_owned) else {
+        return Err(StatusError::unauthorized().into());
+    };
+    let state = depot.state();
+    let allowed = &state.config.storage.allowed_types;
+    let upload = store_upload(state, req, |mime| {
+        mime.starts_with("image/") && allowed.iter().any(|t| t == mime)
+    })
+    .await?;
+
+    let avatar_url = format!("/api/users/{uid}/avatar");
+    let Some(user) = repositories::users(depot)?
+        .set_avatar(&uid, &upload.key, &avatar_url)
+        .await?
+    else {
+        return Err(StatusError::not_found().brief("User not found.").into());
+    };
+    json_ok(user)
+}
+
+/// Redirects to a freshly signed download URL for the user's avatar.
+#[endpoint(tags("users"), parameters(("user_id", description = "user id")))]
+pub async fn user_avatar(
+    user_id: PathParam<String>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> AppResult<()> {
+    let user_id = user_id.into_inner();
+    let Some(key) = repositories::users(depot)?.avatar_key(&user_id).await? else {
+        return Err(StatusError::not_found().brief("User has no avatar.").into());
+    };
+    res.render(Redirect::found(storage::signed_url(
+        &depot.state().config,
+        &key,
+    )));
+    Ok(())
+}
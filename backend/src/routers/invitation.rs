@@ -0,0 +1,259 @@
+use chrono::Utc;
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use validator::Validate;
+
+use super::auth::set_token_cookie;
+use super::user::{CreateInData, create_account};
+use crate::hoops::{CurrentWorkspace, jwt};
+use crate::invitations::{self, Invitation, Invitee, Received, Status};
+use crate::state::AppStateDepotExt;
+use crate::workspaces::Role;
+use crate::{AppResult, EmptyResult, JsonResult, db, empty_ok, json_ok, repositories};
+
+/// An invitation with the token to hand to the invitee, who answers it with `accept` or
+/// `decline`.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct SentInvitation {
+    #[serde(flatten)]
+    pub invitation: Invitation,
+    pub token: String,
+}
+
+fn sent(depot: &Depot, invitation: Invitation) -> AppResult<SentInvitation> {
+    let token = invitations::sign(&depot.state().config.jwt, &invitation)?;
+    Ok(SentInvitation { invitation, token })
+}
+
+/// Pending invitations of the current workspace, expired ones included.
+#[endpoint(tags("invitations"))]
+pub async fn list_invitations(depot: &mut Depot) -> JsonResult<Vec<Invitation>> {
+    let workspace = CurrentWorkspace::of(depot)?.clone();
+    let conn = db::transaction(depot).await?;
+    json_ok(invitations::list_pending(conn, &workspace.id).await?)
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+struct InviteInData {
+    #[validate(email(message = "email is not valid"))]
+    email: Option<String>,
+    username: Option<String>,
+    #[serde(default = "default_role")]
+    role: Role,
+}
+
+fn default_role() -> Role {
+    Role::Member
+}
+
+/// Invites someone to the current workspace by email or username. Only owners may invite owners.
+#[endpoint(tags("invitations"))]
+pub async fn create_invitation(
+    idata: JsonBody<InviteInData>,
+    depot: &mut Depot,
+) -> JsonResult<SentInvitation> {
+    let idata = idata.into_inner();
+    idata.validate()?;
+    let invitee = match (idata.email, idata.username) {
+        (Some(email), None) => Invitee::Email(email.trim().to_owned()),
+        (None, Some(username)) if !username.trim().is_empty() => {
+            Invitee::Username(username.trim().to_owned())
+        }
+        _ => {
+            return Err(StatusError::bad_request()
+                .brief("Give either `email` or `username`.")
+                .into());
+        }
+    };
+    let workspace = CurrentWorkspace::of(depot)?.clone();
+    if idata.role == Role::Owner && workspace.role != Role::Owner {
+        return Err(StatusError::forbidden()
+            .brief("Only owners can invite owners.")
+            .into());
+    }
+    let uid = jwt::current_uid(depot).unwrap_or_default().to_owned();
+    let ttl = depot.state().config.user.invitation_ttl;
+    let id = Ulid::new().to_string();
+    let conn = db::transaction(depot).await?;
+    let invitation =
+        invitations::create(conn, &workspace.id, &id, &invitee, idata.role, &uid, ttl).await?;
+    json_ok(sent(depot, invitation)?)
+}
+
+/// Issues a new token for a pending invitation and restarts its expiry. Earlier tokens stop
+/// working.
+#[endpoint(tags("invitations"), parameters(("invitation_id", description = "invitation id")))]
+pub async fn resend_invitation(
+    invitation_id: PathParam<String>,
+    depot: &mut Depot,
+) -> JsonResult<SentInvitation> {
+    let workspace = CurrentWorkspace::of(depot)?.clone();
+    let ttl = depot.state().config.user.invitation_ttl;
+    let conn = db::transaction(depot).await?;
+    let Some(invitation) =
+        invitations::resend(conn, &workspace.id, &invitation_id.into_inner(), ttl).await?
+    else {
+        return Err(StatusError::not_found()
+            .brief("No pending invitation with this id.")
+            .into());
+    };
+    json_ok(sent(depot, invitation)?)
+}
+
+#[endpoint(tags("invitations"), parameters(("invitation_id", description = "invitation id")))]
+pub async fn revoke_invitation(invitation_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
+    let workspace = CurrentWorkspace::of(depot)?.clone();
+    let conn = db::transaction(depot).await?;
+    if !invitations::revoke(conn, &workspace.id, &invitation_id.into_inner()).await? {
+        return Err(StatusError::not_found()
+            .brief("No pending invitation with this id.")
+            .into());
+    }
+    empty_ok()
+}
+
+fn gone() -> StatusError {
+    StatusError::gone().brief("This invitation was already answered, revoked, or has expired.")
+}
+
+/// The invitation `token` was issued for, 410 unless it can still be answered.
+async fn received(depot: &mut Depot, token: &str) -> AppResult<(Received, i32)> {
+    let (id, version) = invitations::verify(&depot.state().config.jwt, token)?;
+    let conn = db::transaction(depot).await?;
+    match invitations::for_token(conn, &id, version).await? {
+        Some(invitation)
+            if invitation.status == Status::Pending && invitation.expires_at > Utc::now() =>
+        {
+            Ok((invitation, version))
+        }
+        _ => Err(gone().into()),
+    }
+}
+
+/// The account accepting `invitation`: the logged-in user if they are the invitee, otherwise a
+/// new account made from `idata` with the invited email or username.
+async fn invitee_account(
+    depot: &mut Depot,
+    invitation: &Received,
+    idata: AcceptInData,
+) -> AppResult<String> {
+    if let Some(uid) = jwt::current_uid(depot).map(str::to_owned) {
+        let Some(user) = repositories::users(depot)?.find(&uid).await? else {
+            return Err(StatusError::unauthorized().into());
+        };
+        let user = user.user;
+        let matches = match (&invitation.email, &invitation.username) {
+            (Some(email), _) => user
+                .email
+                .as_deref()
+                .is_some_and(|own| own.eq_ignore_ascii_case(email)),
+            (_, Some(username)) => &user.username == username,
+            _ => false,
+        };
+        if !matches {
+            return Err(StatusError::forbidden()
+                .brief("This invitation is for someone else.")
+                .into());
+        }
+        return Ok(uid);
+    }
+    let (Some(username), Some(password)) = (
+        idata.username.or_else(|| invitation.username.clone()),
+        idata.password,
+    ) else {
+        return Err(StatusError::unauthorized()
+            .brief("Log in, or give a username and password to create an account.")
+            .into());
+    };
+    if invitation
+        .username
+        .as_ref()
+        .is_some_and(|invited| invited != &username)
+    {
+        return Err(StatusError::bad_request()
+            .brief("The account must use the invited username.")
+            .into());
+    }
+    let user = create_account(
+        CreateInData {
+            username,
+            password,
+            email: invitation.email.clone().or(idata.email),
+            display_name: idata.display_name,
+        },
+        depot,
+    )
+    .await?;
+    Ok(user.id)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct AcceptInData {
+    token: String,
+    /// For a new account, when not logged in. Defaults to the invited username.
+    username: Option<String>,
+    password: Option<String>,
+    /// For a new account invited by username.
+    email: Option<String>,
+    display_name: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct AcceptOutData {
+    pub user_id: String,
+    pub workspace_id: String,
+    /// Login token acting in the workspace joined, also set as the `jwt_token` cookie.
+    pub token: String,
+    pub exp: i64,
+}
+
+/// Joins the workspace of an invitation, creating the account first when the caller is not
+/// logged in.
+#[endpoint(tags("invitations"))]
+pub async fn accept_invitation(
+    idata: JsonBody<AcceptInData>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> JsonResult<AcceptOutData> {
+    let idata = idata.into_inner();
+    let (invitation, version) = received(depot, &idata.token).await?;
+    let user_id = invitee_account(depot, &invitation, idata).await?;
+    let conn = db::transaction(depot).await?;
+    let Some(workspace_id) =
+        invitations::respond(conn, &invitation.id, version, Some(&user_id), true).await?
+    else {
+        return Err(gone().into());
+    };
+    let (token, exp) = jwt::generate_jwt_token(
+        &depot.state().config.jwt,
+        &user_id,
+        Some(workspace_id.clone()),
+    )?;
+    set_token_cookie(depot, res, &token);
+    json_ok(AcceptOutData {
+        user_id,
+        workspace_id,
+        token,
+        exp,
+    })
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct DeclineInData {
+    token: String,
+}
+
+#[endpoint(tags("invitations"))]
+pub async fn decline_invitation(idata: JsonBody<DeclineInData>, depot: &mut Depot) -> EmptyResult {
+    let (id, version) = invitations::verify(&depot.state().config.jwt, &idata.into_inner().token)?;
+    let conn = db::transaction(depot).await?;
+    if invitations::respond(conn, &id, version, None, false)
+        .await?
+        .is_none()
+    {
+        return Err(gone().into());
+    }
+    empty_ok()
+}
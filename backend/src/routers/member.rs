@@ -0,0 +1,129 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use serde::Deserialize;
+
+use crate::hoops::{CurrentWorkspace, jwt};
+use crate::workspaces::{self, Member, Role};
+use crate::{AppResult, EmptyResult, JsonResult, db, empty_ok, json_ok};
+
+/// Fails with 409 when `workspace_id` would be left without an owner by taking ownership away
+/// from one of them.
+async fn ensure_other_owner(depot: &mut Depot, workspace_id: &str) -> AppResult<()> {
+    let conn = db::transaction(depot).await?;
+    if workspaces::lock_owners(conn, workspace_id).await? <= 1 {
+        return Err(StatusError::conflict()
+            .brief("A workspace must keep at least one owner.")
+            .into());
+    }
+    Ok(())
+}
+
+/// The members of the current workspace.
+#[endpoint(tags("members"))]
+pub async fn list_members(depot: &mut Depot) -> JsonResult<Vec<Member>> {
+    let workspace = CurrentWorkspace::of(depot)?.clone();
+    let conn = db::transaction(depot).await?;
+    json_ok(workspaces::members(conn, &workspace.id).await?)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct RoleInData {
+    role: Role,
+}
+
+/// Changes the role of a member. Only admins may change roles, and only owners may make or
+/// unmake owners.
+#[endpoint(tags("members"), parameters(("user_id", description = "user id")))]
+pub async fn update_member(
+    user_id: PathParam<String>,
+    idata: JsonBody<RoleInData>,
+    depot: &mut Depot,
+) -> EmptyResult {
+    let user_id = user_id.into_inner();
+    let role = idata.into_inner().role;
+    let workspace = CurrentWorkspace::of(depot)?.clone();
+    if !workspace.role.can_manage() {
+        return Err(StatusError::forbidden()
+            .brief("Workspace admin permission required.")
+            .into());
+    }
+    let conn = db::transaction(depot).await?;
+    let Some(current) = workspaces::role(conn, &workspace.id, &user_id).await? else {
+        return Err(StatusError::not_found().brief("Member not found.").into());
+    };
+    if (current == Role::Owner || role == Role::Owner) && workspace.role != Role::Owner {
+        return Err(StatusError::forbidden()
+            .brief("Only owners can change ownership.")
+            .into());
+    }
+    if current == Role::Owner && role != Role::Owner {
+        ensure_other_owner(depot, &workspace.id).await?;
+    }
+    let conn = db::transaction(depot).await?;
+    workspaces::set_role(conn, &workspace.id, &user_id, role).await?;
+    empty_ok()
+}
+
+/// Removes a member from the current workspace: admins may remove others, anyone may leave.
+/// The removed user loses access with their next request.
+#[endpoint(tags("members"), parameters(("user_id", description = "user id")))]
+pub async fn remove_member(user_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
+    let user_id = user_id.into_inner();
+    let workspace = CurrentWorkspace::of(depot)?.clone();
+    let leaving = jwt::current_uid(depot) == Some(user_id.as_str());
+    if !leaving && !workspace.role.can_manage() {
+        return Err(StatusError::forbidden()
+            .brief("Workspace admin permission required.")
+            .into());
+    }
+    let conn = db::transaction(depot).await?;
+    let Some(current) = workspaces::role(conn, &workspace.id, &user_id).await? else {
+        return Err(StatusError::not_found().brief("Member not found.").into());
+    };
+    if current == Role::Owner {
+        if !leaving && workspace.role != Role::Owner {
+            return Err(StatusError::forbidden()
+                .brief("Only owners can remove an owner.")
+                .into());
+        }
+        ensure_other_owner(depot, &workspace.id).await?;
+    }
+    let conn = db::transaction(depot).await?;
+    workspaces::remove_member(conn, &workspace.id, &user_id).await?;
+    empty_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::prelude::*;
+    use salvo::test::{ResponseExt, TestClient};
+
+    use crate::hoops::CurrentWorkspace;
+    use crate::workspaces::Role;
+
+    #[handler]
+    async fn as_member(depot: &mut Depot) {
+        depot.inject(CurrentWorkspace {
+            id: "w1".into(),
+            role: Role::Member,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_member_cannot_change_roles() {
+        let router = Router::with_path("members/{user_id}")
+            .hoop(as_member)
+            .patch(super::update_member);
+        let mut res = TestClient::patch("http://127.0.0.1/members/u1")
+            .json(&serde_json::json!({"role": "admin"}))
+            .send(&Service::new(router))
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::FORBIDDEN));
+        assert!(
+            res.take_string()
+                .await
+                .unwrap()
+                .contains("admin permission")
+        );
+    }
+}
@@ -1,41 +1,206 @@
 use rust_embed::RustEmbed;
 use salvo::prelude::*;
+use salvo::request_id::RequestId;
 use salvo::serve_static::{static_embed, EmbeddedFileExt};
 
+mod admin;
 mod auth;
 mod demo;
+mod document;
+mod file;
+mod health;
+mod invitation;
+mod member;
+mod place;
 mod user;
+mod user_bulk;
+mod user_events;
+mod webhook;
+mod workspace;
 
-use crate::{config, hoops};
+use crate::config::ServerConfig;
+use crate::hoops;
 
 #[derive(RustEmbed)]
 #[folder = "assets"]
 struct Assets;
 
-pub fn root() -> Router {
+pub fn root(config: &ServerConfig) -> Router {
     let favicon = Assets::get("favicon.ico")
         .expect("favicon not found")
         .into_handler();
     let router = Router::new()
+        .hoop(RequestId::new())
         .hoop(Logger::new())
         .get(demo::hello)
+        .push(Router::with_path("healthz").get(health::healthz))
+        .push(Router::with_path("readyz").get(health::readyz))
         .push(Router::with_path("login").get(auth::login_page))
         .push(Router::with_path("users").get(user::list_page))
         .push(
             Router::with_path("api")
-                .push(Router::with_path("login").post(auth::post_login))
+                .hoop(hoops::read_your_writes)
+                .hoop(hoops::unit_of_work)
+                .hoop(hoops::audit_context)
+                .push(
+                    Router::with_path("login")
+                        .hoop(hoops::rate_limit(&config.rate_limit, "login"))
+                        .hoop(hoops::idempotency_key)
+                        .post(auth::post_login),
+                )
+                .push(
+                    Router::with_path("admin")
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .hoop(hoops::admin_guard)
+                        .push(Router::with_path("tasks").get(admin::list_tasks))
+                        .push(
+                            Router::with_path("audit")
+                                .get(admin::list_audit_events)
+                                .push(Router::with_path("verify").get(admin::verify_audit_chain)),
+                        )
+                        .push(
+                            Router::with_path("webhooks")
+                                .get(webhook::list_webhooks)
+                                .post(webhook::create_webhook)
+                                .push(
+                                    Router::with_path("deliveries/{delivery_id}/replay")
+                                        .post(webhook::replay_delivery),
+                                )
+                                .push(
+                                    Router::with_path("{webhook_id}")
+                                        .get(webhook::get_webhook)
+                                        .patch(webhook::update_webhook)
+                                        .delete(webhook::delete_webhook)
+                                        .push(
+                                            Router::with_path("deliveries")
+                                                .get(webhook::list_deliveries),
+                                        ),
+                                ),
+                        ),
+                )
+                .push(
+                    Router::with_path("me")
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .push(Router::with_path("avatar").post(file::upload_avatar)),
+                )
+                .push(
+                    Router::with_path("documents")
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .hoop(hoops::workspace_scope)
+                        .hoop(hoops::workspace_required)
+                        .push(Router::with_path("search").post(document::search_documents))
+                        .push(
+                            Router::with_path("{document_id}")
+                                .get(document::get_document)
+                                .put(document::put_document)
+                                .delete(document::delete_document),
+                        ),
+                )
+                .push(
+                    Router::with_path("files")
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .post(file::upload_file),
+                )
+                .push(
+                    Router::with_path("invitations")
+                        .push(
+                            Router::with_path("accept")
+                                .hoop(hoops::optional_auth_hoop(&config.jwt))
+                                .post(invitation::accept_invitation),
+                        )
+                        .push(Router::with_path("decline").post(invitation::decline_invitation))
+                        .push(
+                            Router::new()
+                                .hoop(hoops::auth_hoop(&config.jwt))
+                                .hoop(hoops::workspace_scope)
+                                .hoop(hoops::workspace_admin)
+                                .get(invitation::list_invitations)
+                                .post(invitation::create_invitation)
+                                .push(
+                                    Router::with_path("{invitation_id}")
+                                        .delete(invitation::revoke_invitation)
+                                        .push(
+                                            Router::with_path("resend")
+                                                .post(invitation::resend_invitation),
+                                        ),
+                                ),
+                        ),
+                )
+                .push(
+                    Router::with_path("members")
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .hoop(hoops::workspace_scope)
+                        .hoop(hoops::workspace_required)
+                        .get(member::list_members)
+                        .push(
+                            Router::with_path("{user_id}")
+                                .patch(member::update_member)
+                                .delete(member::remove_member),
+                        ),
+                )
+                .push(
+                    Router::with_path("places")
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .hoop(hoops::workspace_scope)
+                        .hoop(hoops::workspace_required)
+                        .get(place::list_places)
+                        .post(place::create_place)
+                        .push(Router::with_path("nearby").get(place::nearby_places))
+                        .push(
+                            Router::with_path("{place_id}")
+                                .get(place::get_place)
+                                .put(place::put_place)
+                                .delete(place::delete_place),
+                        ),
+                )
+                .push(
+                    Router::with_path("workspaces")
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .hoop(hoops::workspace_scope)
+                        .get(workspace::list_workspaces)
+                        .post(workspace::create_workspace)
+                        .push(
+                            Router::with_path("{workspace_id}/switch")
+                                .post(workspace::switch_workspace),
+                        ),
+                )
                 .push(
                     Router::with_path("users")
-                        .hoop(hoops::auth_hoop(&config::get().jwt))
-                        .get(user::list_users)
-                        .post(user::create_user)
+                        .hoop(hoops::auth_hoop(&config.jwt))
+                        .push(Router::new().hoop(hoops::admin_guard).get(user::list_users))
+                        .push(
+                            Router::new()
+                                .hoop(hoops::rate_limit(&config.rate_limit, "users"))
+                                .hoop(hoops::idempotency_key)
+                                .post(user::create_user),
+                        )
+                        .push(
+                            Router::with_path("import")
+                                .hoop(hoops::admin_guard)
+                                .post(user_bulk::import_users),
+                        )
+                        .push(
+                            Router::with_path("export")
+                                .hoop(hoops::admin_guard)
+                                .get(user_bulk::export_users),
+                        )
+                        .push(Router::with_path("events").get(user_events::user_events))
                         .push(
                             Router::with_path("{user_id}")
+                                .get(user::get_user)
                                 .put(user::update_user)
-                                .delete(user::delete_user),
+                                .patch(user::patch_user)
+                                .push(Router::with_path("avatar").get(file::user_avatar))
+                                .delete(user::delete_user)
+                                .push(
+                                    Router::with_path("restore")
+                                        .hoop(hoops::admin_guard)
+                                        .post(user::restore_user),
+                                ),
                         ),
                 ),
         )
+        .push(Router::with_path("files/{**key}").get(file::download_file))
         .push(Router::with_path("favicon.ico").get(favicon))
         .push(Router::with_path("assets/{**rest}").get(static_embed::<Assets>()));
     let doc = OpenApi::new("salvo web api", "0.0.1").merge_router(&router);
@@ -0,0 +1,210 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use ulid::Ulid;
+
+use crate::places::{self, BoundingBox, FeatureCollection, Geometry, Place};
+use crate::{AppResult, EmptyResult, JsonResult, db, empty_ok, json_ok};
+
+const MAX_K: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// 400 unless `lng` and `lat` are a longitude and a latitude in degrees.
+fn check_lng_lat(lng: f64, lat: f64) -> AppResult<()> {
+    if !(-180.0..=180.0).contains(&lng) || !(-90.0..=90.0).contains(&lat) {
+        return Err(StatusError::bad_request()
+            .brief("Longitude must be between -180 and 180, latitude between -90 and 90.")
+            .into());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+enum FeatureType {
+    Feature,
+}
+
+/// A GeoJSON feature with a point geometry and a non-empty `name` property.
+#[derive(Deserialize, Debug, ToSchema)]
+struct PlaceInData {
+    /// Only checked by deserializing: it must be `"Feature"`.
+    #[allow(dead_code)]
+    #[serde(rename = "type")]
+    kind: FeatureType,
+    geometry: Geometry,
+    properties: Map<String, Value>,
+}
+
+/// The name and the remaining properties of `idata`, once its location is checked.
+fn split_place(mut idata: PlaceInData) -> AppResult<(String, Value, Geometry)> {
+    let (lng, lat) = idata.geometry.lng_lat();
+    check_lng_lat(lng, lat)?;
+    let name = match idata.properties.remove("name") {
+        Some(Value::String(name)) if !name.trim().is_empty() => name,
+        _ => {
+            return Err(StatusError::bad_request()
+                .brief("`properties.name` must be a non-empty string.")
+                .into());
+        }
+    };
+    Ok((name, Value::Object(idata.properties), idata.geometry))
+}
+
+/// Creates a place with a new id.
+#[endpoint(tags("places"))]
+pub async fn create_place(idata: JsonBody<PlaceInData>, depot: &mut Depot) -> JsonResult<Place> {
+    let (name, properties, geometry) = split_place(idata.into_inner())?;
+    let id = Ulid::new().to_string();
+    let conn = db::transaction(depot).await?;
+    json_ok(places::upsert(conn, &id, &name, &properties, geometry).await?)
+}
+
+/// Creates the place or replaces it.
+#[endpoint(tags("places"), parameters(("place_id", description = "place id")))]
+pub async fn put_place(
+    place_id: PathParam<String>,
+    idata: JsonBody<PlaceInData>,
+    depot: &mut Depot,
+) -> JsonResult<Place> {
+    let (name, properties, geometry) = split_place(idata.into_inner())?;
+    let conn = db::transaction(depot).await?;
+    json_ok(places::upsert(conn, &place_id.into_inner(), &name, &properties, geometry).await?)
+}
+
+#[endpoint(tags("places"), parameters(("place_id", description = "place id")))]
+pub async fn get_place(place_id: PathParam<String>, depot: &mut Depot) -> JsonResult<Place> {
+    let mut tx = db::read_transaction(depot).await?;
+    let Some(place) = places::find(&mut tx, &place_id.into_inner()).await? else {
+        return Err(StatusError::not_found().brief("Place not found.").into());
+    };
+    json_ok(place)
+}
+
+#[endpoint(tags("places"), parameters(("place_id", description = "place id")))]
+pub async fn delete_place(place_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
+    let conn = db::transaction(depot).await?;
+    if !places::delete(conn, &place_id.into_inner()).await? {
+        return Err(StatusError::not_found().brief("Place not found.").into());
+    }
+    empty_ok()
+}
+
+#[derive(Debug, Deserialize, Extractible, ToSchema)]
+#[salvo(extract(default_source(from = "query")))]
+struct NearbyQuery {
+    lat: f64,
+    lng: f64,
+    /// Metres around the point; without it only `k` bounds the results.
+    radius: Option<f64>,
+    /// Number of places to return, at most 100.
+    #[serde(default = "default_k")]
+    k: i64,
+}
+
+fn default_k() -> i64 {
+    10
+}
+
+/// The places nearest to a point, closest first, with their distance in metres.
+#[endpoint(tags("places"))]
+pub async fn nearby_places(req: &mut Request, depot: &mut Depot) -> JsonResult<FeatureCollection> {
+    let query: NearbyQuery = req.extract(depot).await?;
+    check_lng_lat(query.lng, query.lat)?;
+    if query
+        .radius
+        .is_some_and(|radius| !(radius.is_finite() && radius >= 0.0))
+    {
+        return Err(StatusError::bad_request()
+            .brief("`radius` must be a non-negative number of metres.")
+            .into());
+    }
+    if !(1..=MAX_K).contains(&query.k) {
+        return Err(StatusError::bad_request()
+            .brief(format!("`k` must be between 1 and {MAX_K}."))
+            .into());
+    }
+    let center = Geometry::point(query.lng, query.lat);
+    let mut tx = db::read_transaction(depot).await?;
+    json_ok(
+        places::nearby(&mut tx, center, query.radius, query.k)
+            .await?
+            .into(),
+    )
+}
+
+#[derive(Debug, Deserialize, Extractible, ToSchema)]
+#[salvo(extract(default_source(from = "query")))]
+struct ListQuery {
+    /// `west,south,east,north` in degrees; `west` greater than `east` crosses the antimeridian.
+    bbox: String,
+    /// Number of places to return, at most 1000.
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// 400 unless `bbox` is four comma-separated coordinates with `south` not above `north`.
+fn parse_bbox(bbox: &str) -> AppResult<BoundingBox> {
+    let invalid = || {
+        StatusError::bad_request()
+            .brief("`bbox` must be `west,south,east,north` in degrees.")
+            .into()
+    };
+    let edges: Vec<f64> = bbox
+        .split(',')
+        .map(|edge| edge.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let [west, south, east, north] = edges[..] else {
+        return Err(invalid());
+    };
+    check_lng_lat(west, south)?;
+    check_lng_lat(east, north)?;
+    if south > north {
+        return Err(invalid());
+    }
+    Ok(BoundingBox {
+        west,
+        south,
+        east,
+        north,
+    })
+}
+
+/// The places inside a map viewport.
+#[endpoint(tags("places"))]
+pub async fn list_places(req: &mut Request, depot: &mut Depot) -> JsonResult<FeatureCollection> {
+    let query: ListQuery = req.extract(depot).await?;
+    let bbox = parse_bbox(&query.bbox)?;
+    if !(1..=MAX_LIMIT).contains(&query.limit) {
+        return Err(StatusError::bad_request()
+            .brief(format!("`limit` must be between 1 and {MAX_LIMIT}."))
+            .into());
+    }
+    let mut tx = db::read_transaction(depot).await?;
+    json_ok(places::within(&mut tx, bbox, query.limit).await?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_bbox;
+
+    #[test]
+    fn test_parse_bbox() {
+        let bbox = parse_bbox("-10.5, 40, 5,50").unwrap();
+        assert_eq!(
+            (bbox.west, bbox.south, bbox.east, bbox.north),
+            (-10.5, 40.0, 5.0, 50.0)
+        );
+        // Across the antimeridian.
+        assert!(parse_bbox("170,-20,-170,20").is_ok());
+        assert!(parse_bbox("0,50,10,40").is_err());
+        assert!(parse_bbox("0,0,10").is_err());
+        assert!(parse_bbox("0,0,10,x").is_err());
+        assert!(parse_bbox("0,0,190,10").is_err());
+    }
+}
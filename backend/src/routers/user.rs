@@ -6,8 +6,13 @@ use ulid::Ulid;
 use validator::Validate;
 use crate::hoops::jwt;
 
-use crate::models::SafeUser;
-use crate::{db, empty_ok, json_ok, utils, AppResult, EmptyResult, JsonResult};
+use crate::config::UserConfig;
+use crate::models::{SafeUser, UserListItem};
+use crate::repositories::{
+    self, NewAuditEvent, NewUser, UpdateOutcome, UserChanges, UserFilter, UserRepository,
+};
+use crate::state::AppStateDepotExt;
+use crate::{AppResult, EmptyResult, JsonResult, audit, empty_ok, json_ok, utils, webhooks};
 
 #[derive(Template)]
 #[template(path = "user_list_page.html")]
@@ -18,12 +23,12 @@ pub struct UserListPageTemplate {}
 pub struct UserListFragTemplate {}
 
 #[handler]
-pub async fn list_page(req: &mut Request, res: &mut Response) -> AppResult<()> {
+pub async fn list_page(req: &mut Request, depot: &mut Depot, res: &mut Response) -> AppResult<()> {
 
     // 1. Extract and Validate the token from ALL sources
     let jwt_token = utils::extract_jwt_token_manually(req).await;
     // If token is missing OR invalid/expired, redirect to login immediately
-    if jwt_token.is_none() || !jwt::is_jwt_token_valid(&jwt_token.unwrap()) {
+    if jwt_token.is_none() || !jwt::is_jwt_token_valid(&depot.state().config.jwt, &jwt_token.unwrap()) {
         // For HTMX/Fragments, you might want to send a 401 or a special header
         if req.headers().contains_key("hx-request") {
             res.headers_mut().insert("HX-Redirect", "/login".parse().unwrap());
@@ -54,26 +59,127 @@ pub struct CreateInData {
     pub username: String,
     #[validate(length(min = 6, message = "password length must be greater than 5"))]
     pub password: String,
+    #[serde(default)]
+    #[validate(email(message = "email is not valid"))]
+    pub email: Option<String>,
+    #[serde(default)]
+    #[validate(length(max = 255, message = "display name must be at most 255 characters"))]
+    pub display_name: Option<String>,
 }
+/// Fails with 409 when `username` is held by another live user, or by a soft-deleted one while
+/// `release_username_on_delete` is off.
+async fn ensure_username_available(
+    users: &mut dyn UserRepository,
+    config: &UserConfig,
+    username: &str,
+    except_id: &str,
+) -> AppResult<()> {
+    let release = config.release_username_on_delete;
+    if users.username_taken(username, except_id, release).await? {
+        return Err(StatusError::conflict()
+            .brief("Username is already taken.")
+            .into());
+    }
+    Ok(())
+}
+
 #[endpoint(tags("users"))]
-pub async fn create_user(idata: JsonBody<CreateInData>) -> JsonResult<SafeUser> {
-    let CreateInData { username, password } = idata.into_inner();
+pub async fn create_user(idata: JsonBody<CreateInData>, depot: &mut Depot) -> JsonResult<SafeUser> {
+    json_ok(create_account(idata.into_inner(), depot).await?)
+}
+
+/// Validates `idata` and creates the account, failing with 409 when the username is taken.
+pub async fn create_account(idata: CreateInData, depot: &mut Depot) -> AppResult<SafeUser> {
+    idata.validate()?;
+    let CreateInData {
+        username,
+        password,
+        email,
+        display_name,
+    } = idata;
     let id = Ulid::new().to_string();
     let password = utils::hash_password(&password)?;
-    let conn = db::pool();
-    let _ = sqlx::query!(
-        r#"
-            INSERT INTO users (id, username, password)
-            VALUES ($1, $2, $3)
-            "#,
+    let config = depot.state().config.clone();
+    let mut users = repositories::users(depot)?;
+    ensure_username_available(&mut *users, &config.user, &username, &id).await?;
+    users
+        .create(NewUser {
+            id: id.clone(),
+            username: username.clone(),
+            password,
+            email: email.clone(),
+            display_name: display_name.clone(),
+        })
+        .await?;
+    drop(users);
+
+    let user = SafeUser {
         id,
         username,
-        password,
-    )
-    .execute(conn)
-    .await?;
+        email,
+        display_name,
+        avatar_url: None,
+    };
+    account_created(depot, &user).await?;
+    Ok(user)
+}
+
+/// Records the creation of `user` and publishes it to webhooks in the request transaction, for
+/// every route that creates accounts.
+pub async fn account_created(depot: &mut Depot, user: &SafeUser) -> AppResult<()> {
+    let event = NewAuditEvent::new(audit::USER_CREATE)
+        .target("user", &user.id)
+        .diff(audit::diff(None, Some(user)));
+    audit::record(depot, event).await?;
+    webhooks::publish(depot, webhooks::USER_CREATED, user).await
+}
 
-    json_ok(SafeUser { id, username })
+#[endpoint(tags("users"), parameters(("user_id", description = "user id")))]
+pub async fn get_user(
+    user_id: PathParam<String>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> JsonResult<SafeUser> {
+    let user_id = user_id.into_inner();
+    let Some(found) = repositories::users(depot)?.find(&user_id).await? else {
+        return Err(StatusError::not_found().brief("User not found.").into());
+    };
+    utils::set_etag(res, found.version);
+    json_ok(found.user)
+}
+
+/// Response of an update: the new user with its `ETag`, 412 if the user is still there (so the
+/// `If-Match` version was stale), 404 otherwise.
+fn updated(outcome: UpdateOutcome, res: &mut Response) -> JsonResult<SafeUser> {
+    match outcome {
+        UpdateOutcome::Updated(updated) => {
+            utils::set_etag(res, updated.version);
+            json_ok(updated.user)
+        }
+        UpdateOutcome::Stale => Err(StatusError::precondition_failed()
+            .brief("User was modified by someone else.")
+            .into()),
+        UpdateOutcome::NotFound => Err(StatusError::not_found().brief("User not found.").into()),
+    }
+}
+
+/// Audits and publishes an update that went through. The password never shows in the diff,
+/// only whether it was set.
+async fn record_update(
+    depot: &mut Depot,
+    before: Option<SafeUser>,
+    outcome: &UpdateOutcome,
+    password_set: bool,
+) -> AppResult<()> {
+    let UpdateOutcome::Updated(after) = outcome else {
+        return Ok(());
+    };
+    let event = NewAuditEvent::new(audit::USER_UPDATE)
+        .target("user", &after.user.id)
+        .diff(audit::diff(before.as_ref(), Some(&after.user)))
+        .details(serde_json::json!({"password_set": password_set}));
+    audit::record(depot, event).await?;
+    webhooks::publish(depot, webhooks::USER_UPDATED, &after.user).await
 }
 
 #[derive(Deserialize, Debug, Validate, ToSchema)]
@@ -82,55 +188,166 @@ struct UpdateInData {
     username: String,
     #[validate(length(min = 6, message = "password length must be greater than 5"))]
     password: String,
+    #[validate(email(message = "email is not valid"))]
+    email: Option<String>,
+    #[validate(length(max = 255, message = "display name must be at most 255 characters"))]
+    display_name: Option<String>,
 }
-#[endpoint(tags("users"), parameters(("user_id", description = "user id")))]
+#[endpoint(
+    tags("users"),
+    parameters(
+        ("user_id", description = "user id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the update is based on"),
+    )
+)]
 pub async fn update_user(
     user_id: PathParam<String>,
     idata: JsonBody<UpdateInData>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
 ) -> JsonResult<SafeUser> {
     let user_id = user_id.into_inner();
-    let UpdateInData { username, password } = idata.into_inner();
-    let hashed_password = utils::hash_password(&password)?;
-    let conn = db::pool();
-
-    let _ = sqlx::query!(
-        r#"
-            UPDATE users
-            SET username = $1, password = $2
-            WHERE id = $3
-            "#,
+    let idata = idata.into_inner();
+    idata.validate()?;
+    let UpdateInData {
         username,
-        hashed_password,
-        user_id,
+        password,
+        email,
+        display_name,
+    } = idata;
+    let expected = utils::if_match_versions(req);
+    let hashed_password = utils::hash_password(&password)?;
+    let config = depot.state().config.clone();
+    let mut users = repositories::users(depot)?;
+    ensure_username_available(&mut *users, &config.user, &username, &user_id).await?;
+
+    let changes = UserChanges {
+        username: Some(username),
+        password: Some(hashed_password),
+        email: Some(email),
+        display_name: Some(display_name),
+    };
+    let before = users.find(&user_id).await?.map(|found| found.user);
+    let outcome = users.update(&user_id, changes, expected.as_deref()).await?;
+    drop(users);
+    record_update(depot, before, &outcome, true).await?;
+    updated(outcome, res)
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+struct PatchInData {
+    #[validate(length(min = 5, message = "username length must be greater than 5"))]
+    username: Option<String>,
+    #[validate(length(min = 6, message = "password length must be greater than 5"))]
+    password: Option<String>,
+    /// `null` clears it.
+    #[validate(email(message = "email is not valid"))]
+    #[serde(default, deserialize_with = "utils::nullable")]
+    email: Option<Option<String>>,
+    /// `null` clears it.
+    #[validate(length(max = 255, message = "display name must be at most 255 characters"))]
+    #[serde(default, deserialize_with = "utils::nullable")]
+    display_name: Option<Option<String>>,
+}
+/// Updates only the fields present in the body, clearing the email and display name when they
+/// are `null`. The password is rehashed only when it is given.
+#[endpoint(
+    tags("users"),
+    parameters(
+        ("user_id", description = "user id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the update is based on"),
     )
-    .execute(conn)
-    .await?;
-    json_ok(SafeUser {
-        id: user_id,
+)]
+pub async fn patch_user(
+    user_id: PathParam<String>,
+    idata: JsonBody<PatchInData>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> JsonResult<SafeUser> {
+    let user_id = user_id.into_inner();
+    let idata = idata.into_inner();
+    idata.validate()?;
+    let PatchInData {
         username,
-    })
+        password,
+        email,
+        display_name,
+    } = idata;
+    let expected = utils::if_match_versions(req);
+    let hashed_password = password.as_deref().map(utils::hash_password).transpose()?;
+    let config = depot.state().config.clone();
+    let mut users = repositories::users(depot)?;
+    if let Some(username) = &username {
+        ensure_username_available(&mut *users, &config.user, username, &user_id).await?;
+    }
+
+    let password_set = hashed_password.is_some();
+    let changes = UserChanges {
+        username,
+        password: hashed_password,
+        email,
+        display_name,
+    };
+    let before = users.find(&user_id).await?.map(|found| found.user);
+    let outcome = users.update(&user_id, changes, expected.as_deref()).await?;
+    drop(users);
+    record_update(depot, before, &outcome, password_set).await?;
+    updated(outcome, res)
 }
 
+/// Soft-deletes a user. The row is kept for `retention` seconds so an admin can restore it.
 #[endpoint(tags("users"))]
-pub async fn delete_user(user_id: PathParam<String>) -> EmptyResult {
+pub async fn delete_user(user_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
     let user_id = user_id.into_inner();
-    let conn = db::pool();
-    sqlx::query!(
-        r#"
-            DELETE FROM users
-            WHERE id = $1
-            "#,
-        user_id,
-    )
-    .execute(conn)
-    .await?;
+    let mut users = repositories::users(depot)?;
+    let before = users.find(&user_id).await?.map(|found| found.user);
+    if !users.delete(&user_id).await? {
+        return Err(StatusError::not_found().brief("User not found.").into());
+    }
+    drop(users);
+    let event = NewAuditEvent::new(audit::USER_DELETE)
+        .target("user", &user_id)
+        .diff(audit::diff(before.as_ref(), None));
+    audit::record(depot, event).await?;
+    let deleted = serde_json::json!({"id": user_id});
+    webhooks::publish(depot, webhooks::USER_DELETED, &deleted).await?;
     empty_ok()
 }
 
+/// Brings back a soft-deleted user that is still inside the retention window.
+#[endpoint(tags("users"), parameters(("user_id", description = "user id")))]
+pub async fn restore_user(user_id: PathParam<String>, depot: &mut Depot) -> JsonResult<SafeUser> {
+    let user_id = user_id.into_inner();
+    let config = depot.state().config.clone();
+    let mut users = repositories::users(depot)?;
+    let Some(user) = users
+        .find_restorable(&user_id, config.user.retention)
+        .await?
+    else {
+        return Err(StatusError::not_found()
+            .brief("No restorable user with this id.")
+            .into());
+    };
+    ensure_username_available(&mut *users, &config.user, &user.username, &user_id).await?;
+    users.restore(&user_id).await?;
+    drop(users);
+    let event = NewAuditEvent::new(audit::USER_RESTORE)
+        .target("user", &user_id)
+        .diff(audit::diff(None, Some(&user)));
+    audit::record(depot, event).await?;
+    webhooks::publish(depot, webhooks::USER_RESTORED, &user).await?;
+    json_ok(user)
+}
+
 #[derive(Debug, Deserialize, Validate, Extractible, ToSchema)]
 #[salvo(extract(default_source(from = "query")))]
 pub struct UserListQuery {
     pub username: Option<String>,
+    /// Free-text search over username, email and display name. Tolerates typos and orders the
+    /// results by relevance; `username` is ignored when this is set.
+    pub q: Option<String>,
     #[serde(default = "default_page")]
     pub current_page: i64,
     #[serde(default = "default_page_size")]
@@ -142,7 +359,7 @@ fn default_page_size() -> i64 { 10 }
 
 #[derive(Debug, Serialize, ToSchema)]
 pub struct UserListResponse {
-    pub data: Vec<SafeUser>,
+    pub data: Vec<UserListItem>,
     pub total: i64,
     pub current_page: i64,
     pub page_size: i64,
@@ -151,40 +368,119 @@ pub struct UserListResponse {
 #[endpoint(tags("users"))]
 pub async fn list_users(query: &mut Request,depot: &mut Depot // 1. Add this parameter
                         ) -> JsonResult<UserListResponse> {
-    let conn = db::pool();
     let query: UserListQuery = query.extract(depot).await?;
-    let username_filter = query.username.clone().unwrap_or_default();
-    let like_pattern = format!("%{}%", username_filter);
     let offset = (query.current_page - 1) * query.page_size;
-    
-    let total = sqlx::query_scalar!(
-        r#"
-        SELECT COUNT(*) as "count!: i64" FROM users
-        WHERE username LIKE $1
-        "#,
-        like_pattern
-    )
-    .fetch_one(conn)
-    .await?;
-    
-    let users = sqlx::query_as!(
-        SafeUser,
-        r#"
-        SELECT id, username FROM users
-        WHERE username LIKE $1
-        LIMIT $2 OFFSET $3
-        "#,
-        like_pattern,
-        query.page_size,
-        offset
-    )
-    .fetch_all(conn)
-    .await?;
-    
+    let filter = UserFilter {
+        username: query.username,
+        q: query.q,
+    };
+    let (data, total) = repositories::users(depot)?
+        .list(&filter, query.page_size, offset)
+        .await?;
+
     json_ok(UserListResponse {
-        data: users,
+        data,
         total,
         current_page: query.current_page,
         page_size: query.page_size,
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::http::header::{ETAG, IF_MATCH};
+    use salvo::prelude::*;
+    use salvo::test::{ResponseExt, TestClient};
+    use serde_json::{Value, json};
+
+    use crate::state::AppState;
+
+    /// Every call gets its own state, and so its own in-memory users.
+    fn service() -> Service {
+        let router = Router::with_path("api/users")
+            .hoop(affix_state::inject(AppState::for_tests()))
+            .get(super::list_users)
+            .post(super::create_user)
+            .push(
+                Router::with_path("{user_id}")
+                    .get(super::get_user)
+                    .patch(super::patch_user)
+                    .delete(super::delete_user),
+            );
+        Service::new(router)
+    }
+
+    #[tokio::test]
+    async fn test_user_handlers_in_memory() {
+        let service = service();
+        let base = "http://127.0.0.1/api/users";
+
+        let mut res = TestClient::post(base)
+            .json(&json!({"username": "alice01", "password": "secret1", "email": "alice@example.com"}))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        let created: Value = res.take_json().await.unwrap();
+        let id = created["id"].as_str().unwrap().to_owned();
+
+        let res = TestClient::post(base)
+            .json(&json!({"username": "alice01", "password": "secret1"}))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::CONFLICT));
+
+        let res = TestClient::get(format!("{base}/{id}")).send(&service).await;
+        assert_eq!(res.headers().get(ETAG).unwrap(), "\"1\"");
+
+        let res = TestClient::patch(format!("{base}/{id}"))
+            .add_header(IF_MATCH, "\"7\"", true)
+            .json(&json!({"display_name": "Alice"}))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::PRECONDITION_FAILED));
+
+        let mut res = TestClient::patch(format!("{base}/{id}"))
+            .add_header(IF_MATCH, "\"1\"", true)
+            .json(&json!({"display_name": "Alice"}))
+            .send(&service)
+            .await;
+        assert_eq!(res.headers().get(ETAG).unwrap(), "\"2\"");
+        let patched: Value = res.take_json().await.unwrap();
+        assert_eq!(patched["email"], "alice@example.com");
+        assert_eq!(patched["display_name"], "Alice");
+
+        let res = TestClient::patch(format!("{base}/{id}"))
+            .add_header(IF_MATCH, "W/\"2\"", true)
+            .json(&json!({"email": null}))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::PRECONDITION_FAILED));
+
+        let patched: Value = TestClient::patch(format!("{base}/{id}"))
+            .add_header(IF_MATCH, "\"2\"", true)
+            .json(&json!({"email": null}))
+            .send(&service)
+            .await
+            .take_json()
+            .await
+            .unwrap();
+        assert_eq!(patched["email"], Value::Null);
+        assert_eq!(patched["display_name"], "Alice");
+
+        let listed: Value = TestClient::get(format!("{base}?q=ALICE"))
+            .send(&service)
+            .await
+            .take_json()
+            .await
+            .unwrap();
+        assert_eq!(listed["total"], 1);
+        assert_eq!(listed["data"][0]["id"], id.as_str());
+
+        let res = TestClient::delete(format!("{base}/{id}"))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        let res = TestClient::get(format!("{base}/{id}")).send(&service).await;
+        assert_eq!(res.status_code, Some(StatusCode::NOT_FOUND));
+    }
+}
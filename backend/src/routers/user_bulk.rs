@@ -0,0 +1,389 @@
+use std::collections::HashSet;
+
+use futures_util::{StreamExt, stream};
+use salvo::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use validator::Validate;
+
+use super::user::{self, CreateInData};
+use crate::models::SafeUser;
+use crate::repositories::{self, NewUser};
+use crate::state::AppStateDepotExt;
+use crate::{AppError, AppResult, JsonResult, db, json_ok, utils};
+
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl BulkFormat {
+    fn from_request(req: &Request) -> Option<Self> {
+        let content_type = req.content_type()?;
+        match content_type.essence_str() {
+            "text/csv" => Some(Self::Csv),
+            "application/x-ndjson" | "application/ndjson" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    /// Parses every row of an import body, numbering data rows from 1. Blank lines are not
+    /// rows, in either format.
+    fn parse(self, body: &[u8]) -> Vec<(usize, Result<CreateInData, String>)> {
+        match self {
+            Self::Csv => csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(body)
+                .deserialize::<CreateInData>()
+                .enumerate()
+                .map(|(i, row)| (i + 1, row.map_err(|e| e.to_string())))
+                .collect(),
+            Self::Ndjson => body
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.trim_ascii().is_empty())
+                .enumerate()
+                .map(|(i, line)| {
+                    (
+                        i + 1,
+                        serde_json::from_slice(line).map_err(|e| e.to_string()),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn encode(self, user: &SafeUser) -> Vec<u8> {
+        match self {
+            Self::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(vec![]);
+                // Writing into a Vec can only fail on serialization, and SafeUser is plain strings.
+                writer
+                    .serialize(user)
+                    .expect("user should serialize to csv");
+                writer
+                    .into_inner()
+                    .expect("csv writer should flush into a vec")
+            }
+            Self::Ndjson => {
+                let mut line = serde_json::to_vec(user).expect("user should serialize to json");
+                line.push(b'\n');
+                line
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Extractible, ToSchema)]
+#[salvo(extract(default_source(from = "query")))]
+pub struct ImportQuery {
+    /// Only validate the rows, do not write anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportRowError {
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportOutData {
+    pub dry_run: bool,
+    pub total: usize,
+    /// Rows written, or that would be written on a dry run.
+    pub imported: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+/// Creates users from a `text/csv` (with a `username,password` header) or
+/// `application/x-ndjson` body.
+///
+/// Every row goes through the same validation as `POST /api/users`. When `import_chunk_size`
+/// is `0` the file is all-or-nothing and any row error rejects it with 422; otherwise valid rows
+/// are committed chunk by chunk, each chunk in a transaction of its own that stays committed
+/// whatever happens to the next ones, and the failed ones are reported.
+#[endpoint(tags("users"))]
+pub async fn import_users(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> JsonResult<ImportOutData> {
+    let query: ImportQuery = req.extract(depot).await?;
+    let Some(format) = BulkFormat::from_request(req) else {
+        return Err(StatusError::unsupported_media_type()
+            .brief("Expected a text/csv or application/x-ndjson body.")
+            .into());
+    };
+    let state_config = depot.state().config.clone();
+    let config = &state_config.user;
+    let body = req.payload_with_max_size(config.import_max_size).await?;
+
+    let rows = format.parse(body);
+    let total = rows.len();
+    let mut errors = vec![];
+    let mut valid = vec![];
+    let mut seen = HashSet::new();
+    for (row, parsed) in rows {
+        let result = parsed.and_then(|data| {
+            data.validate().map_err(|e| e.to_string())?;
+            if !seen.insert(data.username.clone()) {
+                return Err("username appears more than once in the file".to_owned());
+            }
+            Ok(data)
+        });
+        match result {
+            Ok(data) => valid.push((row, data)),
+            Err(message) => errors.push(ImportRowError { row, message }),
+        }
+    }
+
+    let usernames: Vec<String> = valid
+        .iter()
+        .map(|(_, data)| data.username.clone())
+        .collect();
+    let taken = repositories::users(depot)?
+        .usernames_taken(&usernames, config.release_username_on_delete)
+        .await?;
+    valid.retain(|(row, data)| {
+        let available = !taken.contains(&data.username);
+        if !available {
+            errors.push(ImportRowError {
+                row: *row,
+                message: "username is already taken".to_owned(),
+            });
+        }
+        available
+    });
+    errors.sort_by_key(|e| e.row);
+
+    let all_or_nothing = config.import_chunk_size == 0;
+    if all_or_nothing && !errors.is_empty() {
+        res.status_code(StatusCode::UNPROCESSABLE_ENTITY);
+        return json_ok(ImportOutData {
+            dry_run: query.dry_run,
+            total,
+            imported: 0,
+            errors,
+        });
+    }
+    if query.dry_run {
+        return json_ok(ImportOutData {
+            dry_run: true,
+            total,
+            imported: valid.len(),
+            errors,
+        });
+    }
+
+    // Argon2 is deliberately slow; keep hundreds of hashes off the async workers.
+    let valid = tokio::task::spawn_blocking(move || {
+        valid
+            .into_iter()
+            .map(|(row, data)| {
+                let user = NewUser {
+                    id: Ulid::new().to_string(),
+                    username: data.username,
+                    password: utils::hash_password(&data.password)?,
+                    email: data.email,
+                    display_name: data.display_name,
+                };
+                Ok((row, user))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    })
+    .await
+    .map_err(|e| AppError::internal(e.to_string()))??;
+
+    let chunk_size = if all_or_nothing {
+        valid.len().max(1)
+    } else {
+        config.import_chunk_size
+    };
+    // Each chunk is a transaction of its own, committed before the next one starts, so what
+    // the request wrote before them is committed first.
+    db::uow::unit_of_work(depot)?.commit().await?;
+    let mut imported = 0;
+    for chunk in valid.chunks(chunk_size) {
+        let written = write_chunk(depot, chunk).await;
+        let uow = db::uow::unit_of_work(depot)?;
+        match written {
+            Ok(()) => {
+                uow.commit().await?;
+                imported += chunk.len();
+            }
+            Err(e) if all_or_nothing => return Err(e),
+            Err(e) => {
+                uow.rollback().await?;
+                // The database error stays in the log; it can name tables and constraints.
+                tracing::error!(error = ?e, first_row = chunk[0].0, "user import chunk failed");
+                errors.extend(chunk.iter().map(|(row, _)| ImportRowError {
+                    row: *row,
+                    message: "could not be written with the rest of its chunk".to_owned(),
+                }));
+            }
+        }
+    }
+    errors.sort_by_key(|e| e.row);
+
+    json_ok(ImportOutData {
+        dry_run: false,
+        total,
+        imported,
+        errors,
+    })
+}
+
+/// Creates the users of one chunk in the request transaction, recording and publishing them
+/// as `create_account` does.
+async fn write_chunk(depot: &mut Depot, chunk: &[(usize, NewUser)]) -> AppResult<()> {
+    let users = chunk.iter().map(|(_, user)| user.clone()).collect();
+    repositories::users(depot)?.create_many(users).await?;
+    for (_, user) in chunk {
+        let user = SafeUser {
+            id: user.id.clone(),
+            username: user.username.clone(),
+            email: user.email.clone(),
+            display_name: user.display_name.clone(),
+            avatar_url: None,
+        };
+        user::account_created(depot, &user).await?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Extractible, ToSchema)]
+#[salvo(extract(default_source(from = "query")))]
+pub struct ExportQuery {
+    pub username: Option<String>,
+    #[serde(default)]
+    pub format: BulkFormat,
+}
+
+/// Streams the live users matching the same `username` filter as `GET /api/users`, row by row,
+/// without loading the result set into memory.
+#[endpoint(tags("users"))]
+pub async fn export_users(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> AppResult<()> {
+    let query: ExportQuery = req.extract(depot).await?;
+    // The body is produced after the hoops have run, so it cannot live in the request transaction.
+    db::uow::unit_of_work(depot)?.detach().await?;
+    let format = query.format;
+    let users = repositories::users(depot)?.export(&query.username.unwrap_or_default());
+    let header = (format == BulkFormat::Csv)
+        .then(|| Ok(b"id,username,email,display_name,avatar_url\n".to_vec()));
+    let body =
+        stream::iter(header).chain(users.map(move |user| user.map(|user| format.encode(&user))));
+
+    res.add_header(CONTENT_TYPE, format.content_type(), true)?;
+    res.add_header(
+        CONTENT_DISPOSITION,
+        format!("attachment; filename=\"users.{}\"", format.extension()),
+        true,
+    )?;
+    res.stream(body);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::prelude::*;
+    use salvo::test::{ResponseExt, TestClient};
+    use serde_json::Value;
+
+    use crate::repositories::{AuditFilter, AuditRepository, UserStore};
+    use crate::state::AppState;
+    use crate::{audit, hoops};
+
+    #[tokio::test]
+    async fn test_import_and_export_in_memory() {
+        let state = AppState::for_tests();
+        let router = Router::with_path("api/users")
+            .hoop(affix_state::inject(state.clone()))
+            .hoop(hoops::unit_of_work)
+            .push(Router::with_path("import").post(super::import_users))
+            .push(Router::with_path("export").get(super::export_users));
+        let service = Service::new(router);
+        let import = |body: &'static str| {
+            TestClient::post("http://127.0.0.1/api/users/import")
+                .add_header("content-type", "text/csv", true)
+                .raw_form(body)
+                .send(&service)
+        };
+
+        let mut res = import("username,password\nbulk01,secret1\nbulk02,short\n").await;
+        assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        let report: Value = res.take_json().await.unwrap();
+        assert_eq!(report["errors"][0]["row"], 2);
+
+        let mut res = TestClient::post("http://127.0.0.1/api/users/import")
+            .add_header("content-type", "application/x-ndjson", true)
+            // Blank lines are not rows, so the short password is on row 2.
+            .raw_form(concat!(
+                "\n",
+                r#"{"username":"bulk01","password":"secret1"}"#,
+                "\n\n",
+                r#"{"username":"bulk02","password":"short"}"#,
+                "\n",
+            ))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        let report: Value = res.take_json().await.unwrap();
+        assert_eq!(report["errors"][0]["row"], 2);
+
+        let mut res = import("username,password\nbulk01,secret1\nbulk02,secret2\n").await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.take_json::<Value>().await.unwrap()["imported"], 2);
+        let UserStore::Memory { mut audit, .. } = state.users.clone() else {
+            unreachable!("tests keep users in memory");
+        };
+        let filter = AuditFilter {
+            action: Some(audit::USER_CREATE.into()),
+            ..Default::default()
+        };
+        assert_eq!(audit.list(&filter, 10, 0).await.unwrap().1, 2);
+
+        let mut res = import("username,password\nbulk02,secret2\n").await;
+        assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        let report: Value = res.take_json().await.unwrap();
+        assert_eq!(report["errors"][0]["message"], "username is already taken");
+
+        let export = "http://127.0.0.1/api/users/export?format=ndjson&username=bulk";
+        let exported = TestClient::get(export)
+            .send(&service)
+            .await
+            .take_string()
+            .await
+            .unwrap();
+        let usernames: Vec<String> = exported
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["username"].to_string())
+            .collect();
+        assert_eq!(usernames.len(), 2);
+        assert!(usernames.iter().all(|name| name.contains("bulk0")));
+    }
+}
@@ -0,0 +1,23 @@
+use futures_util::stream;
+use salvo::prelude::*;
+use salvo::sse::{SseEvent, SseKeepAlive};
+
+use crate::db::notify::Notification;
+use crate::models::UserEvents;
+use crate::state::AppStateDepotExt;
+
+/// Streams changes to users as server-sent events until the client disconnects: a `user` event
+/// with a `UserChange` per committed change, made through any instance, and a `resync` event
+/// when some were missed and anything derived from them should be reloaded.
+#[endpoint(tags("users"))]
+pub async fn user_events(depot: &mut Depot, res: &mut Response) {
+    let subscription = depot.state().db.notifications().subscribe::<UserEvents>();
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        let event = match subscription.recv().await {
+            Notification::Event(change) => SseEvent::default().name("user").json(change),
+            Notification::Missed => Ok(SseEvent::default().name("resync").text("")),
+        };
+        Some((event, subscription))
+    });
+    SseKeepAlive::new(events).stream(res);
+}
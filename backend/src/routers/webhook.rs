@@ -0,0 +1,205 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use validator::Validate;
+
+use crate::hoops::jwt;
+use crate::webhooks::{self, Delivery, Subscription, SubscriptionChanges};
+use crate::{AppResult, EmptyResult, JsonResult, db, empty_ok, json_ok, utils};
+
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// 400 unless `url` is an http(s) URL and `event_types` are known, non-empty event types.
+fn check_target(url: Option<&str>, event_types: Option<&[String]>) -> AppResult<()> {
+    if url.is_some_and(|url| !url.starts_with("https://") && !url.starts_with("http://")) {
+        return Err(StatusError::bad_request()
+            .brief("`url` must be an http or https URL.")
+            .into());
+    }
+    if let Some(event_types) = event_types {
+        let unknown = event_types
+            .iter()
+            .find(|event_type| !webhooks::EVENT_TYPES.contains(&event_type.as_str()));
+        if event_types.is_empty() || unknown.is_some() {
+            return Err(StatusError::bad_request()
+                .brief(format!(
+                    "`event_types` must list some of: {}.",
+                    webhooks::EVENT_TYPES.join(", ")
+                ))
+                .into());
+        }
+    }
+    Ok(())
+}
+
+#[endpoint(tags("webhooks"))]
+pub async fn list_webhooks(depot: &mut Depot) -> JsonResult<Vec<Subscription>> {
+    let conn = db::transaction(depot).await?;
+    json_ok(webhooks::list(conn).await?)
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+struct CreateInData {
+    #[validate(url(message = "url is not valid"))]
+    url: String,
+    event_types: Vec<String>,
+    description: Option<String>,
+}
+
+/// A new subscription with the secret its deliveries are signed with, shown only this once.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct CreatedSubscription {
+    #[serde(flatten)]
+    pub subscription: Subscription,
+    pub secret: String,
+}
+
+#[endpoint(tags("webhooks"))]
+pub async fn create_webhook(
+    idata: JsonBody<CreateInData>,
+    depot: &mut Depot,
+) -> JsonResult<CreatedSubscription> {
+    let idata = idata.into_inner();
+    idata.validate()?;
+    check_target(Some(&idata.url), Some(&idata.event_types))?;
+    let uid = jwt::current_uid(depot).map(str::to_owned);
+    let id = Ulid::new().to_string();
+    let secret = utils::random_string(40);
+    let conn = db::transaction(depot).await?;
+    let subscription = webhooks::create(
+        conn,
+        &id,
+        &idata.url,
+        &idata.event_types,
+        idata.description.as_deref(),
+        &secret,
+        uid.as_deref(),
+    )
+    .await?;
+    json_ok(CreatedSubscription {
+        subscription,
+        secret,
+    })
+}
+
+#[endpoint(tags("webhooks"), parameters(("webhook_id", description = "subscription id")))]
+pub async fn get_webhook(
+    webhook_id: PathParam<String>,
+    depot: &mut Depot,
+) -> JsonResult<Subscription> {
+    let conn = db::transaction(depot).await?;
+    let Some(subscription) = webhooks::find(conn, &webhook_id.into_inner()).await? else {
+        return Err(StatusError::not_found().brief("Webhook not found.").into());
+    };
+    json_ok(subscription)
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+struct UpdateInData {
+    #[validate(url(message = "url is not valid"))]
+    url: Option<String>,
+    event_types: Option<Vec<String>>,
+    description: Option<String>,
+    /// `true` turns a disabled subscription back on and clears its failures.
+    active: Option<bool>,
+}
+
+/// Updates only the fields present in the body.
+#[endpoint(tags("webhooks"), parameters(("webhook_id", description = "subscription id")))]
+pub async fn update_webhook(
+    webhook_id: PathParam<String>,
+    idata: JsonBody<UpdateInData>,
+    depot: &mut Depot,
+) -> JsonResult<Subscription> {
+    let idata = idata.into_inner();
+    idata.validate()?;
+    check_target(idata.url.as_deref(), idata.event_types.as_deref())?;
+    let changes = SubscriptionChanges {
+        url: idata.url,
+        event_types: idata.event_types,
+        description: idata.description.map(Some),
+        active: idata.active,
+    };
+    let conn = db::transaction(depot).await?;
+    let Some(subscription) = webhooks::update(conn, &webhook_id.into_inner(), changes).await?
+    else {
+        return Err(StatusError::not_found().brief("Webhook not found.").into());
+    };
+    json_ok(subscription)
+}
+
+/// Deletes a subscription and its delivery log.
+#[endpoint(tags("webhooks"), parameters(("webhook_id", description = "subscription id")))]
+pub async fn delete_webhook(webhook_id: PathParam<String>, depot: &mut Depot) -> EmptyResult {
+    let conn = db::transaction(depot).await?;
+    if !webhooks::delete(conn, &webhook_id.into_inner()).await? {
+        return Err(StatusError::not_found().brief("Webhook not found.").into());
+    }
+    empty_ok()
+}
+
+#[derive(Debug, Deserialize, Extractible, ToSchema)]
+#[salvo(extract(default_source(from = "query")))]
+struct DeliveriesQuery {
+    #[serde(default = "default_page")]
+    current_page: i64,
+    /// At most 100.
+    #[serde(default = "default_page_size")]
+    page_size: i64,
+}
+
+fn default_page() -> i64 {
+    1
+}
+fn default_page_size() -> i64 {
+    20
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeliveryListResponse {
+    pub data: Vec<Delivery>,
+    pub total: i64,
+    pub current_page: i64,
+    pub page_size: i64,
+}
+
+/// The delivery log of a subscription, newest first.
+#[endpoint(tags("webhooks"), parameters(("webhook_id", description = "subscription id")))]
+pub async fn list_deliveries(
+    webhook_id: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> JsonResult<DeliveryListResponse> {
+    let query: DeliveriesQuery = req.extract(depot).await?;
+    if query.current_page < 1 || !(1..=MAX_PAGE_SIZE).contains(&query.page_size) {
+        return Err(StatusError::bad_request()
+            .brief(format!(
+                "`current_page` must be positive and `page_size` between 1 and {MAX_PAGE_SIZE}."
+            ))
+            .into());
+    }
+    let webhook_id = webhook_id.into_inner();
+    let offset = (query.current_page - 1) * query.page_size;
+    let conn = db::transaction(depot).await?;
+    if webhooks::find(conn, &webhook_id).await?.is_none() {
+        return Err(StatusError::not_found().brief("Webhook not found.").into());
+    }
+    let (data, total) = webhooks::deliveries(conn, &webhook_id, query.page_size, offset).await?;
+    json_ok(DeliveryListResponse {
+        data,
+        total,
+        current_page: query.current_page,
+        page_size: query.page_size,
+    })
+}
+
+/// Sends a past delivery again, as a new delivery carrying the same event.
+#[endpoint(tags("webhooks"), parameters(("delivery_id", description = "delivery id")))]
+pub async fn replay_delivery(
+    delivery_id: PathParam<String>,
+    depot: &mut Depot,
+) -> JsonResult<Delivery> {
+    let conn = db::transaction(depot).await?;
+    json_ok(webhooks::replay(conn, &delivery_id.into_inner()).await?)
+}
@@ -0,0 +1,79 @@
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use validator::Validate;
+
+use super::auth::set_token_cookie;
+use crate::hoops::jwt;
+use crate::state::AppStateDepotExt;
+use crate::workspaces::{self, Workspace};
+use crate::{AppError, JsonResult, db, json_ok};
+
+/// Id of the user `workspace_scope` scoped this request to.
+fn current_uid(depot: &Depot) -> Result<String, AppError> {
+    jwt::current_uid(depot)
+        .map(str::to_owned)
+        .ok_or_else(|| StatusError::unauthorized().into())
+}
+
+/// The workspaces the current user belongs to.
+#[endpoint(tags("workspaces"))]
+pub async fn list_workspaces(depot: &mut Depot) -> JsonResult<Vec<Workspace>> {
+    let uid = current_uid(depot)?;
+    let conn = db::transaction(depot).await?;
+    json_ok(workspaces::list_for_user(conn, &uid).await?)
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+struct CreateInData {
+    #[validate(length(min = 1, max = 255, message = "name must be 1 to 255 characters"))]
+    name: String,
+}
+
+/// Creates a workspace owned by the current user. Their token keeps its workspace until they
+/// switch.
+#[endpoint(tags("workspaces"))]
+pub async fn create_workspace(
+    idata: JsonBody<CreateInData>,
+    depot: &mut Depot,
+) -> JsonResult<Workspace> {
+    let idata = idata.into_inner();
+    idata.validate()?;
+    let uid = current_uid(depot)?;
+    let id = Ulid::new().to_string();
+    let conn = db::transaction(depot).await?;
+    json_ok(workspaces::create(conn, &id, idata.name.trim(), &uid).await?)
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct SwitchOutData {
+    pub workspace_id: String,
+    pub token: String,
+    pub exp: i64,
+}
+
+/// A new token for the current user acting in the workspace, also set as the `jwt_token` cookie.
+#[endpoint(tags("workspaces"), parameters(("workspace_id", description = "workspace id")))]
+pub async fn switch_workspace(
+    workspace_id: PathParam<String>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> JsonResult<SwitchOutData> {
+    let workspace_id = workspace_id.into_inner();
+    let uid = current_uid(depot)?;
+    let conn = db::transaction(depot).await?;
+    if workspaces::role(conn, &workspace_id, &uid).await?.is_none() {
+        return Err(StatusError::forbidden()
+            .brief("You are not a member of this workspace.")
+            .into());
+    }
+    let (token, exp) =
+        jwt::generate_jwt_token(&depot.state().config.jwt, &uid, Some(workspace_id.clone()))?;
+    set_token_cookie(depot, res, &token);
+    json_ok(SwitchOutData {
+        workspace_id,
+        token,
+        exp,
+    })
+}
@@ -0,0 +1,104 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use salvo::async_trait;
+use ulid::Ulid;
+
+use super::Storage;
+use crate::AppResult;
+
+/// Stores files under a directory on the local filesystem.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Bytes) -> AppResult<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write next to the target and rename, so readers never see a partial file.
+        let tmp = path.with_extension(format!("{}.tmp", Ulid::new()));
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn put_file(&self, key: &str, source: &Path) -> AppResult<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = path.with_extension(format!("{}.tmp", Ulid::new()));
+        tokio::fs::copy(source, &tmp).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> AppResult<Option<Bytes>> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> AppResult<bool> {
+        Ok(tokio::fs::try_exists(self.path(key)).await?)
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_round_trip() {
+        let root = std::env::temp_dir().join(format!("backend-storage-{}", Ulid::new()));
+        let storage = LocalStorage::new(&root);
+
+        assert_eq!(storage.get("ab/missing.png").await.unwrap(), None);
+        storage
+            .put("ab/file.png", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        assert!(storage.exists("ab/file.png").await.unwrap());
+        assert_eq!(
+            storage.get("ab/file.png").await.unwrap().as_deref(),
+            Some(&b"data"[..])
+        );
+        storage.delete("ab/file.png").await.unwrap();
+        storage.delete("ab/file.png").await.unwrap();
+        assert!(!storage.exists("ab/file.png").await.unwrap());
+
+        let source = root.join("upload");
+        tokio::fs::write(&source, b"streamed").await.unwrap();
+        storage.put_file("cd/file.png", &source).await.unwrap();
+        assert_eq!(
+            storage.get("cd/file.png").await.unwrap().as_deref(),
+            Some(&b"streamed"[..])
+        );
+
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
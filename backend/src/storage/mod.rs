@@ -0,0 +1,117 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use salvo::async_trait;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tokio::io::AsyncReadExt;
+
+use crate::AppResult;
+use crate::config::{ServerConfig, StorageConfig, storage_config};
+
+mod local;
+mod s3;
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+/// Blob store behind uploaded files. Keys are relative, `/`-separated paths as produced by
+/// [`content_key`].
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Bytes) -> AppResult<()>;
+    /// Stores the file at `path` without loading it into memory.
+    async fn put_file(&self, key: &str, path: &Path) -> AppResult<()>;
+    /// Returns `None` when nothing is stored under `key`.
+    async fn get(&self, key: &str) -> AppResult<Option<Bytes>>;
+    async fn exists(&self, key: &str) -> AppResult<bool>;
+    /// Deleting a missing key is not an error.
+    #[allow(dead_code)]
+    async fn delete(&self, key: &str) -> AppResult<()>;
+}
+
+pub fn from_config(config: &StorageConfig) -> Arc<dyn Storage> {
+    match &*config.backend {
+        storage_config::BACKEND_LOCAL => Arc::new(LocalStorage::new(&config.directory)),
+        storage_config::BACKEND_S3 => {
+            let s3 = config
+                .s3
+                .as_ref()
+                .expect("storage.s3 should be set for the s3 backend");
+            Arc::new(S3Storage::new(s3).expect("S3 storage configuration failed."))
+        }
+        backend => panic!("Unknown storage backend `{backend}`"),
+    }
+}
+
+/// Key derived from the SHA-256 of the content, as returned by [`file_digest`], so identical
+/// uploads share one object.
+pub fn content_key(digest: &[u8], extension: &str) -> String {
+    let hash = hex::encode(digest);
+    format!("{}/{hash}.{extension}", &hash[..2])
+}
+
+/// The SHA-256 of the file at `path`, read a chunk at a time.
+pub async fn file_digest(path: &Path) -> AppResult<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(hasher.finalize().to_vec());
+        }
+        hasher.update(&chunk[..read]);
+    }
+}
+
+/// Whether `key` looks like a key produced by [`content_key`]. Guards backends against path
+/// traversal through user-supplied keys.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.split('/').all(|part| {
+            !part.is_empty()
+                && !part.starts_with('.')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+        })
+}
+
+fn signature(config: &ServerConfig, key: &str, expires: i64) -> Hmac<Sha256> {
+    let secret = config
+        .storage
+        .signing_secret
+        .as_deref()
+        .unwrap_or(&config.jwt.secret);
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(format!("{key}:{expires}").as_bytes());
+    mac
+}
+
+/// Download URL for `key` that stops working after `storage.url_expiry` seconds.
+pub fn signed_url(config: &ServerConfig, key: &str) -> String {
+    let expires = OffsetDateTime::now_utc().unix_timestamp() + config.storage.url_expiry;
+    let signature = hex::encode(signature(config, key, expires).finalize().into_bytes());
+    format!("/files/{key}?expires={expires}&signature={signature}")
+}
+
+/// Checks a signature produced by [`signed_url`] in constant time.
+pub fn verify_signature(
+    config: &ServerConfig,
+    key: &str,
+    expires: i64,
+    signature_hex: &str,
+) -> bool {
+    if expires < OffsetDateTime::now_utc().unix_timestamp() {
+        return false;
+    }
+    let Ok(expected) = hex::decode(signature_hex) else {
+        return false;
+    };
+    signature(config, key, expires)
+        .verify_slice(&expected)
+        .is_ok()
+}
@@ -0,0 +1,150 @@
+use bytes::Bytes;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload, WriteMultipart};
+use salvo::async_trait;
+use tokio::io::AsyncReadExt;
+
+use super::Storage;
+use crate::AppResult;
+use crate::config::S3Config;
+
+/// Size of the parts of multipart uploads, the smallest S3 accepts. Smaller files are sent in
+/// one request.
+const PART_SIZE: usize = 5 * 1024 * 1024;
+/// Parts of one upload in flight at a time.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// Stores files in an S3 bucket, or in any service speaking the S3 API.
+pub struct S3Storage {
+    store: AmazonS3,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config) -> AppResult<Self> {
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(&config.bucket)
+            .with_region(&config.region)
+            .with_access_key_id(&config.access_key_id)
+            .with_secret_access_key(&config.secret_access_key)
+            .with_allow_http(config.allow_http);
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.with_endpoint(endpoint);
+        }
+        let store = builder.build().map_err(anyhow::Error::from)?;
+        Ok(Self { store })
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Bytes) -> AppResult<()> {
+        self.store
+            .put(&Path::from(key), PutPayload::from(data))
+            .await
+            .map_err(anyhow::Error::from)?;
+        Ok(())
+    }
+
+    async fn put_file(&self, key: &str, source: &std::path::Path) -> AppResult<()> {
+        let mut file = tokio::fs::File::open(source).await?;
+        if file.metadata().await?.len() <= PART_SIZE as u64 {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).await?;
+            return self.put(key, data.into()).await;
+        }
+        let upload = self
+            .store
+            .put_multipart(&Path::from(key))
+            .await
+            .map_err(anyhow::Error::from)?;
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+        let written: AppResult<()> = async {
+            let mut chunk = vec![0; 64 * 1024];
+            loop {
+                let read = file.read(&mut chunk).await?;
+                if read == 0 {
+                    return Ok(());
+                }
+                writer
+                    .wait_for_capacity(MAX_CONCURRENT_PARTS)
+                    .await
+                    .map_err(anyhow::Error::from)?;
+                writer.write(&chunk[..read]);
+            }
+        }
+        .await;
+        match written {
+            Ok(()) => {
+                writer.finish().await.map_err(anyhow::Error::from)?;
+                Ok(())
+            }
+            Err(e) => {
+                // Parts already sent are billed until the upload is aborted.
+                if let Err(abort) = writer.abort().await {
+                    tracing::warn!(key, error = ?abort, "could not abort multipart upload");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> AppResult<Option<Bytes>> {
+        match self.store.get(&Path::from(key)).await {
+            Ok(result) => Ok(Some(result.bytes().await.map_err(anyhow::Error::from)?)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(anyhow::Error::from(e).into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> AppResult<bool> {
+        match self.store.head(&Path::from(key)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(anyhow::Error::from(e).into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        match self.store.delete(&Path::from(key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(anyhow::Error::from(e).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against a local S3 stand-in, e.g. the `minio` service of `docker/db/docker-compose.yaml`:
+    /// `S3_TEST_ENDPOINT=http://127.0.0.1:9000 cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_s3_round_trip() {
+        let env = |name: &str, default: &str| std::env::var(name).unwrap_or(default.into());
+        let storage = S3Storage::new(&S3Config {
+            bucket: env("S3_TEST_BUCKET", "backend"),
+            region: env("S3_TEST_REGION", "us-east-1"),
+            endpoint: Some(env("S3_TEST_ENDPOINT", "http://127.0.0.1:9000")),
+            access_key_id: env("S3_TEST_ACCESS_KEY_ID", "minioadmin"),
+            secret_access_key: env("S3_TEST_SECRET_ACCESS_KEY", "minioadmin"),
+            allow_http: true,
+        })
+        .unwrap();
+        let key = format!("test/{}.txt", ulid::Ulid::new());
+
+        assert_eq!(storage.get(&key).await.unwrap(), None);
+        storage
+            .put(&key, Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        assert!(storage.exists(&key).await.unwrap());
+        assert_eq!(
+            storage.get(&key).await.unwrap().as_deref(),
+            Some(&b"data"[..])
+        );
+        storage.delete(&key).await.unwrap();
+        assert!(!storage.exists(&key).await.unwrap());
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use salvo::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::AppResult;
+use crate::config::LogConfig;
+use crate::jobs::{self, Job};
+use crate::state::AppState;
+use crate::webhooks::DeliverWebhook;
+
+pub mod scheduler;
+
+pub use scheduler::Scheduler;
+
+/// The jobs the workers of this server run.
+pub fn jobs() -> jobs::Registry {
+    jobs::Registry::new()
+        .register::<PurgeDeletedUser>()
+        .register::<DeliverWebhook>()
+}
+
+/// Hard-deletes one user once its retention window has passed. Queued by the soft delete, to
+/// run when the window closes; does nothing if the user was restored in the meantime.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PurgeDeletedUser {
+    pub id: String,
+}
+
+#[async_trait]
+impl Job for PurgeDeletedUser {
+    const KIND: &'static str = "purge_deleted_user";
+
+    async fn run(self, state: &AppState) -> AppResult<()> {
+        let purged = sqlx::query!(
+            r#"
+            DELETE FROM users
+            WHERE id = $1 AND deleted_at IS NOT NULL
+              AND deleted_at <= now() - make_interval(secs => $2)
+            "#,
+            self.id,
+            state.config.user.retention as f64,
+        )
+        .execute(state.db.pool())
+        .await?
+        .rows_affected();
+        if purged > 0 {
+            tracing::info!(user = self.id, "purged soft-deleted user");
+        }
+        Ok(())
+    }
+}
+
+/// The recurring tasks of this server, with their default schedules.
+/// Sessions and reset tokens need no cleanup: tokens are stateless JWTs, and none are stored.
+pub fn scheduler() -> Scheduler {
+    Scheduler::new()
+        .task("purge_deleted_users", "0 0 * * * *", |state| {
+            Box::pin(async move {
+                let purged =
+                    purge_deleted_users(state.db.pool(), state.config.user.retention).await?;
+                Ok(format!("purged {purged} users"))
+            })
+        })
+        .task("prune_jobs", "0 15 * * * *", |state| {
+            Box::pin(async move {
+                let pruned = prune_jobs(state.db.pool(), state.config.jobs.retention).await?;
+                Ok(format!("pruned {pruned} finished jobs"))
+            })
+        })
+        .task("prune_idempotency_keys", "0 45 * * * *", |state| {
+            Box::pin(async move {
+                let pruned = state.idempotency.prune(state.db.pool()).await?;
+                Ok(format!("pruned {pruned} idempotency keys"))
+            })
+        })
+        .task("prune_rate_limits", "0 */10 * * * *", |state| {
+            Box::pin(async move {
+                let pruned = state.rate_limits.prune(state.db.pool()).await?;
+                Ok(format!("pruned {pruned} rate limit counters"))
+            })
+        })
+        .local_task("rotate_logs", "0 30 3 * * *", |state| {
+            Box::pin(async move {
+                let removed = rotate_logs(&state.config.log).await?;
+                Ok(format!("removed {removed} log files"))
+            })
+        })
+}
+
+/// Hard-deletes users deleted more than `retention` seconds ago, returning how many rows were
+/// removed. Catches the users whose [`PurgeDeletedUser`] job was dead-lettered or never queued.
+pub async fn purge_deleted_users(pool: &PgPool, retention: i64) -> AppResult<u64> {
+    let purged = sqlx::query!(
+        r#"
+            DELETE FROM users
+            WHERE deleted_at IS NOT NULL
+              AND deleted_at <= now() - make_interval(secs => $1)
+            "#,
+        retention as f64,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(purged)
+}
+
+/// Deletes jobs that finished more than `retention` seconds ago. Dead jobs are left alone.
+pub async fn prune_jobs(pool: &PgPool, retention: i64) -> AppResult<u64> {
+    let pruned = sqlx::query!(
+        r#"
+            DELETE FROM jobs
+            WHERE status = 'done' AND finished_at <= now() - make_interval(secs => $1)
+            "#,
+        retention as f64,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(pruned)
+}
+
+/// Deletes the rolled files of the log in `config.directory` last written more than
+/// `config.max_age_days` ago, returning how many were removed.
+pub async fn rotate_logs(config: &LogConfig) -> AppResult<usize> {
+    if config.max_age_days == 0 {
+        return Ok(0);
+    }
+    let max_age = Duration::from_secs(config.max_age_days * 24 * 60 * 60);
+    let prefix = format!("{}.", config.file_name);
+    let mut removed = 0;
+    let mut entries = tokio::fs::read_dir(&config.directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+            continue;
+        }
+        let metadata = entry.metadata().await?;
+        let age = metadata.modified()?.elapsed().unwrap_or_default();
+        if metadata.is_file() && age > max_age {
+            tokio::fs::remove_file(entry.path()).await?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
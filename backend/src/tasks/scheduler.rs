@@ -0,0 +1,270 @@
+//! Recurring tasks on cron schedules.
+//!
+//! Every instance runs the same clock. A shared task only does work on the instance that
+//! holds its advisory lock and claims the tick in `scheduled_tasks`, which then records the
+//! outcome in the same row. A
+//! local task, for work on the instance itself like its log files, runs on every instance and
+//! its outcome is only logged.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use futures_util::future::BoxFuture;
+use sqlx::PgConnection;
+
+use crate::AppResult;
+use crate::config::CronConfig;
+use crate::config::cron_config::SCHEDULE_OFF;
+use crate::state::AppState;
+
+/// A task returns a one-line summary of what it did, stored as its last result.
+pub type TaskFn = fn(AppState) -> BoxFuture<'static, AppResult<String>>;
+
+struct Task {
+    name: &'static str,
+    schedule: &'static str,
+    local: bool,
+    run: TaskFn,
+}
+
+/// A task with the schedule that applies after config overrides; `None` when turned off.
+pub struct Scheduled {
+    pub name: &'static str,
+    pub schedule: Option<Schedule>,
+    /// Whether every instance runs it, rather than one per tick.
+    pub local: bool,
+    run: TaskFn,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<Task>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `run` under `name` with a default `schedule`, which `[cron.schedules]` can
+    /// replace. Each tick runs on one instance.
+    pub fn task(self, name: &'static str, schedule: &'static str, run: TaskFn) -> Self {
+        self.register(name, schedule, false, run)
+    }
+
+    /// Like [`Scheduler::task`], but every instance runs every tick.
+    pub fn local_task(self, name: &'static str, schedule: &'static str, run: TaskFn) -> Self {
+        self.register(name, schedule, true, run)
+    }
+
+    fn register(
+        mut self,
+        name: &'static str,
+        schedule: &'static str,
+        local: bool,
+        run: TaskFn,
+    ) -> Self {
+        assert!(
+            self.tasks.iter().all(|task| task.name != name),
+            "task `{name}` is registered twice"
+        );
+        self.tasks.push(Task {
+            name,
+            schedule,
+            local,
+            run,
+        });
+        self
+    }
+
+    /// The tasks with their effective schedules. Panics on an invalid expression or an
+    /// override naming no task, so a typo stops the server instead of silently doing nothing.
+    pub fn resolve(self, config: &CronConfig) -> Vec<Scheduled> {
+        if let Some(name) = config
+            .schedules
+            .keys()
+            .find(|name| self.tasks.iter().all(|task| task.name != name.as_str()))
+        {
+            panic!("Unknown scheduled task `{name}` in [cron.schedules]");
+        }
+        self.tasks
+            .into_iter()
+            .map(|task| {
+                let expression = config
+                    .schedules
+                    .get(task.name)
+                    .map_or(task.schedule, String::as_str);
+                let schedule = (expression != SCHEDULE_OFF).then(|| {
+                    Schedule::from_str(expression).unwrap_or_else(|e| {
+                        panic!(
+                            "Invalid schedule `{expression}` for task `{}`: {e}",
+                            task.name
+                        )
+                    })
+                });
+                Scheduled {
+                    name: task.name,
+                    schedule,
+                    local: task.local,
+                    run: task.run,
+                }
+            })
+            .collect()
+    }
+
+    /// Starts one timer per enabled task, unless `config.enabled` is off.
+    pub fn spawn(self, config: &CronConfig, state: AppState) {
+        let tasks = self.resolve(config);
+        if !config.enabled {
+            return;
+        }
+        for task in tasks {
+            if let Some(schedule) = task.schedule.clone() {
+                tokio::spawn(drive(task, schedule, state.clone()));
+            }
+        }
+    }
+}
+
+impl Scheduled {
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.schedule.as_ref()?.upcoming(Utc).next()
+    }
+}
+
+async fn drive(task: Scheduled, schedule: Schedule, state: AppState) {
+    let mut upcoming = schedule.upcoming(Utc).next();
+    while let Some(tick) = upcoming {
+        let wait = (tick - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        if let Err(e) = run_tick(&task, tick, &state).await {
+            tracing::error!(task = task.name, error = ?e, "scheduled task could not run");
+        }
+        // Ticks missed while the task was running are skipped, not caught up.
+        upcoming = schedule.after(&tick.max(Utc::now())).next();
+    }
+}
+
+/// First key of the advisory locks held by shared tasks while they run, the second being a hash
+/// of the task name.
+const TASK_LOCK: i32 = 0x7461_736b; // "task"
+
+/// Runs `task` for `tick` if this instance is the one to do it.
+async fn run_tick(task: &Scheduled, tick: DateTime<Utc>, state: &AppState) -> AppResult<()> {
+    if task.local {
+        run(task, state).await;
+        return Ok(());
+    }
+    // A session-level advisory lock, held on a connection of its own for the whole run, keeps
+    // a run that outlasts the interval from overlapping the next tick on another instance,
+    // which skips that tick instead.
+    let mut conn = state.db.pool().acquire().await?;
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_lock($1, hashtext($2)) AS "locked!""#,
+        TASK_LOCK,
+        task.name,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !locked {
+        tracing::info!(task = task.name, %tick, "scheduled task still running elsewhere, skipping tick");
+        return Ok(());
+    }
+    let result = claim_and_run(task, tick, state, &mut conn).await;
+    let unlocked = sqlx::query_scalar!(
+        "SELECT pg_advisory_unlock($1, hashtext($2))",
+        TASK_LOCK,
+        task.name,
+    )
+    .fetch_one(&mut *conn)
+    .await;
+    if unlocked.is_err() {
+        // A connection that may still hold the lock must not go back to the pool; closing it
+        // releases the lock.
+        drop(conn.detach());
+    }
+    result
+}
+
+/// Runs `task` for `tick` unless another instance already did, recording the outcome.
+async fn claim_and_run(
+    task: &Scheduled,
+    tick: DateTime<Utc>,
+    state: &AppState,
+    conn: &mut PgConnection,
+) -> AppResult<()> {
+    // Only one instance moves `last_tick` forward to a given tick, so an instance that reaches
+    // the lock after the run of another one finished does not run the tick again.
+    let claimed = sqlx::query!(
+        r#"
+        INSERT INTO scheduled_tasks (name, last_tick) VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE SET last_tick = EXCLUDED.last_tick
+        WHERE scheduled_tasks.last_tick < EXCLUDED.last_tick
+        "#,
+        task.name,
+        tick,
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    if claimed == 0 {
+        return Ok(());
+    }
+
+    let started_at = Utc::now();
+    let (duration, status, summary) = run(task, state).await;
+    sqlx::query!(
+        r#"
+        UPDATE scheduled_tasks
+        SET last_started_at = $2, last_duration_ms = $3, last_status = $4, last_result = $5,
+            runs = runs + 1
+        WHERE name = $1
+        "#,
+        task.name,
+        started_at,
+        millis(duration),
+        status,
+        summary,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Runs `task` once and logs the outcome, returned as its duration, `ok` or `error`, and its
+/// summary or error.
+async fn run(task: &Scheduled, state: &AppState) -> (Duration, &'static str, String) {
+    let started = Instant::now();
+    // A task of its own turns a panic into a failed run.
+    let result = match tokio::spawn((task.run)(state.clone())).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(format!("task panicked: {e}")),
+    };
+    let duration = started.elapsed();
+    match result {
+        Ok(summary) => {
+            tracing::info!(
+                task = task.name,
+                duration_ms = millis(duration),
+                summary,
+                "scheduled task done"
+            );
+            (duration, "ok", summary)
+        }
+        Err(error) => {
+            tracing::error!(
+                task = task.name,
+                duration_ms = millis(duration),
+                error,
+                "scheduled task failed"
+            );
+            (duration, "error", error)
+        }
+    }
+}
+
+fn millis(duration: Duration) -> i64 {
+    duration.as_millis().try_into().unwrap_or(i64::MAX)
+}
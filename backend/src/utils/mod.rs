@@ -6,7 +6,11 @@ use rand::{ RngExt};
 use std::iter;
 use salvo::jwt_auth::{CookieFinder, HeaderFinder, JwtTokenFinder, QueryFinder};
 // added by Manish
+use salvo::http::header::{ETAG, IF_MATCH};
 use salvo::prelude::*;
+use serde::{Deserialize, Deserializer};
+
+use crate::state::AppStateDepotExt;
 
 #[allow(dead_code)]
 #[inline]
@@ -19,7 +23,7 @@ pub fn random_string(limit: usize) -> String {
 }
 
 pub fn verify_password(password: &str, password_hash: &str) -> anyhow::Result<()> {
-    let hash = PasswordHash::new(&password_hash)
+    let hash = PasswordHash::new(password_hash)
         .map_err(|e| anyhow::anyhow!("invalid password hash: {}", e))?;
     let result = hash.verify_password(&[&Argon2::default()], password);
     match result {
@@ -55,7 +59,44 @@ pub async fn extract_jwt_token_manually(req: &mut Request) -> Option<String> {
     None
 }
 /// Returns true if the server is configured to use TLS (HTTPS/Quinn)
-pub fn is_secure_context() -> bool {
-    crate::config::get().tls.is_some()
+pub fn is_secure_context(depot: &Depot) -> bool {
+    depot.state().config.tls.is_some()
+}
+
+
+/// Sets the `ETag` of a versioned resource on the response.
+pub fn set_etag(res: &mut Response, version: i64) {
+    res.headers_mut()
+        .insert(ETAG, format!("\"{version}\"").parse().unwrap());
 }
 
+/// Versions accepted by the request's `If-Match` header.
+///
+/// `None` means there is no precondition (header missing or `*`). `If-Match` uses the strong
+/// comparison, so weak tags (`W/"1"`) never match, like tags that are not versions issued by
+/// `set_etag`; either way they end up as an empty list.
+pub fn if_match_versions(req: &Request) -> Option<Vec<i64>> {
+    let header = req.headers().get(IF_MATCH)?.to_str().unwrap_or_default();
+    if header.trim() == "*" {
+        return None;
+    }
+    Some(
+        header
+            .split(',')
+            .filter_map(|tag| {
+                let tag = tag.trim();
+                tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+            })
+            .collect(),
+    )
+}
+
+/// For `#[serde(default, deserialize_with = "utils::nullable")]` on fields of partial updates:
+/// a field left out is `None`, an explicit `null` is `Some(None)`, which clears it.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,574 @@
+//! Outbound webhooks: subscriptions to event types, and their signed, retried deliveries.
+//!
+//! [`publish`] runs in the request transaction. It stores one delivery per active subscription
+//! to the event type and queues a [`DeliverWebhook`] job for each, so receivers hear only about
+//! committed changes. The job posts the payload with an HMAC-SHA256 signature (see [`sign`]);
+//! failures are retried with the backoff of the job queue, and a subscription is disabled once
+//! `[webhooks] disable_after` attempts in a row have failed.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use salvo::http::StatusError;
+use salvo::oapi::ToSchema;
+use salvo::{Depot, async_trait};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use sqlx::PgConnection;
+use ulid::Ulid;
+
+use crate::jobs::{self, Job};
+use crate::repositories::UserStore;
+use crate::state::{AppState, AppStateDepotExt};
+use crate::{AppError, AppResult, db};
+
+pub const USER_CREATED: &str = "user.created";
+pub const USER_UPDATED: &str = "user.updated";
+pub const USER_DELETED: &str = "user.deleted";
+pub const USER_RESTORED: &str = "user.restored";
+pub const USER_LOGGED_IN: &str = "user.logged_in";
+
+/// Event types a subscription may ask for.
+pub const EVENT_TYPES: &[&str] = &[
+    USER_CREATED,
+    USER_UPDATED,
+    USER_DELETED,
+    USER_RESTORED,
+    USER_LOGGED_IN,
+];
+
+/// Headers of every delivery. The signature is `sha256=` and the hex HMAC-SHA256, keyed with
+/// the subscription secret, of `{timestamp}.{body}`.
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Subscription {
+    pub id: String,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub description: Option<String>,
+    /// Off when turned off by an admin, or after too many failures in a row.
+    pub active: bool,
+    pub consecutive_failures: i32,
+    #[salvo(schema(value_type = Option<String>, format = DateTime))]
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub created_by: Option<String>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, sqlx::Type, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Delivery {
+    pub id: String,
+    pub subscription_id: String,
+    pub event_type: String,
+    /// Body posted to the receiver.
+    pub payload: Value,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    /// Status of the last answer, none when the receiver could not be reached.
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub replay_of: Option<String>,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+    #[salvo(schema(value_type = Option<String>, format = DateTime))]
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    #[salvo(schema(value_type = Option<String>, format = DateTime))]
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+pub async fn create(
+    conn: &mut PgConnection,
+    id: &str,
+    url: &str,
+    event_types: &[String],
+    description: Option<&str>,
+    secret: &str,
+    created_by: Option<&str>,
+) -> AppResult<Subscription> {
+    Ok(sqlx::query_as!(
+        Subscription,
+        r#"
+        INSERT INTO webhook_subscriptions (id, url, event_types, description, secret, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, url, event_types, description, active, consecutive_failures, disabled_at,
+                  created_by, created_at, updated_at
+        "#,
+        id,
+        url,
+        event_types,
+        description,
+        secret,
+        created_by,
+    )
+    .fetch_one(conn)
+    .await?)
+}
+
+pub async fn list(conn: &mut PgConnection) -> AppResult<Vec<Subscription>> {
+    Ok(sqlx::query_as!(
+        Subscription,
+        r#"
+        SELECT id, url, event_types, description, active, consecutive_failures, disabled_at,
+               created_by, created_at, updated_at
+        FROM webhook_subscriptions
+        ORDER BY created_at, id
+        "#
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+pub async fn find(conn: &mut PgConnection, id: &str) -> AppResult<Option<Subscription>> {
+    Ok(sqlx::query_as!(
+        Subscription,
+        r#"
+        SELECT id, url, event_types, description, active, consecutive_failures, disabled_at,
+               created_by, created_at, updated_at
+        FROM webhook_subscriptions WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+/// Fields to change; `None` keeps the current value.
+#[derive(Default, Debug)]
+pub struct SubscriptionChanges {
+    pub url: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    pub description: Option<Option<String>>,
+    pub active: Option<bool>,
+}
+
+/// Applies `changes`. Turning a subscription back on clears its failures.
+pub async fn update(
+    conn: &mut PgConnection,
+    id: &str,
+    changes: SubscriptionChanges,
+) -> AppResult<Option<Subscription>> {
+    Ok(sqlx::query_as!(
+        Subscription,
+        r#"
+        UPDATE webhook_subscriptions
+        SET url = COALESCE($2, url),
+            event_types = COALESCE($3, event_types),
+            description = CASE WHEN $4 THEN $5 ELSE description END,
+            consecutive_failures = CASE WHEN $6 AND NOT active THEN 0 ELSE consecutive_failures END,
+            disabled_at = CASE WHEN $6 THEN NULL
+                               WHEN $6 = false AND active THEN now()
+                               ELSE disabled_at END,
+            active = COALESCE($6, active),
+            updated_at = now()
+        WHERE id = $1
+        RETURNING id, url, event_types, description, active, consecutive_failures, disabled_at,
+                  created_by, created_at, updated_at
+        "#,
+        id,
+        changes.url,
+        changes.event_types.as_deref(),
+        changes.description.is_some(),
+        changes.description.flatten(),
+        changes.active,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+/// Deletes a subscription with its deliveries, returning whether there was one.
+pub async fn delete(conn: &mut PgConnection, id: &str) -> AppResult<bool> {
+    let deleted = sqlx::query!("DELETE FROM webhook_subscriptions WHERE id = $1", id)
+        .execute(conn)
+        .await?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+/// One page of the deliveries of a subscription, newest first, and their number.
+pub async fn deliveries(
+    conn: &mut PgConnection,
+    subscription_id: &str,
+    limit: i64,
+    offset: i64,
+) -> AppResult<(Vec<Delivery>, i64)> {
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM webhook_deliveries WHERE subscription_id = $1"#,
+        subscription_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT id, subscription_id, event_type, payload, status as "status: DeliveryStatus",
+               attempts, response_status, last_error, replay_of, created_at, last_attempt_at,
+               delivered_at
+        FROM webhook_deliveries
+        WHERE subscription_id = $1
+        ORDER BY created_at DESC, id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        subscription_id,
+        limit,
+        offset,
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok((deliveries, total))
+}
+
+/// Stores a pending delivery of `payload` and queues its job.
+async fn queue(
+    conn: &mut PgConnection,
+    subscription_id: &str,
+    event_type: &str,
+    payload: &Value,
+    replay_of: Option<&str>,
+) -> AppResult<Delivery> {
+    let delivery = sqlx::query_as!(
+        Delivery,
+        r#"
+        INSERT INTO webhook_deliveries (id, subscription_id, event_type, payload, replay_of)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, subscription_id, event_type, payload, status as "status: DeliveryStatus",
+                  attempts, response_status, last_error, replay_of, created_at, last_attempt_at,
+                  delivered_at
+        "#,
+        Ulid::new().to_string(),
+        subscription_id,
+        event_type,
+        payload,
+        replay_of,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let job = DeliverWebhook {
+        id: delivery.id.clone(),
+    };
+    jobs::enqueue(conn, &job, Duration::ZERO).await?;
+    Ok(delivery)
+}
+
+/// Sends the payload of a past delivery again, as a new delivery with the same event id.
+/// 404 when there is no such delivery, 409 when its subscription is disabled.
+pub async fn replay(conn: &mut PgConnection, delivery_id: &str) -> AppResult<Delivery> {
+    let Some(original) = sqlx::query!(
+        r#"
+        SELECT d.subscription_id, d.event_type, d.payload, s.active
+        FROM webhook_deliveries d
+        JOIN webhook_subscriptions s ON s.id = d.subscription_id
+        WHERE d.id = $1
+        "#,
+        delivery_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Err(StatusError::not_found().brief("Delivery not found.").into());
+    };
+    if !original.active {
+        return Err(StatusError::conflict()
+            .brief("The subscription is disabled; turn it back on first.")
+            .into());
+    }
+    queue(
+        conn,
+        &original.subscription_id,
+        &original.event_type,
+        &original.payload,
+        Some(delivery_id),
+    )
+    .await
+}
+
+/// Sends `event_type` with `data` to every active subscription to it, once the request
+/// transaction commits. Webhooks live in Postgres, so nothing is sent while users are kept in
+/// memory.
+pub async fn publish<T: Serialize>(depot: &mut Depot, event_type: &str, data: &T) -> AppResult<()> {
+    if let UserStore::Memory { .. } = depot.state().users {
+        return Ok(());
+    }
+    let payload = json!({
+        "id": Ulid::new().to_string(),
+        "type": event_type,
+        "occurred_at": Utc::now(),
+        "data": data,
+    });
+    let conn = db::transaction(depot).await?;
+    let subscriptions = sqlx::query_scalar!(
+        "SELECT id FROM webhook_subscriptions WHERE active AND $1 = ANY(event_types)",
+        event_type,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for subscription_id in subscriptions {
+        queue(conn, &subscription_id, event_type, &payload, None).await?;
+    }
+    Ok(())
+}
+
+/// Value of the signature header for `body` sent at `timestamp` (Unix seconds).
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Why an attempt failed.
+#[derive(Debug)]
+pub struct Failure {
+    /// Status of the answer, when there was one.
+    pub status: Option<u16>,
+    pub error: String,
+}
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(concat!("backend-webhooks/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("webhook client should build")
+});
+
+/// Posts `body` to `url`, signed with `secret`. Only a 2xx answer counts as delivered;
+/// redirects are not followed.
+pub async fn send(
+    url: &str,
+    secret: &str,
+    delivery_id: &str,
+    event_type: &str,
+    body: Vec<u8>,
+    timeout: Duration,
+) -> Result<u16, Failure> {
+    let timestamp = Utc::now().timestamp();
+    let signature = sign(secret, timestamp, &body);
+    let response = CLIENT
+        .post(url)
+        .timeout(timeout)
+        .header("content-type", "application/json")
+        .header(DELIVERY_HEADER, delivery_id)
+        .header(EVENT_HEADER, event_type)
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| Failure {
+            status: None,
+            error: e.to_string(),
+        })?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Failure {
+            status: Some(status.as_u16()),
+            error: format!("receiver answered {status}"),
+        });
+    }
+    Ok(status.as_u16())
+}
+
+/// Posts one delivery. Does nothing once the delivery is no longer pending, and gives up on
+/// it when its subscription has been disabled.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeliverWebhook {
+    pub id: String,
+}
+
+#[async_trait]
+impl Job for DeliverWebhook {
+    const KIND: &'static str = "deliver_webhook";
+    const MAX_ATTEMPTS: i32 = 8;
+
+    async fn run(self, state: &AppState) -> AppResult<()> {
+        let pool = state.db.pool();
+        let Some(target) = sqlx::query!(
+            r#"
+            SELECT d.event_type, d.payload, s.id as subscription_id, s.url, s.secret, s.active
+            FROM webhook_deliveries d
+            JOIN webhook_subscriptions s ON s.id = d.subscription_id
+            WHERE d.id = $1 AND d.status = 'pending'
+            "#,
+            self.id,
+        )
+        .fetch_optional(pool)
+        .await?
+        else {
+            return Ok(());
+        };
+        if !target.active {
+            sqlx::query!(
+                r#"
+                UPDATE webhook_deliveries
+                SET status = 'failed', last_error = 'subscription is disabled'
+                WHERE id = $1
+                "#,
+                self.id,
+            )
+            .execute(pool)
+            .await?;
+            return Ok(());
+        }
+
+        let body =
+            serde_json::to_vec(&target.payload).map_err(|e| AppError::internal(e.to_string()))?;
+        let timeout = Duration::from_millis(state.config.webhooks.timeout);
+        let sent = send(
+            &target.url,
+            &target.secret,
+            &self.id,
+            &target.event_type,
+            body,
+            timeout,
+        )
+        .await;
+        let mut tx = pool.begin().await?;
+        let failure = match sent {
+            Ok(status) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE webhook_deliveries
+                    SET status = 'succeeded', attempts = attempts + 1, response_status = $2,
+                        last_error = NULL, last_attempt_at = now(), delivered_at = now()
+                    WHERE id = $1
+                    "#,
+                    self.id,
+                    status as i32,
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!(
+                    "UPDATE webhook_subscriptions SET consecutive_failures = 0 WHERE id = $1",
+                    target.subscription_id,
+                )
+                .execute(&mut *tx)
+                .await?;
+                None
+            }
+            Err(failure) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE webhook_deliveries
+                    SET attempts = attempts + 1, response_status = $2, last_error = $3,
+                        last_attempt_at = now(),
+                        status = CASE WHEN attempts + 1 >= $4 THEN 'failed' ELSE 'pending' END
+                    WHERE id = $1
+                    "#,
+                    self.id,
+                    failure.status.map(i32::from),
+                    failure.error,
+                    Self::MAX_ATTEMPTS,
+                )
+                .execute(&mut *tx)
+                .await?;
+                let disabled = sqlx::query_scalar!(
+                    r#"
+                    UPDATE webhook_subscriptions
+                    SET consecutive_failures = consecutive_failures + 1,
+                        active = NOT ($2 > 0 AND consecutive_failures + 1 >= $2),
+                        disabled_at = CASE WHEN $2 > 0 AND consecutive_failures + 1 >= $2
+                                           THEN now() END,
+                        updated_at = now()
+                    WHERE id = $1 AND active
+                    RETURNING NOT active as "disabled!"
+                    "#,
+                    target.subscription_id,
+                    state.config.webhooks.disable_after,
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+                if disabled == Some(true) {
+                    tracing::warn!(
+                        subscription = target.subscription_id,
+                        url = target.url,
+                        "webhook subscription disabled after repeated failures"
+                    );
+                }
+                Some(failure)
+            }
+        };
+        tx.commit().await?;
+        match failure {
+            None => Ok(()),
+            Some(failure) => Err(AppError::internal(format!(
+                "webhook delivery failed: {}",
+                failure.error
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use salvo::conn::{Acceptor, TcpListener};
+    use salvo::prelude::*;
+    use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+    use super::{SIGNATURE_HEADER, TIMESTAMP_HEADER, send, sign};
+
+    type Received = (String, String, Vec<u8>);
+
+    #[handler]
+    async fn receive(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+        let received = depot.obtain::<UnboundedSender<Received>>().unwrap().clone();
+        let timestamp = req.header::<String>(TIMESTAMP_HEADER).unwrap();
+        let signature = req.header::<String>(SIGNATURE_HEADER).unwrap();
+        let body = req.payload().await.unwrap().to_vec();
+        received.send((timestamp, signature, body)).unwrap();
+        res.status_code(StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_send_to_local_receiver() {
+        let (received, mut requests) = unbounded_channel::<Received>();
+        let receiver = Router::new()
+            .hoop(affix_state::inject(received))
+            .push(Router::with_path("hook").post(receive));
+        let acceptor = TcpListener::new("127.0.0.1:0").bind().await;
+        let addr = acceptor.holdings()[0]
+            .local_addr
+            .clone()
+            .into_std()
+            .unwrap();
+        tokio::spawn(Server::new(acceptor).serve(receiver));
+
+        let body = br#"{"type":"user.created"}"#.to_vec();
+        let timeout = Duration::from_secs(5);
+        let url = format!("http://{addr}/hook");
+        let status = send(&url, "s3cret", "d1", "user.created", body.clone(), timeout).await;
+        assert_eq!(status.unwrap(), 204);
+
+        let (timestamp, signature, received) = requests.recv().await.unwrap();
+        let timestamp = timestamp.parse().unwrap();
+        assert_eq!(received, body);
+        assert_eq!(signature, sign("s3cret", timestamp, &body));
+        assert_ne!(signature, sign("other", timestamp, &body));
+
+        let url = format!("http://{addr}/missing");
+        let failed = send(&url, "s3cret", "d2", "user.created", body, timeout).await;
+        assert_eq!(failed.unwrap_err().status, Some(404));
+    }
+}
@@ -0,0 +1,185 @@
+//! Workspaces, the tenants whose data row-level security keeps apart (see `db::tenant`).
+//!
+//! Everything here runs on a connection already scoped to the caller, so it only sees the
+//! workspaces the caller belongs to.
+
+use chrono::{DateTime, Utc};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::AppResult;
+
+/// What a member may do in a workspace. Admins manage members and invitations; only owners may
+/// grant or take away ownership, and a workspace always keeps one owner.
+#[derive(
+    Serialize, Deserialize, ToSchema, sqlx::Type, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Role {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn can_manage(self) -> bool {
+        self >= Self::Admin
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Workspace {
+    pub id: String,
+    pub name: String,
+    /// Role of the current user in the workspace.
+    pub role: Role,
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Creates a workspace with `owner_id` as its owner.
+pub async fn create(
+    conn: &mut PgConnection,
+    id: &str,
+    name: &str,
+    owner_id: &str,
+) -> AppResult<Workspace> {
+    let workspace = sqlx::query!(
+        r#"
+        SELECT id as "id!", name as "name!", created_at as "created_at!"
+        FROM create_workspace($1, $2, $3)
+        "#,
+        id,
+        name,
+        owner_id,
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(Workspace {
+        id: workspace.id,
+        name: workspace.name,
+        role: Role::Owner,
+        created_at: workspace.created_at,
+    })
+}
+
+/// Workspaces `user_id` belongs to, oldest membership first.
+pub async fn list_for_user(conn: &mut PgConnection, user_id: &str) -> AppResult<Vec<Workspace>> {
+    Ok(sqlx::query_as!(
+        Workspace,
+        r#"
+        SELECT w.id, w.name, m.role as "role: Role", w.created_at
+        FROM memberships m
+        JOIN workspaces w ON w.id = m.workspace_id
+        WHERE m.user_id = $1
+        ORDER BY m.created_at, w.id
+        "#,
+        user_id,
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Role of `user_id` in `workspace_id`, `None` when they are not a member.
+pub async fn role(
+    conn: &mut PgConnection,
+    workspace_id: &str,
+    user_id: &str,
+) -> AppResult<Option<Role>> {
+    Ok(sqlx::query_scalar!(
+        r#"
+        SELECT role as "role: Role" FROM memberships
+        WHERE workspace_id = $1 AND user_id = $2
+        "#,
+        workspace_id,
+        user_id,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Member {
+    pub user_id: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub role: Role,
+    /// When they joined.
+    #[salvo(schema(value_type = String, format = DateTime))]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Members of `workspace_id`, owners first.
+pub async fn members(conn: &mut PgConnection, workspace_id: &str) -> AppResult<Vec<Member>> {
+    Ok(sqlx::query_as!(
+        Member,
+        r#"
+        SELECT m.user_id, u.username, u.display_name, m.role as "role: Role", m.created_at
+        FROM memberships m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.workspace_id = $1
+        ORDER BY m.role = 'owner' DESC, m.role = 'admin' DESC, u.username
+        "#,
+        workspace_id,
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Number of owners of `workspace_id`, locking their rows so concurrent changes cannot remove
+/// the last one.
+pub async fn lock_owners(conn: &mut PgConnection, workspace_id: &str) -> AppResult<usize> {
+    Ok(sqlx::query_scalar!(
+        r#"
+        SELECT user_id FROM memberships
+        WHERE workspace_id = $1 AND role = 'owner'
+        FOR UPDATE
+        "#,
+        workspace_id,
+    )
+    .fetch_all(conn)
+    .await?
+    .len())
+}
+
+/// Changes the role of a member, returning whether they are one.
+pub async fn set_role(
+    conn: &mut PgConnection,
+    workspace_id: &str,
+    user_id: &str,
+    role: Role,
+) -> AppResult<bool> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE memberships SET role = $3
+        WHERE workspace_id = $1 AND user_id = $2
+        "#,
+        workspace_id,
+        user_id,
+        role as Role,
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+    Ok(updated > 0)
+}
+
+/// Takes a member out of the workspace, returning whether they were one. Their next request in
+/// it is refused by `workspace_scope`.
+pub async fn remove_member(
+    conn: &mut PgConnection,
+    workspace_id: &str,
+    user_id: &str,
+) -> AppResult<bool> {
+    let deleted = sqlx::query!(
+        "DELETE FROM memberships WHERE workspace_id = $1 AND user_id = $2",
+        workspace_id,
+        user_id,
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+    Ok(deleted > 0)
+}
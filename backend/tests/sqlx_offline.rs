@@ -0,0 +1,88 @@
+//! Keeps the committed `.sqlx` query data in sync with the queries in `src`.
+//!
+//! Needs the database of `DATABASE_URL` and compiles the crate once more, so it is ignored by
+//! default:
+//!
+//! ```text
+//! cargo test --test sqlx_offline -- --ignored            # check
+//! SQLX_PREPARE=1 cargo test --test sqlx_offline -- --ignored  # rewrite .sqlx
+//! ```
+//!
+//! `cargo sqlx prepare` produces the same files, for those who have sqlx-cli installed.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn query_files(dir: &Path) -> BTreeMap<String, String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+    entries
+        .map(|entry| entry.expect("readable .sqlx entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (
+                name,
+                fs::read_to_string(&path).expect("readable query file"),
+            )
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "needs a live database and recompiles the crate"]
+fn test_sqlx_offline_data_is_up_to_date() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let committed_dir = manifest_dir.join(".sqlx");
+    let generated_dir = std::env::temp_dir().join(format!("backend-sqlx-{}", std::process::id()));
+    fs::create_dir_all(&generated_dir).unwrap();
+
+    // A target dir of its own, with the crate itself cleaned, forces every query macro to expand
+    // again, online, and write what it saw into SQLX_OFFLINE_DIR. Dependencies stay built.
+    let target_dir = manifest_dir.join("target").join("sqlx-offline");
+    let cargo = |args: &[&str]| {
+        Command::new(env!("CARGO"))
+            .args(args)
+            .current_dir(&manifest_dir)
+            .env("SQLX_OFFLINE", "false")
+            .env("SQLX_OFFLINE_DIR", &generated_dir)
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .status()
+            .expect("cargo should run")
+    };
+    assert!(cargo(&["clean", "--quiet", "--package", "backend"]).success());
+    assert!(
+        cargo(&["check", "--all-targets", "--quiet"]).success(),
+        "cargo check against the live database failed"
+    );
+
+    let generated = query_files(&generated_dir);
+    let committed = query_files(&committed_dir);
+    if std::env::var_os("SQLX_PREPARE").is_some() {
+        if committed_dir.exists() {
+            fs::remove_dir_all(&committed_dir).unwrap();
+        }
+        fs::create_dir_all(&committed_dir).unwrap();
+        for (name, content) in &generated {
+            fs::write(committed_dir.join(name), content).unwrap();
+        }
+    } else {
+        let stale: Vec<_> = committed
+            .keys()
+            .filter(|name| generated.get(*name) != committed.get(*name))
+            .collect();
+        let missing: Vec<_> = generated
+            .keys()
+            .filter(|name| !committed.contains_key(*name))
+            .collect();
+        assert!(
+            stale.is_empty() && missing.is_empty(),
+            ".sqlx is out of date (stale: {stale:?}, missing: {missing:?}); \
+             rerun with SQLX_PREPARE=1 to update it"
+        );
+    }
+    let _ = fs::remove_dir_all(&generated_dir);
+}